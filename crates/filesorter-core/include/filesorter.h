@@ -0,0 +1,56 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+/* The C API of filesorter-core, built into its cdylib with the `ffi` feature.
+ *
+ * Functions returning a pointer return NULL on failure, filesorter_last_error() then tells why.
+ * Everything returned must be freed by the matching _free function. */
+
+#ifndef FILESORTER_H
+#define FILESORTER_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Loaded settings, opaque */
+typedef struct FilesorterSettings FilesorterSettings;
+
+/* A file the sort moved, or copied in copy mode */
+typedef struct FilesorterMove {
+    char *source;
+    char *destination;
+} FilesorterMove;
+
+/* What filesorter_sort_path() did */
+typedef struct FilesorterResults {
+    FilesorterMove *moves;
+    size_t moves_len;
+    /* Why files failed to be sorted, one message each */
+    char **failures;
+    size_t failures_len;
+} FilesorterResults;
+
+/* Loads a config file, NULL if it can't be loaded */
+FilesorterSettings *filesorter_settings_load(const char *path);
+
+void filesorter_settings_free(FilesorterSettings *settings);
+
+/* Sorts the files of the dir `path` by the rules of `settings`, instead of its sources.
+ * NULL if the sort couldn't run, files failing on their own are in the results. */
+FilesorterResults *filesorter_sort_path(const FilesorterSettings *settings, const char *path);
+
+void filesorter_results_free(FilesorterResults *results);
+
+/* Why the last call on this thread returned NULL, NULL if it didn't.
+ * The message stays valid until the next call. */
+const char *filesorter_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* FILESORTER_H */
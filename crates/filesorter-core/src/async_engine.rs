@@ -0,0 +1,207 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! A [`SortEngine`] for async services, behind the `async` feature.
+//!
+//! Scanning and moving is file system work, so it runs on tokio's blocking pool while the caller's
+//! tasks carry on. Files are matched by the same rules and moved the same way as with
+//! [`SortEngine::run`].
+
+use crate::error::{Error, Result};
+use crate::events::EventSink;
+use crate::executor::{self, Report};
+use crate::journal;
+use crate::metrics;
+use crate::planner::{self, PlannedMove};
+use crate::sorter::{self, SortEngine};
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::{self, JoinHandle};
+
+/// Runs a [`SortEngine`] on the tokio runtime it's called from, moving at most
+/// [`concurrency`](AsyncSortEngine::concurrency) files at a time.
+///
+/// ```no_run
+/// use filesorter_core::async_engine::AsyncSortEngine;
+/// use filesorter_core::events::Event;
+/// use filesorter_core::settings::Settings;
+/// use filesorter_core::sorter::SortEngine;
+///
+/// # async fn sort() -> filesorter_core::Result<()> {
+/// let settings = Settings::load("filesorter.yaml".as_ref())?;
+/// let engine = AsyncSortEngine::new(SortEngine::new(settings)).concurrency(8);
+/// let report = engine
+///     .run(|event: &Event| println!("{:?}", event))
+///     .await?;
+/// println!("{}", report.summary());
+/// # Ok(())
+/// # }
+/// ```
+pub struct AsyncSortEngine {
+    engine: Arc<SortEngine>,
+    concurrency: usize,
+}
+
+impl AsyncSortEngine {
+    /// Moves as many files at a time as the engine has jobs, or CPUs with 0 jobs.
+    pub fn new(engine: SortEngine) -> Self {
+        let concurrency = match engine.options.jobs {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            jobs => jobs,
+        };
+
+        AsyncSortEngine {
+            engine: Arc::new(engine),
+            concurrency,
+        }
+    }
+
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn engine(&self) -> &SortEngine {
+        &self.engine
+    }
+
+    /// Plans and carries out the moves of every source like [`SortEngine::run`]. Events of a source
+    /// reach `sink` once it has been scanned, the progress bar option is ignored.
+    pub async fn run(&self, sink: impl EventSink + Send + Sync) -> Result<Report> {
+        let settings = &self.engine.settings;
+        sorter::check_sources(settings)?;
+
+        let mut moves: Vec<PlannedMove> = Vec::new();
+        let mut failures: Vec<Error> = Vec::new();
+
+        for index in 0..settings.sources.len() {
+            sink.on_scan_start(&settings.sources[index]);
+            let engine = self.engine.clone();
+            let plan = unblock(task::spawn_blocking(move || {
+                let source = &engine.settings.sources[index];
+                sorter::plan_source(
+                    &engine.settings,
+                    &engine.options,
+                    &engine.plugins,
+                    source,
+                    &(),
+                )
+            }))
+            .await?;
+
+            for planned in &plan.moves {
+                sink.on_match(planned);
+            }
+            for failure in &plan.failures {
+                sink.on_error(failure);
+            }
+            moves.extend(plan.moves);
+            failures.extend(plan.failures);
+        }
+
+        let moves = Arc::new(moves);
+        let journaled = moves.clone();
+        let (mode, conflict_policy) = (settings.mode, settings.conflict_policy);
+        unblock(task::spawn_blocking(move || {
+            journal::Journal::new(mode, conflict_policy, &journaled).save()
+        }))
+        .await?;
+        let result = self.execute(moves.clone(), &sink).await;
+        unblock(task::spawn_blocking(journal::Journal::remove)).await?;
+        let engine = self.engine.clone();
+        let result = unblock(task::spawn_blocking(move || {
+            sorter::prune(&engine.settings, &engine.options, &moves, result)
+        }))
+        .await;
+
+        sorter::finish(result, failures, &sink)
+    }
+
+    async fn execute(
+        &self,
+        moves: Arc<Vec<PlannedMove>>,
+        sink: &(dyn EventSink + Sync),
+    ) -> Result<Report> {
+        let fail_fast = self.engine.options.fail_fast;
+        let (movable, failures) = {
+            let moves = moves.clone();
+            unblock(task::spawn_blocking(move || {
+                executor::create_destination_dirs(&moves, fail_fast)
+            }))
+            .await?
+        };
+        for failure in &failures {
+            sink.on_error(failure);
+        }
+
+        metrics::set_queue_depth(movable.len());
+        let movable = Arc::new(movable);
+        let groups = {
+            let movable: Vec<&PlannedMove> = movable.iter().map(|&index| &moves[index]).collect();
+            planner::destination_groups(&movable)
+        };
+        let semaphore = Arc::new(Semaphore::new(self.concurrency));
+        let failed = Arc::new(AtomicBool::new(false));
+        let mut handles = Vec::with_capacity(groups.len());
+        for group in groups {
+            let permit = semaphore
+                .clone()
+                .acquire_owned()
+                .await
+                .expect("Expected the semaphore to stay open");
+            let engine = self.engine.clone();
+            let moves = moves.clone();
+            let movable = movable.clone();
+            let failed = failed.clone();
+            // Moves to the same destination run one after another
+            handles.push(task::spawn_blocking(move || {
+                let _permit = permit;
+                group
+                    .into_iter()
+                    .map(|position| {
+                        if (fail_fast && failed.load(Ordering::Relaxed)) || executor::interrupted()
+                        {
+                            return (position, None);
+                        }
+                        let planned = &moves[movable[position]];
+                        let (result, size) = executor::transfer_one(&engine.settings, planned);
+                        if result.as_ref().is_err_and(|e| !e.is_locked()) {
+                            failed.store(true, Ordering::Relaxed);
+                        }
+                        (position, Some((result, size)))
+                    })
+                    .collect::<Vec<_>>()
+            }));
+        }
+
+        // Put back in plan order, so events come in the same order as with the sync engine
+        let mut grouped = Vec::with_capacity(handles.len());
+        for handle in handles {
+            grouped.push(unblock(handle).await);
+        }
+        let mut results = executor::in_plan_order(movable.len(), grouped);
+
+        let movable: Vec<&PlannedMove> = movable.iter().map(|&index| &moves[index]).collect();
+        executor::retry_locked(&self.engine.settings, &movable, &mut results);
+        executor::report(
+            &self.engine.settings,
+            &movable,
+            results,
+            failures,
+            fail_fast,
+            sink,
+        )
+    }
+}
+
+/// The result of a blocking task, a panic in it is passed on to the caller.
+async fn unblock<T>(handle: JoinHandle<T>) -> T {
+    match handle.await {
+        Ok(value) => value,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => panic!("Blocking task didn't finish: {}", e),
+    }
+}
@@ -0,0 +1,297 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use crate::error::{Error, Result};
+use crate::manifest::ManifestFormat;
+use crate::rules::{MatchMode, MatchPolicy, NameExclusions, NameGlobs, NameRegex, Script};
+use crate::settings::{Settings, SimilarImages, SortPattern, Source};
+use crate::size::ByteSize;
+use crate::transfer::{
+    CleanupAction, ConflictPolicy, DedupPolicy, Quarantine, TransferMode, WindowsNames,
+};
+
+use regex::Regex;
+use std::path::PathBuf;
+use std::time::Duration;
+
+impl SortPattern {
+    /// Starts a pattern, like `SortPattern::builder().extension("webp").mime("image/webp").dest("images")`.
+    pub fn builder() -> SortPatternBuilder {
+        SortPatternBuilder::default()
+    }
+}
+
+/// Builds a [`SortPattern`], the regex and globs are compiled by [`build`](SortPatternBuilder::build).
+#[derive(Clone, Debug, Default)]
+pub struct SortPatternBuilder {
+    pattern: SortPattern,
+    name_regex: Option<String>,
+    globs: Vec<String>,
+    exclude: Vec<String>,
+    script: Option<String>,
+    finder_tags: Vec<String>,
+}
+
+impl SortPatternBuilder {
+    pub fn extension(mut self, extension: &str) -> Self {
+        self.pattern.extensions.push(extension.to_string());
+        self
+    }
+
+    pub fn extensions(mut self, extensions: &[&str]) -> Self {
+        self.pattern
+            .extensions
+            .extend(extensions.iter().map(|extension| extension.to_string()));
+        self
+    }
+
+    pub fn mime(mut self, mime_type: &str) -> Self {
+        self.pattern.mime_types.push(mime_type.to_string());
+        self
+    }
+
+    pub fn name_regex(mut self, name_regex: &str) -> Self {
+        self.name_regex = Some(name_regex.to_string());
+        self
+    }
+
+    pub fn glob(mut self, glob: &str) -> Self {
+        self.globs.push(glob.to_string());
+        self
+    }
+
+    pub fn exclude(mut self, exclude: &str) -> Self {
+        self.exclude.push(exclude.to_string());
+        self
+    }
+
+    pub fn script(mut self, script: &str) -> Self {
+        self.script = Some(script.to_string());
+        self
+    }
+
+    pub fn min_size(mut self, min_size: u64) -> Self {
+        self.pattern.min_size = Some(ByteSize(min_size));
+        self
+    }
+
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.pattern.max_size = Some(ByteSize(max_size));
+        self
+    }
+
+    pub fn older_than(mut self, older_than: Duration) -> Self {
+        self.pattern.older_than = Some(older_than);
+        self
+    }
+
+    pub fn newer_than(mut self, newer_than: Duration) -> Self {
+        self.pattern.newer_than = Some(newer_than);
+        self
+    }
+
+    pub fn match_mode(mut self, match_mode: MatchMode) -> Self {
+        self.pattern.match_mode = Some(match_mode);
+        self
+    }
+
+    pub fn priority(mut self, priority: i32) -> Self {
+        self.pattern.priority = priority;
+        self
+    }
+
+    pub fn date_pattern(mut self, date_pattern: &str) -> Self {
+        self.pattern.use_date_pattern = Some(true);
+        self.pattern.date_pattern = Some(date_pattern.to_string());
+        self
+    }
+
+    pub fn no_date_pattern(mut self) -> Self {
+        self.pattern.use_date_pattern = Some(false);
+        self
+    }
+
+    pub fn dest(mut self, destination: &str) -> Self {
+        self.pattern.destination = destination.to_string();
+        self
+    }
+
+    pub fn rename(mut self, rename: &str) -> Self {
+        self.pattern.rename = Some(rename.to_string());
+        self
+    }
+
+    /// Puts a Finder tag like `red` or `Invoices:green` on the sorted files, on macOS.
+    pub fn finder_tag(mut self, finder_tag: &str) -> Self {
+        self.finder_tags.push(finder_tag.to_string());
+        self
+    }
+
+    /// Deletes or trashes the matched files instead, no destination needed then.
+    pub fn clean_up(mut self, action: CleanupAction) -> Self {
+        self.pattern.action = Some(action);
+        self
+    }
+
+    /// Fails on an invalid regex, glob, script or Finder tag, or without a destination or cleanup action.
+    pub fn build(self) -> Result<SortPattern> {
+        let mut pattern = self.pattern;
+
+        if pattern.destination.is_empty() && pattern.action.is_none() {
+            return Err(Error::Settings(String::from(
+                "Sort pattern has no destination",
+            )));
+        }
+        if let Some(name_regex) = self.name_regex {
+            let regex = Regex::new(&name_regex).map_err(|e| {
+                Error::Settings(format!("Invalid name_regex '{}': {}", name_regex, e))
+            })?;
+            pattern.name_regex = Some(NameRegex(regex));
+        }
+        pattern.globs = NameGlobs::new(self.globs)
+            .map_err(|e| Error::Settings(format!("Invalid glob: {}", e)))?;
+        pattern.exclude = NameExclusions::new(self.exclude)
+            .map_err(|e| Error::Settings(format!("Invalid exclude: {}", e)))?;
+        if let Some(script) = self.script {
+            pattern.script = Some(
+                Script::new(script)
+                    .map_err(|e| Error::Settings(format!("Invalid script: {}", e)))?,
+            );
+        }
+        pattern.finder_tags = self
+            .finder_tags
+            .iter()
+            .map(|finder_tag| finder_tag.parse())
+            .collect::<std::result::Result<_, String>>()
+            .map_err(|e| Error::Settings(format!("Invalid Finder tag: {}", e)))?;
+
+        Ok(pattern)
+    }
+}
+
+impl Settings {
+    /// Starts settings without any sort patterns, unlike [`Settings::default`].
+    pub fn builder() -> SettingsBuilder {
+        SettingsBuilder {
+            settings: Settings {
+                sort_patterns: vec![],
+                ..Settings::default()
+            },
+        }
+    }
+}
+
+/// Builds [`Settings`], everything not set keeps its default.
+#[derive(Clone, Debug)]
+pub struct SettingsBuilder {
+    settings: Settings,
+}
+
+impl SettingsBuilder {
+    pub fn source(mut self, source: impl Into<PathBuf>) -> Self {
+        self.settings.sources.push(Source::from(source.into()));
+        self
+    }
+
+    /// A source with its own destination root or extra rules.
+    pub fn source_entry(mut self, source: Source) -> Self {
+        self.settings.sources.push(source);
+        self
+    }
+
+    pub fn destination(mut self, destination: impl Into<PathBuf>) -> Self {
+        self.settings.destination = destination.into();
+        self
+    }
+
+    pub fn pattern(mut self, pattern: SortPattern) -> Self {
+        self.settings.sort_patterns.push(pattern);
+        self
+    }
+
+    /// Adds the patterns of [`Settings::default`].
+    pub fn default_patterns(mut self) -> Self {
+        self.settings
+            .sort_patterns
+            .extend(Settings::default().sort_patterns);
+        self
+    }
+
+    pub fn date_pattern(mut self, date_pattern: &str) -> Self {
+        self.settings.use_date_pattern = true;
+        self.settings.date_pattern = date_pattern.to_string();
+        self
+    }
+
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.settings.recursive = recursive;
+        self
+    }
+
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.settings.max_depth = Some(max_depth);
+        self
+    }
+
+    pub fn mode(mut self, mode: TransferMode) -> Self {
+        self.settings.mode = mode;
+        self
+    }
+
+    pub fn match_policy(mut self, match_policy: MatchPolicy) -> Self {
+        self.settings.match_policy = match_policy;
+        self
+    }
+
+    pub fn quarantine(mut self, quarantine: Quarantine) -> Self {
+        self.settings.quarantine = quarantine;
+        self
+    }
+
+    pub fn conflict_policy(mut self, conflict_policy: ConflictPolicy) -> Self {
+        self.settings.conflict_policy = conflict_policy;
+        self
+    }
+
+    pub fn windows_names(mut self, windows_names: WindowsNames) -> Self {
+        self.settings.windows_names = windows_names;
+        self
+    }
+
+    pub fn dedup(mut self, dedup: DedupPolicy) -> Self {
+        self.settings.dedup = dedup;
+        self
+    }
+
+    /// Bytes hashed at each end of large files before they're hashed whole, 0 to always hash whole files.
+    pub fn partial_hash(mut self, partial_hash: u64) -> Self {
+        self.settings.partial_hash = ByteSize(partial_hash);
+        self
+    }
+
+    pub fn similar_images(mut self, similar_images: SimilarImages) -> Self {
+        self.settings.similar_images = Some(similar_images);
+        self
+    }
+
+    pub fn manifest(mut self, manifest: ManifestFormat) -> Self {
+        self.settings.manifest = Some(manifest);
+        self
+    }
+
+    pub fn trash(mut self, trash: bool) -> Self {
+        self.settings.trash = trash;
+        self
+    }
+
+    /// Whether completed moves are recorded for `filesorter history`, they are by default.
+    pub fn history(mut self, history: bool) -> Self {
+        self.settings.history = history;
+        self
+    }
+
+    pub fn build(self) -> Settings {
+        self.settings
+    }
+}
@@ -0,0 +1,184 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use crate::error::{Error, Result};
+use crate::settings::Settings;
+
+use chrono::prelude::*;
+use chrono::DateTime;
+use chrono_tz::Tz;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Which file timestamp dates files without a better (EXIF or file name) date.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DateSource {
+    #[default]
+    Modified,
+    /// Falls back to the modification time where the platform doesn't record it
+    Created,
+    Accessed,
+}
+
+/// Time zone dates are formatted in: `utc`, `local` or a named one like `Australia/Sydney`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DateTimezone {
+    #[default]
+    Utc,
+    Local,
+    Named(Tz),
+}
+
+impl DateTimezone {
+    /// Formats `date` with a strftime `pattern` in this time zone.
+    pub fn format(&self, date: &DateTime<Utc>, pattern: &str) -> String {
+        match self {
+            DateTimezone::Utc => date.format(pattern).to_string(),
+            DateTimezone::Local => date.with_timezone(&Local).format(pattern).to_string(),
+            DateTimezone::Named(tz) => date.with_timezone(tz).format(pattern).to_string(),
+        }
+    }
+
+    /// Interprets a date without a zone (EXIF, file names) as local to this time zone.
+    fn localize(&self, date: &NaiveDateTime) -> Option<DateTime<Utc>> {
+        match self {
+            DateTimezone::Utc => Some(Utc.from_utc_datetime(date)),
+            DateTimezone::Local => Local
+                .from_local_datetime(date)
+                .earliest()
+                .map(|date| date.with_timezone(&Utc)),
+            DateTimezone::Named(tz) => tz
+                .from_local_datetime(date)
+                .earliest()
+                .map(|date| date.with_timezone(&Utc)),
+        }
+    }
+}
+
+impl FromStr for DateTimezone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "utc" => Ok(DateTimezone::Utc),
+            "local" => Ok(DateTimezone::Local),
+            _ => s
+                .parse::<Tz>()
+                .map(DateTimezone::Named)
+                .map_err(|_| format!("Unknown time zone '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for DateTimezone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DateTimezone::Utc => write!(f, "utc"),
+            DateTimezone::Local => write!(f, "local"),
+            DateTimezone::Named(tz) => write!(f, "{}", tz.name()),
+        }
+    }
+}
+
+impl Serialize for DateTimezone {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for DateTimezone {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(de::Error::custom)
+    }
+}
+
+/// Shorthand `date_pattern` layouts producing nested folders.
+pub const DATE_LAYOUTS: &[(&str, &str)] = &[
+    ("yearly", "%Y"),
+    ("monthly", "%Y/%m"),
+    ("daily", "%Y/%m/%d"),
+    ("iso-week", "%G/W%V"),
+];
+
+/// Resolves a `date_pattern` layout name to its strftime pattern, other patterns are returned as is.
+pub fn resolve_date_pattern(date_pattern: &str) -> &str {
+    DATE_LAYOUTS
+        .iter()
+        .find(|(name, _)| *name == date_pattern)
+        .map(|(_, pattern)| *pattern)
+        .unwrap_or(date_pattern)
+}
+
+/// The date used for date folders and date placeholders.
+pub fn get_file_date(settings: &Settings, file: &Path) -> Result<DateTime<Utc>> {
+    if settings.use_exif_date {
+        if let Some(date) = get_exif_date(settings, file) {
+            return Ok(date);
+        }
+    }
+
+    if let Some(date) = get_filename_date(settings, file) {
+        return Ok(date);
+    }
+
+    get_timestamp_date(settings.date_source, file)
+}
+
+fn get_timestamp_date(date_source: DateSource, file: &Path) -> Result<DateTime<Utc>> {
+    let metadata = std::fs::metadata(file).map_err(|e| Error::io(file, e))?;
+    let timestamp = match date_source {
+        DateSource::Modified => None,
+        DateSource::Created => metadata.created().ok(),
+        DateSource::Accessed => metadata.accessed().ok(),
+    };
+
+    let timestamp = match timestamp {
+        Some(timestamp) => timestamp,
+        None => metadata.modified().map_err(|e| Error::io(file, e))?,
+    };
+    Ok(DateTime::<Utc>::from(timestamp))
+}
+
+/// Tries `filename_date_regexes` in order. Missing `month` / `day` groups default to the first one.
+fn get_filename_date(settings: &Settings, file: &Path) -> Option<DateTime<Utc>> {
+    let name = file.file_name()?.to_string_lossy();
+
+    settings.filename_date_regexes.iter().find_map(|regex| {
+        let captures = regex.0.captures(&name)?;
+        let group = |name: &str, default: u32| match captures.name(name) {
+            Some(value) => value.as_str().parse::<u32>().ok(),
+            None => Some(default),
+        };
+
+        let date = NaiveDate::from_ymd_opt(
+            group("year", 0)? as i32,
+            group("month", 1)?,
+            group("day", 1)?,
+        )?
+        .and_hms_opt(group("hour", 0)?, group("minute", 0)?, group("second", 0)?)?;
+        settings.date_timezone.localize(&date)
+    })
+}
+
+/// Reads the EXIF `DateTimeOriginal` (when the photo was taken), `None` for files without one.
+fn get_exif_date(settings: &Settings, file: &Path) -> Option<DateTime<Utc>> {
+    let mut reader = BufReader::new(File::open(file).ok()?);
+    let exif = exif::Reader::new().read_from_container(&mut reader).ok()?;
+    let field = exif.get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY)?;
+
+    let date = match &field.value {
+        exif::Value::Ascii(values) => exif::DateTime::from_ascii(values.first()?).ok()?,
+        _ => return None,
+    };
+
+    let date = NaiveDate::from_ymd_opt(date.year as i32, date.month as u32, date.day as u32)?
+        .and_hms_opt(date.hour as u32, date.minute as u32, date.second as u32)?;
+    settings.date_timezone.localize(&date)
+}
@@ -0,0 +1,288 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Checks of everything a run depends on, for `filesorter doctor`.
+
+use crate::error::Error;
+use crate::journal::Journal;
+use crate::lock::{self, PidFile, RunLock};
+use crate::settings::Settings;
+use crate::size::ByteSize;
+use crate::sorter::{self, SortOptions};
+use crate::transfer::{TransferMode, TEMP_PREFIX};
+use crate::validate;
+
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+/// A destination with less free space than this share of the disk gets a warning.
+const LOW_SPACE_PERCENT: u64 = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Ok,
+    Warning,
+    /// A run fails or does something it shouldn't
+    Error,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Check {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Check {
+    fn new(severity: Severity, message: String) -> Self {
+        Check { severity, message }
+    }
+}
+
+/// What filesorter runs on and where it keeps its files, to paste into a bug report.
+pub fn environment(settings_path: &Path) -> Vec<(&'static str, String)> {
+    let settings_version = fs::read_to_string(settings_path)
+        .ok()
+        .and_then(|text| serde_yaml::from_str::<serde_yaml::Value>(&text).ok())
+        .and_then(|value| value.get("version").and_then(|version| version.as_u64()))
+        .map_or_else(|| String::from("unknown"), |version| version.to_string());
+
+    vec![
+        ("version", env!("CARGO_PKG_VERSION").to_string()),
+        (
+            "os",
+            format!("{} {}", std::env::consts::OS, std::env::consts::ARCH),
+        ),
+        (
+            "settings",
+            format!("{} (version {})", settings_path.display(), settings_version),
+        ),
+        (
+            "config dir",
+            Settings::get_config_dir().display().to_string(),
+        ),
+        ("data dir", Settings::get_data_dir().display().to_string()),
+        (
+            "runtime dir",
+            Settings::get_runtime_dir().display().to_string(),
+        ),
+    ]
+}
+
+/// Checks the settings file, the sources and destinations and the state left by other runs.
+pub fn check(settings_path: &Path) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let problems = validate::validate_file(settings_path);
+    if problems.is_empty() {
+        checks.push(Check::new(
+            Severity::Ok,
+            format!("{} is valid", settings_path.display()),
+        ));
+    }
+    for problem in problems {
+        checks.push(Check::new(
+            Severity::Warning,
+            format!("{}: {}", settings_path.display(), problem),
+        ));
+    }
+
+    // With the environment overrides, like a run would see them
+    let settings = match sorter::load_settings(settings_path, &SortOptions::default()) {
+        Ok(settings) => settings,
+        Err(e) => {
+            checks.push(Check::new(
+                Severity::Error,
+                format!("Failed to load the settings: {}", e),
+            ));
+            return checks;
+        }
+    };
+
+    if settings.sources.is_empty() {
+        checks.push(Check::new(
+            Severity::Error,
+            String::from("No sources, run `filesorter init` to add one"),
+        ));
+    }
+    for source in &settings.sources {
+        checks.push(check_dir("Source", &source.path));
+        let destination = settings.destination_for(source);
+        if let Some(check) = check_devices(&settings, &source.path, destination) {
+            checks.push(check);
+        }
+    }
+    for destination in settings.destination_roots() {
+        checks.push(check_dir("Destination", &destination));
+        if destination.is_dir() {
+            checks.push(check_space(&settings, &destination));
+        }
+    }
+
+    checks.extend(check_runs());
+
+    checks
+}
+
+/// Whether the dir exists and files can be listed and written in it.
+fn check_dir(kind: &str, dir: &Path) -> Check {
+    if !dir.is_dir() {
+        return Check::new(
+            Severity::Error,
+            format!("{} dir {} doesn't exist", kind, dir.display()),
+        );
+    }
+    if let Err(e) = fs::read_dir(dir) {
+        return Check::new(
+            Severity::Error,
+            format!("{} dir {} can't be read: {}", kind, dir.display(), e),
+        );
+    }
+    let probe = dir.join(format!("{}doctor", TEMP_PREFIX));
+    if let Err(e) = fs::write(&probe, b"").and_then(|()| fs::remove_file(&probe)) {
+        return Check::new(
+            Severity::Error,
+            format!("{} dir {} can't be written to: {}", kind, dir.display(), e),
+        );
+    }
+
+    Check::new(
+        Severity::Ok,
+        format!("{} dir {} is readable and writable", kind, dir.display()),
+    )
+}
+
+/// Moves across filesystems are copies, which take longer and need room at the destination.
+fn check_devices(settings: &Settings, source: &Path, destination: &Path) -> Option<Check> {
+    let (source_device, destination_device) = (device(source)?, device(destination)?);
+    if source_device == destination_device || settings.mode == TransferMode::Copy {
+        return None;
+    }
+
+    Some(Check::new(
+        Severity::Warning,
+        format!(
+            "{} and {} are on different filesystems, files are copied and then deleted",
+            source.display(),
+            destination.display()
+        ),
+    ))
+}
+
+#[cfg(unix)]
+fn device(path: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    fs::metadata(path).ok().map(|metadata| metadata.dev())
+}
+
+// Telling volumes apart takes more bindings, the check is left out
+#[cfg(not(unix))]
+fn device(_path: &Path) -> Option<u64> {
+    None
+}
+
+fn check_space(settings: &Settings, destination: &Path) -> Check {
+    let space = fs2::available_space(destination)
+        .and_then(|available| Ok((available, fs2::total_space(destination)?)));
+    let (available, total) = match space {
+        Ok(space) => space,
+        Err(e) => {
+            return Check::new(
+                Severity::Warning,
+                format!(
+                    "Failed to get the free space of {}: {}",
+                    destination.display(),
+                    e
+                ),
+            )
+        }
+    };
+
+    let free = format!(
+        "{} has {} free of {}",
+        destination.display(),
+        ByteSize(available),
+        ByteSize(total)
+    );
+    let reserve = settings.min_free_space.bytes(total);
+    if available <= reserve {
+        Check::new(
+            Severity::Error,
+            format!(
+                "{}, less than the {} min_free_space keeps, nothing can be copied",
+                free, settings.min_free_space
+            ),
+        )
+    } else if available.saturating_mul(100) < total.saturating_mul(LOW_SPACE_PERCENT) {
+        Check::new(Severity::Warning, format!("{}, it's running low", free))
+    } else {
+        Check::new(Severity::Ok, free)
+    }
+}
+
+/// Runs in progress, a running or stale `watch` and an interrupted run.
+fn check_runs() -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    match RunLock::acquire(false) {
+        Ok(_lock) => checks.push(Check::new(
+            Severity::Ok,
+            String::from("No other run in progress"),
+        )),
+        Err(Error::Locked { pid }) => checks.push(Check::new(
+            Severity::Warning,
+            match pid {
+                Some(pid) => format!("A run is in progress (pid {}), others wait for it", pid),
+                None => String::from("A run is in progress, others wait for it"),
+            },
+        )),
+        Err(e) => checks.push(Check::new(
+            Severity::Error,
+            format!("Failed to take the run lock: {}", e),
+        )),
+    }
+
+    let pid_path = PidFile::get_pid_path();
+    let pid = fs::read_to_string(&pid_path)
+        .ok()
+        .and_then(|pid| pid.trim().parse::<u32>().ok());
+    match pid {
+        Some(pid) if lock::is_running(pid) => checks.push(Check::new(
+            Severity::Ok,
+            format!("filesorter watch is running (pid {})", pid),
+        )),
+        Some(pid) => checks.push(Check::new(
+            Severity::Warning,
+            format!(
+                "{} names pid {}, which isn't running, the next watch replaces it",
+                pid_path.display(),
+                pid
+            ),
+        )),
+        None => {}
+    }
+
+    match Journal::load() {
+        Ok(Some(journal)) => checks.push(Check::new(
+            Severity::Error,
+            format!(
+                "A run was interrupted with {} move(s) planned, run `filesorter sort --resume`",
+                journal.moves.len()
+            ),
+        )),
+        Ok(None) => {}
+        Err(e) => checks.push(Check::new(
+            Severity::Error,
+            format!(
+                "Failed to read the journal {}: {}",
+                Journal::get_journal_path().display(),
+                e
+            ),
+        )),
+    }
+
+    checks
+}
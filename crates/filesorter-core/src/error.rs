@@ -0,0 +1,120 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// The run completed and nothing failed.
+pub const EXIT_SUCCESS: i32 = 0;
+/// The run completed, but some files couldn't be sorted.
+pub const EXIT_PARTIAL: i32 = 1;
+/// The run couldn't be started or was aborted.
+pub const EXIT_FATAL: i32 = 2;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    /// A filesystem operation on `path` failed
+    #[error("{}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Failed to parse settings: {0}")]
+    Parse(#[from] serde_yaml::Error),
+    /// The settings can't be used as they are
+    #[error("{0}")]
+    Settings(String),
+    #[error("Failed to start worker threads: {0}")]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
+    #[error(
+        "Another filesorter run{} is in progress, use --wait to wait for it",
+        pid.map(|pid| format!(" (pid {})", pid)).unwrap_or_default()
+    )]
+    Locked { pid: Option<u32> },
+    /// A `pre_hook` or `post_hook` failed for the file at `path`
+    #[error("{hook} failed for {}: {reason}", path.display())]
+    Hook {
+        hook: &'static str,
+        path: PathBuf,
+        reason: String,
+    },
+    /// The file would be named something Windows doesn't allow, with `windows_names: fail`
+    #[error("{}: '{name}' isn't allowed as a name on Windows", path.display())]
+    WindowsName { path: PathBuf, name: String },
+    /// A `..` or an absolute path in the destination, from a rule, a capture or a plugin
+    #[error("{}: {} is outside the destination", path.display(), destination.display())]
+    OutsideDestination { path: PathBuf, destination: PathBuf },
+    #[error("Failed to watch sources: {0}")]
+    Watch(#[from] notify::Error),
+    #[error("History database: {0}")]
+    History(#[from] rusqlite::Error),
+    /// A file failed with `--fail-fast`
+    #[error("Aborted after an error. {0}")]
+    Aborted(Box<Error>),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    pub fn io(path: &Path, source: io::Error) -> Self {
+        Error::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    /// The file the error is about, if any.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Error::Io { path, .. }
+            | Error::Hook { path, .. }
+            | Error::WindowsName { path, .. }
+            | Error::OutsideDestination { path, .. } => Some(path),
+            Error::Aborted(error) => error.path(),
+            _ => None,
+        }
+    }
+
+    /// Whether the file is open in another program in a way that keeps it from being moved:
+    /// a sharing or lock violation on Windows, `EBUSY` elsewhere.
+    pub fn is_locked(&self) -> bool {
+        match self {
+            Error::Io { source, .. } => is_locked(source),
+            Error::Aborted(error) => error.is_locked(),
+            _ => false,
+        }
+    }
+
+    /// The error without the file it's about, so similar failures can be grouped.
+    pub fn reason(&self) -> String {
+        match self {
+            Error::Io { source, .. } => source.to_string(),
+            Error::Hook { hook, reason, .. } => format!("{} failed: {}", hook, reason),
+            Error::WindowsName { .. } => String::from("the name isn't allowed on Windows"),
+            Error::OutsideDestination { .. } => {
+                String::from("the destination is outside the destination dir")
+            }
+            Error::Aborted(error) => error.reason(),
+            error => error.to_string(),
+        }
+    }
+}
+
+#[cfg(windows)]
+fn is_locked(error: &io::Error) -> bool {
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    const ERROR_LOCK_VIOLATION: i32 = 33;
+
+    matches!(
+        error.raw_os_error(),
+        Some(ERROR_SHARING_VIOLATION | ERROR_LOCK_VIOLATION)
+    )
+}
+
+#[cfg(not(windows))]
+fn is_locked(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::ResourceBusy
+}
@@ -0,0 +1,177 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use crate::error::Error;
+use crate::executor::Report;
+use crate::planner::PlannedMove;
+use crate::settings::Source;
+
+use serde::Serialize;
+use std::path::Path;
+
+/// Observes a run driven by [`SortEngine::run`](crate::sorter::SortEngine::run). File events come in plan order
+/// once the files of the run have been moved. Every method does nothing by default.
+///
+/// Closures taking an [`Event`] are sinks too, and so are pairs of sinks. `()` ignores everything.
+pub trait EventSink {
+    /// Files of the source are about to be matched against the rules
+    fn on_scan_start(&self, _source: &Source) {}
+    fn on_match(&self, _planned: &PlannedMove) {}
+    /// The file was moved, or copied in copy mode
+    fn on_move(&self, _planned: &PlannedMove, _destination_file: &Path) {}
+    fn on_skip(&self, _planned: &PlannedMove, _reason: SkipReason) {}
+    /// A cleanup rule deleted or trashed the file
+    fn on_clean_up(&self, _planned: &PlannedMove) {}
+    fn on_error(&self, _error: &Error) {}
+    fn on_finish(&self, _report: &Report) {}
+}
+
+/// Ignores every event.
+impl EventSink for () {}
+
+impl<F> EventSink for F
+where
+    F: Fn(&Event),
+{
+    fn on_match(&self, planned: &PlannedMove) {
+        self(&Event::matched(
+            &planned.file,
+            &planned.rule,
+            &planned.destination_file,
+        ));
+    }
+
+    fn on_move(&self, planned: &PlannedMove, destination_file: &Path) {
+        self(&Event::moved(&planned.file, destination_file));
+    }
+
+    fn on_skip(&self, planned: &PlannedMove, reason: SkipReason) {
+        self(&Event::skipped(&planned.file, reason));
+    }
+
+    fn on_clean_up(&self, planned: &PlannedMove) {
+        self(&Event::cleaned_up(&planned.file));
+    }
+
+    fn on_error(&self, error: &Error) {
+        self(&Event::error(error));
+    }
+}
+
+impl<A: EventSink, B: EventSink> EventSink for (A, B) {
+    fn on_scan_start(&self, source: &Source) {
+        self.0.on_scan_start(source);
+        self.1.on_scan_start(source);
+    }
+
+    fn on_match(&self, planned: &PlannedMove) {
+        self.0.on_match(planned);
+        self.1.on_match(planned);
+    }
+
+    fn on_move(&self, planned: &PlannedMove, destination_file: &Path) {
+        self.0.on_move(planned, destination_file);
+        self.1.on_move(planned, destination_file);
+    }
+
+    fn on_skip(&self, planned: &PlannedMove, reason: SkipReason) {
+        self.0.on_skip(planned, reason);
+        self.1.on_skip(planned, reason);
+    }
+
+    fn on_clean_up(&self, planned: &PlannedMove) {
+        self.0.on_clean_up(planned);
+        self.1.on_clean_up(planned);
+    }
+
+    fn on_error(&self, error: &Error) {
+        self.0.on_error(error);
+        self.1.on_error(error);
+    }
+
+    fn on_finish(&self, report: &Report) {
+        self.0.on_finish(report);
+        self.1.on_finish(report);
+    }
+}
+
+/// Why a file was left where it is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SkipReason {
+    /// Another file is at the destination and the conflict policy is `skip`
+    Exists,
+    /// An identical file is at the destination
+    Duplicate,
+}
+
+/// Something that happened to a file, passed to closures given to [`SortEngine::run`](crate::sorter::SortEngine::run).
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum Event {
+    /// The watcher saw the file change
+    Detected { path: String },
+    /// A rule matched the file
+    #[serde(rename = "matched")]
+    FileMatched {
+        file: String,
+        rule: String,
+        destination: String,
+    },
+    /// The file was moved, or copied in copy mode
+    Moved { file: String, destination: String },
+    /// The file was left where it is
+    Skipped { file: String, reason: SkipReason },
+    /// A cleanup rule deleted or trashed the file
+    #[serde(rename = "cleaned")]
+    CleanedUp { file: String },
+    Error {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+        reason: String,
+    },
+}
+
+impl Event {
+    pub fn detected(path: &Path) -> Self {
+        Event::Detected {
+            path: path.display().to_string(),
+        }
+    }
+
+    pub fn matched(file: &Path, rule: &str, destination: &Path) -> Self {
+        Event::FileMatched {
+            file: file.display().to_string(),
+            rule: rule.to_string(),
+            destination: destination.display().to_string(),
+        }
+    }
+
+    pub fn moved(file: &Path, destination: &Path) -> Self {
+        Event::Moved {
+            file: file.display().to_string(),
+            destination: destination.display().to_string(),
+        }
+    }
+
+    pub fn skipped(file: &Path, reason: SkipReason) -> Self {
+        Event::Skipped {
+            file: file.display().to_string(),
+            reason,
+        }
+    }
+
+    pub fn cleaned_up(file: &Path) -> Self {
+        Event::CleanedUp {
+            file: file.display().to_string(),
+        }
+    }
+
+    pub fn error(error: &Error) -> Self {
+        Event::Error {
+            path: error.path().map(|path| path.display().to_string()),
+            reason: error.reason(),
+        }
+    }
+}
@@ -0,0 +1,414 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use crate::error::{Error, Result};
+use crate::events::{EventSink, SkipReason};
+use crate::finder;
+use crate::history;
+use crate::hooks;
+use crate::manifest;
+use crate::metrics;
+use crate::planner::{self, PlannedMove};
+use crate::settings::Settings;
+use crate::size::ByteSize;
+use crate::transfer::{self, CleanupAction, Outcome, TransferMode};
+use crate::utils;
+
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::Level;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+
+/// What a run did.
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Files moved or copied
+    pub sorted: usize,
+    /// Files moved or copied by each rule, by its destination
+    pub sorted_by_rule: BTreeMap<String, usize>,
+    /// Size of the files moved or copied
+    pub bytes: u64,
+    /// Files identical to the one at their destination
+    pub duplicates: usize,
+    /// Size of the duplicates removed from the sources by `dedup`
+    pub duplicate_bytes: u64,
+    /// Files deleted or trashed by cleanup rules
+    pub cleaned_up: usize,
+    /// Source dirs removed once the moves left them empty
+    pub pruned: usize,
+    /// Files not started because the run was interrupted
+    pub left: usize,
+    pub failures: Vec<Error>,
+}
+
+impl Report {
+    /// Like "Sorted 3 file(s), 2 duplicate(s) freeing 1.5 MiB, 1 failed".
+    pub fn summary(&self) -> String {
+        let mut summary = format!("Sorted {} file(s)", self.sorted);
+        if self.duplicates > 0 {
+            summary.push_str(&format!(", {} duplicate(s)", self.duplicates));
+            if self.duplicate_bytes > 0 {
+                summary.push_str(&format!(" freeing {}", ByteSize(self.duplicate_bytes)));
+            }
+        }
+        if self.cleaned_up > 0 {
+            summary.push_str(&format!(", {} cleaned up", self.cleaned_up));
+        }
+        if self.pruned > 0 {
+            summary.push_str(&format!(", {} empty dir(s) removed", self.pruned));
+        }
+        if !self.failures.is_empty() {
+            summary.push_str(&format!(", {} failed", self.failures.len()));
+        }
+        if self.left > 0 {
+            summary.push_str(&format!(", {} left after an interrupt", self.left));
+        }
+        summary
+    }
+}
+
+/// Set by [`interrupt`].
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Keeps runs from starting more transfers for the rest of the process. The ones in progress are
+/// finished, so no file is left half-moved, and the others are counted in [`Report::left`].
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Carries out the moves and reports the ones that failed, a failing file doesn't stop the rest
+/// unless `fail_fast` is set, in which case the first failure aborts the run.
+/// With `progress` a bar is drawn when stdout is a terminal.
+pub fn execute(
+    settings: &Settings,
+    moves: &[PlannedMove],
+    jobs: usize,
+    fail_fast: bool,
+    progress: bool,
+    sink: &dyn EventSink,
+) -> Result<Report> {
+    let (movable, failures) = create_destination_dirs(moves, fail_fast)?;
+    let moves: Vec<&PlannedMove> = movable.into_iter().map(|index| &moves[index]).collect();
+    for failure in &failures {
+        sink.on_error(failure);
+    }
+
+    // 0 lets rayon pick the number of logical CPUs
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(jobs).build()?;
+
+    let progress = if shows_progress(progress) {
+        Some(progress_bar(&moves))
+    } else {
+        None
+    };
+    let done = AtomicUsize::new(0);
+    metrics::set_queue_depth(moves.len());
+
+    // Results are put back in plan order, so output stays deterministic.
+    // Moves skipped after a failure in fail-fast mode or an interrupt have no result.
+    let failed = AtomicBool::new(false);
+    let groups = planner::destination_groups(&moves);
+    let grouped: Vec<Vec<_>> = pool.install(|| {
+        groups
+            .par_iter()
+            .map(|group| {
+                group
+                    .iter()
+                    .map(|&index| {
+                        if (fail_fast && failed.load(Ordering::Relaxed)) || interrupted() {
+                            return (index, None);
+                        }
+                        let (result, size) = transfer_one(settings, moves[index]);
+                        // Locked files get another chance at the end
+                        if result.as_ref().is_err_and(|e| !e.is_locked()) {
+                            failed.store(true, Ordering::Relaxed);
+                        }
+                        if let Some(progress) = &progress {
+                            let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+                            progress.set_message(format!("{}/{} files", done, moves.len()));
+                            progress.inc(size);
+                        }
+                        (index, Some((result, size)))
+                    })
+                    .collect()
+            })
+            .collect()
+    });
+    let mut results = in_plan_order(moves.len(), grouped);
+
+    if let Some(progress) = &progress {
+        progress.finish_and_clear();
+    }
+
+    retry_locked(settings, &moves, &mut results);
+    report(settings, &moves, results, failures, fail_fast, sink)
+}
+
+/// The results of the groups of [`planner::destination_groups`] in plan order.
+pub(crate) fn in_plan_order<T>(
+    len: usize,
+    grouped: Vec<Vec<(usize, Option<T>)>>,
+) -> Vec<Option<T>> {
+    let mut results: Vec<Option<T>> = (0..len).map(|_| None).collect();
+    for (index, result) in grouped.into_iter().flatten() {
+        results[index] = result;
+    }
+    results
+}
+
+/// Creates every destination dir up front, so workers don't race on it. Returns the indices of
+/// the moves whose dir is there, and the dirs that couldn't be created.
+pub(crate) fn create_destination_dirs(
+    moves: &[PlannedMove],
+    fail_fast: bool,
+) -> Result<(Vec<usize>, Vec<Error>)> {
+    let mut failures: Vec<Error> = Vec::new();
+
+    // Files cleaned up have nowhere to go
+    let mut destination_dirs: Vec<&PathBuf> = moves
+        .iter()
+        .filter(|m| m.action.is_none())
+        .map(|m| &m.destination_dir)
+        .collect();
+    destination_dirs.sort();
+    destination_dirs.dedup();
+
+    let mut failed_dirs: Vec<&PathBuf> = Vec::new();
+    for destination_dir in destination_dirs {
+        if let Err(e) = utils::create_dir(destination_dir) {
+            if fail_fast {
+                return Err(Error::Aborted(Box::new(e)));
+            }
+            error!("Failed to create destination dir {}", e);
+            failures.push(e);
+            failed_dirs.push(destination_dir);
+        }
+    }
+    let movable = (0..moves.len())
+        .filter(|&index| {
+            moves[index].action.is_some() || !failed_dirs.contains(&&moves[index].destination_dir)
+        })
+        .collect();
+
+    Ok((movable, failures))
+}
+
+/// Moves or copies a single file with its hooks, along with its size.
+pub(crate) fn transfer_one(settings: &Settings, planned: &PlannedMove) -> (Result<Outcome>, u64) {
+    // Measured first, a moved file is gone afterwards
+    let size = file_size(planned);
+    if let Some(action) = planned.action {
+        let result =
+            transfer::clean_up(settings, &planned.file, action).map(|()| Outcome::CleanedUp);
+        metrics::decrease_queue_depth();
+        return (result, size);
+    }
+    let result = hooks::pre_move(planned)
+        .and_then(|()| transfer::transfer(settings, &planned.file, &planned.destination_file));
+    if let Ok(Outcome::Transferred(destination_file)) = &result {
+        hooks::post_move(planned, destination_file);
+        if !planned.finder_tags.is_empty() {
+            if let Err(e) = finder::apply(destination_file, &planned.finder_tags) {
+                warn!("Failed to set Finder tags {}", e);
+            }
+        }
+        if let Some(format) = settings.manifest {
+            // The file is sorted already, a manifest that can't be updated doesn't undo that
+            if let Err(e) = manifest::record(format, destination_file) {
+                warn!("Failed to add to the manifest {}", e);
+            }
+        }
+    }
+    metrics::decrease_queue_depth();
+
+    (result, size)
+}
+
+/// Transfers the files another program had open again once the others are done, up to
+/// `locked_retries` times `locked_retry_delay` apart, replacing their results.
+pub(crate) fn retry_locked(
+    settings: &Settings,
+    moves: &[&PlannedMove],
+    results: &mut [Option<(Result<Outcome>, u64)>],
+) {
+    for attempt in 1..=settings.locked_retries {
+        let locked: Vec<usize> = (0..results.len())
+            .filter(|&index| match &results[index] {
+                Some((Err(e), _)) => e.is_locked(),
+                _ => false,
+            })
+            .collect();
+        if locked.is_empty() || interrupted() {
+            return;
+        }
+        if attempt == 1 {
+            for &index in &locked {
+                warn!(
+                    "Skipping {} for now, it's in use by another program",
+                    moves[index].file.display()
+                );
+            }
+        }
+        info!(
+            "Retrying {} file(s) in use in {} ({}/{})",
+            locked.len(),
+            humantime::format_duration(settings.locked_retry_delay),
+            attempt,
+            settings.locked_retries
+        );
+        thread::sleep(settings.locked_retry_delay);
+
+        metrics::set_queue_depth(locked.len());
+        for index in locked {
+            if interrupted() {
+                return;
+            }
+            results[index] = Some(transfer_one(settings, moves[index]));
+        }
+    }
+}
+
+/// Tells the sink about the results, in plan order, and sums them up.
+pub(crate) fn report(
+    settings: &Settings,
+    moves: &[&PlannedMove],
+    results: Vec<Option<(Result<Outcome>, u64)>>,
+    mut failures: Vec<Error>,
+    fail_fast: bool,
+    sink: &dyn EventSink,
+) -> Result<Report> {
+    let action = match settings.mode {
+        TransferMode::Move => "move",
+        TransferMode::Copy => "copy",
+    };
+    // Whatever fail-fast skipped isn't waiting anymore
+    metrics::set_queue_depth(0);
+
+    let mut report = Report::default();
+    let mut history: Vec<history::Entry> = Vec::new();
+    for (planned, result) in moves.iter().zip(results) {
+        let (result, size) = match result {
+            Some((result, size)) => (result, size),
+            None => {
+                report.left += 1;
+                continue;
+            }
+        };
+        match result {
+            Ok(Outcome::Transferred(destination_file)) => {
+                sink.on_move(planned, &destination_file);
+                let action = match settings.mode {
+                    TransferMode::Move => history::Action::Moved,
+                    TransferMode::Copy => history::Action::Copied,
+                };
+                history.push(history_entry(
+                    settings,
+                    planned,
+                    action,
+                    Some(destination_file),
+                    size,
+                ));
+                report.sorted += 1;
+                *report
+                    .sorted_by_rule
+                    .entry(planned.rule.clone())
+                    .or_default() += 1;
+                report.bytes += size;
+            }
+            Ok(Outcome::Skipped) => sink.on_skip(planned, SkipReason::Exists),
+            Ok(Outcome::CleanedUp) => {
+                sink.on_clean_up(planned);
+                report.cleaned_up += 1;
+                let action = match planned.action {
+                    Some(CleanupAction::Trash) if settings.trash => history::Action::Trashed,
+                    _ => history::Action::Deleted,
+                };
+                history.push(history_entry(settings, planned, action, None, size));
+            }
+            Ok(Outcome::Duplicate) => {
+                sink.on_skip(planned, SkipReason::Duplicate);
+                report.duplicates += 1;
+                if settings.dedup.removes(settings.mode) {
+                    report.duplicate_bytes += size;
+                }
+            }
+            Err(e) => {
+                error!("Failed to {} {}", action, e);
+                sink.on_error(&e);
+                failures.push(e);
+            }
+        }
+    }
+
+    // The files were sorted either way, a history that can't be written is only a warning
+    if settings.history && !history.is_empty() {
+        if let Err(e) = history::History::open().and_then(|mut db| db.record(&history)) {
+            warn!("Failed to record the moves in the history: {}", e);
+        }
+    }
+
+    if fail_fast && !failures.is_empty() {
+        return Err(Error::Aborted(Box::new(failures.remove(0))));
+    }
+
+    report.failures = failures;
+
+    Ok(report)
+}
+
+fn history_entry(
+    settings: &Settings,
+    planned: &PlannedMove,
+    action: history::Action,
+    destination: Option<PathBuf>,
+    size: u64,
+) -> history::Entry {
+    let source = settings
+        .sources
+        .iter()
+        .filter(|source| planned.file.starts_with(&source.path))
+        .max_by_key(|source| source.path.components().count())
+        .map(|source| source.path.clone());
+
+    history::Entry {
+        time: chrono::Utc::now().timestamp(),
+        action,
+        source,
+        file: planned.file.clone(),
+        destination,
+        rule: planned.rule.clone(),
+        size,
+    }
+}
+
+/// Whether `execute` draws a bar with `progress`: when stdout is a terminal someone is watching.
+pub fn shows_progress(progress: bool) -> bool {
+    progress && io::stdout().is_terminal() && log_enabled!(Level::Info)
+}
+
+/// A bar counting files and bytes.
+fn progress_bar(moves: &[&PlannedMove]) -> ProgressBar {
+    let total = moves.iter().map(|planned| file_size(planned)).sum();
+    let style = ProgressStyle::with_template(
+        "[{elapsed_precise}] {wide_bar} {bytes}/{total_bytes} ({msg}, ETA {eta})",
+    )
+    .expect("Expected valid progress template");
+
+    ProgressBar::with_draw_target(Some(total), ProgressDrawTarget::stdout())
+        .with_style(style)
+        .with_message(format!("0/{} files", moves.len()))
+}
+
+fn file_size(planned: &PlannedMove) -> u64 {
+    fs::metadata(&planned.file).map_or(0, |metadata| metadata.len())
+}
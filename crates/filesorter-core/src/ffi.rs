@@ -0,0 +1,196 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! A C API for embedding the sorter, behind the `ffi` feature. `include/filesorter.h` declares it.
+//!
+//! Functions returning a pointer return null on failure, [`filesorter_last_error`] then tells why.
+//! Everything returned must be freed by the matching `_free` function.
+
+use crate::error::{Error, Result};
+use crate::events::EventSink;
+use crate::planner::PlannedMove;
+use crate::settings::{Settings, Source};
+use crate::sorter::SortEngine;
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// A file the sort moved, or copied in copy mode.
+#[repr(C)]
+pub struct FilesorterMove {
+    pub source: *mut c_char,
+    pub destination: *mut c_char,
+}
+
+/// What [`filesorter_sort_path`] did.
+#[repr(C)]
+pub struct FilesorterResults {
+    pub moves: *mut FilesorterMove,
+    pub moves_len: usize,
+    /// Why files failed to be sorted, one message each
+    pub failures: *mut *mut c_char,
+    pub failures_len: usize,
+}
+
+/// Loads a config file, null if it can't be loaded.
+///
+/// # Safety
+/// `path` must be a valid NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn filesorter_settings_load(path: *const c_char) -> *mut Settings {
+    guard(|| {
+        let path = PathBuf::from(c_str(path)?);
+        let mut settings = Settings::load(&path)?;
+        settings.apply_env_overrides()?;
+        Ok(Box::into_raw(Box::new(settings)))
+    })
+}
+
+/// # Safety
+/// `settings` must come from [`filesorter_settings_load`] or be null, and is freed only once.
+#[no_mangle]
+pub unsafe extern "C" fn filesorter_settings_free(settings: *mut Settings) {
+    if !settings.is_null() {
+        drop(Box::from_raw(settings));
+    }
+}
+
+/// Sorts the files of the dir `path` by the rules of `settings`, instead of its sources.
+/// Null if the sort couldn't run, files failing on their own are in the results.
+///
+/// # Safety
+/// `settings` must come from [`filesorter_settings_load`], `path` must be a valid NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn filesorter_sort_path(
+    settings: *const Settings,
+    path: *const c_char,
+) -> *mut FilesorterResults {
+    guard(|| {
+        let mut settings = settings
+            .as_ref()
+            .ok_or_else(|| Error::Settings(String::from("No settings given")))?
+            .clone();
+        settings.sources = vec![Source::from(PathBuf::from(c_str(path)?))];
+
+        let sink = MoveRecorder::default();
+        let report = SortEngine::new(settings).run(&sink)?;
+
+        let moves: Vec<FilesorterMove> = sink
+            .0
+            .into_inner()
+            .into_iter()
+            .map(|(source, destination)| FilesorterMove {
+                source: to_c_string(&source.display().to_string()).into_raw(),
+                destination: to_c_string(&destination.display().to_string()).into_raw(),
+            })
+            .collect();
+        let failures: Vec<*mut c_char> = report
+            .failures
+            .iter()
+            .map(|failure| to_c_string(&failure.to_string()).into_raw())
+            .collect();
+
+        let (moves, moves_len) = into_raw_parts(moves);
+        let (failures, failures_len) = into_raw_parts(failures);
+        Ok(Box::into_raw(Box::new(FilesorterResults {
+            moves,
+            moves_len,
+            failures,
+            failures_len,
+        })))
+    })
+}
+
+/// # Safety
+/// `results` must come from [`filesorter_sort_path`] or be null, and is freed only once.
+#[no_mangle]
+pub unsafe extern "C" fn filesorter_results_free(results: *mut FilesorterResults) {
+    if results.is_null() {
+        return;
+    }
+
+    let results = Box::from_raw(results);
+    for planned in from_raw_parts(results.moves, results.moves_len) {
+        drop(CString::from_raw(planned.source));
+        drop(CString::from_raw(planned.destination));
+    }
+    for failure in from_raw_parts(results.failures, results.failures_len) {
+        drop(CString::from_raw(failure));
+    }
+}
+
+/// Why the last call on this thread returned null, null if it didn't. The message stays valid
+/// until the next call.
+#[no_mangle]
+pub extern "C" fn filesorter_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| {
+        last_error
+            .borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
+
+#[derive(Default)]
+struct MoveRecorder(RefCell<Vec<(PathBuf, PathBuf)>>);
+
+impl EventSink for &MoveRecorder {
+    fn on_move(&self, planned: &PlannedMove, destination_file: &Path) {
+        self.0
+            .borrow_mut()
+            .push((planned.file.clone(), destination_file.to_path_buf()));
+    }
+}
+
+/// Runs `f` keeping errors and panics from crossing into C, they become the last error and null.
+fn guard<T>(f: impl FnOnce() -> Result<*mut T>) -> *mut T {
+    let message = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => {
+            set_last_error(None);
+            return value;
+        }
+        Ok(Err(e)) => e.to_string(),
+        Err(_) => String::from("filesorter panicked"),
+    };
+    set_last_error(Some(message));
+
+    ptr::null_mut()
+}
+
+fn set_last_error(message: Option<String>) {
+    let message = message.map(|message| to_c_string(&message));
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = message);
+}
+
+unsafe fn c_str<'a>(s: *const c_char) -> Result<&'a str> {
+    if s.is_null() {
+        return Err(Error::Settings(String::from("No path given")));
+    }
+
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|e| Error::Settings(format!("Path isn't UTF-8: {}", e)))
+}
+
+/// C strings can't hold NULs, they're dropped.
+fn to_c_string(s: &str) -> CString {
+    CString::new(s.replace('\0', "")).expect("Expected NULs to be removed")
+}
+
+fn into_raw_parts<T>(values: Vec<T>) -> (*mut T, usize) {
+    let len = values.len();
+    (Box::into_raw(values.into_boxed_slice()) as *mut T, len)
+}
+
+unsafe fn from_raw_parts<T>(values: *mut T, len: usize) -> Vec<T> {
+    Box::from_raw(ptr::slice_from_raw_parts_mut(values, len)).into_vec()
+}
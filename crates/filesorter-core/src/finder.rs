@@ -0,0 +1,155 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Finder tags rules put on the files they sort, on macOS.
+
+use crate::error::Result;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The colors of Finder tags, by the number Finder keeps for each.
+const COLORS: &[(&str, u8)] = &[
+    ("gray", 1),
+    ("green", 2),
+    ("purple", 3),
+    ("blue", 4),
+    ("yellow", 5),
+    ("red", 6),
+    ("orange", 7),
+];
+
+/// A Finder tag: a color like `red` for the tag Finder has for it, or a name with an optional
+/// color like `Invoices:green`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FinderTag {
+    pub name: String,
+    pub color: Option<u8>,
+}
+
+impl FinderTag {
+    /// How Finder stores the tag, the name and the color number on separate lines.
+    #[cfg(target_os = "macos")]
+    fn stored(&self) -> String {
+        match self.color {
+            Some(color) => format!("{}\n{}", self.name, color),
+            None => self.name.clone(),
+        }
+    }
+}
+
+fn color_number(name: &str) -> Option<u8> {
+    COLORS
+        .iter()
+        .find(|(color, _)| color.eq_ignore_ascii_case(name))
+        .map(|(_, number)| *number)
+}
+
+impl FromStr for FinderTag {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.trim();
+        if let Some(color) = color_number(text) {
+            // Finder's own tags are named like the color, capitalized
+            let mut name = text.to_lowercase();
+            name[..1].make_ascii_uppercase();
+            return Ok(FinderTag {
+                name,
+                color: Some(color),
+            });
+        }
+
+        let (name, color) = match text.rsplit_once(':') {
+            Some((name, color)) => {
+                let color = color_number(color.trim()).ok_or_else(|| {
+                    format!(
+                        "unknown Finder tag color '{}', expected one of {}",
+                        color.trim(),
+                        COLORS
+                            .iter()
+                            .map(|(color, _)| *color)
+                            .collect::<Vec<&str>>()
+                            .join(", ")
+                    )
+                })?;
+                (name.trim(), Some(color))
+            }
+            None => (text, None),
+        };
+        if name.is_empty() {
+            return Err(format!("Finder tag '{}' has no name", text));
+        }
+
+        Ok(FinderTag {
+            name: name.to_string(),
+            color,
+        })
+    }
+}
+
+impl fmt::Display for FinderTag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let color = self
+            .color
+            .and_then(|number| COLORS.iter().find(|(_, n)| *n == number))
+            .map(|(color, _)| *color);
+        match color {
+            Some(color) if color.eq_ignore_ascii_case(&self.name) => f.write_str(color),
+            Some(color) => write!(f, "{}:{}", self.name, color),
+            None => f.write_str(&self.name),
+        }
+    }
+}
+
+impl Serialize for FinderTag {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for FinderTag {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Adds the tags to the ones the file has, keeping those.
+#[cfg(target_os = "macos")]
+pub fn apply(file: &Path, tags: &[FinderTag]) -> Result<()> {
+    use crate::error::Error;
+    use std::io;
+
+    const USER_TAGS: &str = "com.apple.metadata:_kMDItemUserTags";
+
+    let mut stored: Vec<String> = match xattr::get(file, USER_TAGS) {
+        // Tags Finder can't read either are replaced
+        Ok(Some(value)) => plist::from_bytes(&value).unwrap_or_default(),
+        Ok(None) => Vec::new(),
+        Err(e) => return Err(Error::io(file, e)),
+    };
+    for tag in tags {
+        let present = stored
+            .iter()
+            .any(|stored| stored.split('\n').next() == Some(tag.name.as_str()));
+        if !present {
+            stored.push(tag.stored());
+        }
+    }
+
+    let mut value = Vec::new();
+    plist::to_writer_binary(&mut value, &stored)
+        .map_err(|e| Error::io(file, io::Error::other(e)))?;
+    xattr::set(file, USER_TAGS, &value).map_err(|e| Error::io(file, e))
+}
+
+// Only Finder shows them, elsewhere the tags are left out
+#[cfg(not(target_os = "macos"))]
+pub fn apply(_file: &Path, _tags: &[FinderTag]) -> Result<()> {
+    Ok(())
+}
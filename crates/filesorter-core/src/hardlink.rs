@@ -0,0 +1,309 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Finds identical files in a destination tree, and replaces them with hardlinks to a single copy.
+
+use crate::error::{Error, Result};
+use crate::hash;
+use crate::size::ByteSize;
+use crate::transfer;
+use crate::utils;
+
+use rayon::prelude::*;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, Metadata};
+use std::path::{Path, PathBuf};
+
+/// What a pass did, or would do with `dry_run`.
+#[derive(Debug, Default)]
+pub struct LinkReport {
+    /// Files replaced by a hardlink
+    pub linked: usize,
+    /// Space freed, files that had other links free nothing
+    pub reclaimed: u64,
+    pub failures: Vec<Error>,
+}
+
+impl LinkReport {
+    /// Like "Linked 3 duplicate(s), freeing 1.5 MiB".
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "Linked {} duplicate(s), freeing {}",
+            self.linked,
+            ByteSize(self.reclaimed)
+        );
+        if !self.failures.is_empty() {
+            summary.push_str(&format!(", {} failed", self.failures.len()));
+        }
+        summary
+    }
+}
+
+/// Files with the same contents.
+#[derive(Debug, Serialize)]
+pub struct DuplicateGroup {
+    /// BLAKE3 hash of the contents, in hex
+    pub hash: String,
+    /// Size of each file
+    pub size: u64,
+    /// Sorted, one per inode, files already linked together count once
+    pub paths: Vec<PathBuf>,
+    /// Space freed by linking all but the first to it, copies that have other links free nothing
+    pub savings: u64,
+}
+
+/// The identical files of a tree.
+#[derive(Debug, Default, Serialize)]
+pub struct DuplicateReport {
+    /// Largest savings first
+    pub groups: Vec<DuplicateGroup>,
+    #[serde(skip)]
+    pub failures: Vec<Error>,
+}
+
+impl DuplicateReport {
+    pub fn savings(&self) -> u64 {
+        self.groups.iter().map(|group| group.savings).sum()
+    }
+
+    /// Like "Found 2 group(s) of 5 identical file(s), 1.5 MiB can be freed".
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "Found {} group(s) of {} identical file(s), {} can be freed",
+            self.groups.len(),
+            self.groups
+                .iter()
+                .map(|group| group.paths.len())
+                .sum::<usize>(),
+            ByteSize(self.savings())
+        );
+        if !self.failures.is_empty() {
+            summary.push_str(&format!(", {} failed", self.failures.len()));
+        }
+        summary
+    }
+}
+
+/// A file that may have copies, one per inode.
+struct Candidate {
+    path: PathBuf,
+    size: u64,
+    links: u64,
+}
+
+/// Groups the identical files under `root`, without changing anything. Hidden and empty files,
+/// copies on another filesystem and files already linked together are left out. Files are compared
+/// by [`hash::same_contents`] rules with `partial_hash` bytes at each end, on `jobs` threads, 0 for
+/// the number of CPUs.
+pub fn find_duplicates(root: &Path, partial_hash: u64, jobs: usize) -> Result<DuplicateReport> {
+    let mut report = DuplicateReport::default();
+    for (hash, mut copies) in find_copies(root, partial_hash, jobs, &mut report.failures)? {
+        copies.sort_by(|a, b| a.path.cmp(&b.path));
+        report.groups.push(DuplicateGroup {
+            hash: hash.to_hex().to_string(),
+            size: copies[0].size,
+            savings: copies[1..]
+                .iter()
+                .filter(|copy| copy.links <= 1)
+                .map(|copy| copy.size)
+                .sum(),
+            paths: copies.into_iter().map(|copy| copy.path).collect(),
+        });
+    }
+    // Stable, so groups of equal savings keep their order by size
+    report.groups.sort_by_key(|group| Reverse(group.savings));
+
+    Ok(report)
+}
+
+/// Links every copy of a file under `root` to the first of them by path, files are found like
+/// [`find_duplicates`] does.
+pub fn link_duplicates(
+    root: &Path,
+    partial_hash: u64,
+    jobs: usize,
+    dry_run: bool,
+) -> Result<LinkReport> {
+    let mut report = LinkReport::default();
+    for (_, mut copies) in find_copies(root, partial_hash, jobs, &mut report.failures)? {
+        copies.sort_by(|a, b| a.path.cmp(&b.path));
+        let original = &copies[0];
+        for copy in &copies[1..] {
+            let result = if dry_run {
+                Ok(())
+            } else {
+                link(&original.path, &copy.path)
+            };
+            match result {
+                Ok(()) => {
+                    info!(
+                        "{} {} to {}",
+                        if dry_run { "Would link" } else { "Linked" },
+                        copy.path.display(),
+                        original.path.display()
+                    );
+                    report.linked += 1;
+                    if copy.links <= 1 {
+                        report.reclaimed += copy.size;
+                    }
+                }
+                Err(e) => {
+                    error!("Failed to link {}", e);
+                    report.failures.push(e);
+                }
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// The groups of identical files under `root` with their hash, files that couldn't be read are
+/// added to `failures`.
+fn find_copies(
+    root: &Path,
+    partial_hash: u64,
+    jobs: usize,
+    failures: &mut Vec<Error>,
+) -> Result<Vec<(blake3::Hash, Vec<Candidate>)>> {
+    let mut files = utils::get_files(root, usize::MAX, &[], &|path, _| utils::is_hidden(path))?;
+    files.sort();
+
+    // Only files of the same size on the same filesystem can be linked together
+    let mut by_size: BTreeMap<(u64, u64), Vec<Candidate>> = BTreeMap::new();
+    let mut seen_inodes: HashSet<(u64, u64)> = HashSet::new();
+    for path in files {
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_file() && metadata.len() > 0 => metadata,
+            Ok(_) => continue,
+            Err(e) => {
+                let e = Error::io(&path, e);
+                error!("Failed to read {}", e);
+                failures.push(e);
+                continue;
+            }
+        };
+
+        let (device, inode) = file_id(&metadata);
+        // Links to an inode already seen are copies that share it already
+        if inode.is_some_and(|inode| !seen_inodes.insert((device, inode))) {
+            continue;
+        }
+        by_size
+            .entry((device, metadata.len()))
+            .or_default()
+            .push(Candidate {
+                path,
+                size: metadata.len(),
+                links: links(&metadata),
+            });
+    }
+
+    let groups: Vec<(blake3::Hash, Vec<Candidate>)> = by_size
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .map(|group| (blake3::Hash::from([0; 32]), group))
+        .collect();
+
+    // 0 lets rayon pick the number of logical CPUs
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(jobs).build()?;
+    // The ends of files tell most apart, only files whose ends match are hashed whole
+    let groups = regroup(&pool, groups, failures, |candidate, _| {
+        hash::hash_ends(&candidate.path, partial_hash)
+    });
+    Ok(regroup(&pool, groups, failures, |candidate, ends| {
+        if hash::ends_cover(candidate.size, partial_hash) {
+            // The ends were the whole file
+            Ok(ends)
+        } else {
+            hash::hash_file(&candidate.path)
+        }
+    }))
+}
+
+/// Splits each group by the hashes of its files, keeping the parts with more than one file.
+/// `hash` is given each file along with the hash of its group. Files that can't be hashed are
+/// left out as failures.
+fn regroup(
+    pool: &rayon::ThreadPool,
+    groups: Vec<(blake3::Hash, Vec<Candidate>)>,
+    failures: &mut Vec<Error>,
+    hash: impl Fn(&Candidate, blake3::Hash) -> Result<blake3::Hash> + Sync,
+) -> Vec<(blake3::Hash, Vec<Candidate>)> {
+    let candidates: Vec<(usize, blake3::Hash, Candidate)> = groups
+        .into_iter()
+        .enumerate()
+        .flat_map(|(index, (group_hash, group))| {
+            group
+                .into_iter()
+                .map(move |candidate| (index, group_hash, candidate))
+        })
+        .collect();
+    debug!("Hashing {} possible duplicate(s)", candidates.len());
+    let hashes: Vec<Result<blake3::Hash>> = pool.install(|| {
+        candidates
+            .par_iter()
+            .map(|(_, group_hash, candidate)| hash(candidate, *group_hash))
+            .collect()
+    });
+
+    let mut by_hash: BTreeMap<(usize, [u8; 32]), Vec<Candidate>> = BTreeMap::new();
+    for ((index, _, candidate), hash) in candidates.into_iter().zip(hashes) {
+        match hash {
+            Ok(hash) => by_hash
+                .entry((index, *hash.as_bytes()))
+                .or_default()
+                .push(candidate),
+            Err(e) => {
+                error!("Failed to hash {}", e);
+                failures.push(e);
+            }
+        }
+    }
+
+    by_hash
+        .into_iter()
+        .filter(|(_, group)| group.len() > 1)
+        .map(|((_, hash), group)| (blake3::Hash::from(hash), group))
+        .collect()
+}
+
+/// Replaces `copy` with a link to `original`, through a temporary link renamed into place
+/// so the copy is never missing.
+fn link(original: &Path, copy: &Path) -> Result<()> {
+    let temp_file = transfer::temp_path(copy);
+    fs::hard_link(original, &temp_file).map_err(|e| Error::io(copy, e))?;
+
+    fs::rename(&temp_file, copy).map_err(|e| {
+        let _ = fs::remove_file(&temp_file);
+        Error::io(copy, e)
+    })
+}
+
+/// The filesystem and inode of a file, where the platform tells them.
+#[cfg(unix)]
+fn file_id(metadata: &Metadata) -> (u64, Option<u64>) {
+    use std::os::unix::fs::MetadataExt;
+
+    (metadata.dev(), Some(metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &Metadata) -> (u64, Option<u64>) {
+    (0, None)
+}
+
+#[cfg(unix)]
+fn links(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.nlink()
+}
+
+#[cfg(not(unix))]
+fn links(_metadata: &Metadata) -> u64 {
+    1
+}
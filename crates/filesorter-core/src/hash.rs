@@ -0,0 +1,56 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use crate::error::{Error, Result};
+
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// BLAKE3 hash of the file contents.
+pub fn hash_file(path: &Path) -> Result<blake3::Hash> {
+    let mut file = File::open(path).map_err(|e| Error::io(path, e))?;
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut file, &mut hasher).map_err(|e| Error::io(path, e))?;
+
+    Ok(hasher.finalize())
+}
+
+/// Whether hashing `partial` bytes at each end of a file of `size` bytes covers all of it,
+/// always with 0.
+pub fn ends_cover(size: u64, partial: u64) -> bool {
+    partial == 0 || size <= partial.saturating_mul(2)
+}
+
+/// BLAKE3 hash of the first and last `partial` bytes of the file, of all of it when they cover it.
+pub fn hash_ends(path: &Path, partial: u64) -> Result<blake3::Hash> {
+    let mut file = File::open(path).map_err(|e| Error::io(path, e))?;
+    let size = file.metadata().map_err(|e| Error::io(path, e))?.len();
+    if ends_cover(size, partial) {
+        return hash_file(path);
+    }
+
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut (&mut file).take(partial), &mut hasher).map_err(|e| Error::io(path, e))?;
+    file.seek(SeekFrom::End(-(partial as i64)))
+        .map_err(|e| Error::io(path, e))?;
+    io::copy(&mut file, &mut hasher).map_err(|e| Error::io(path, e))?;
+
+    Ok(hasher.finalize())
+}
+
+/// Whether both files have the same contents. Cheapest first: sizes, then the `partial` bytes
+/// at both ends of the files, and only when those match the whole files.
+pub fn same_contents(a: &Path, b: &Path, partial: u64) -> Result<bool> {
+    let a_len = fs::metadata(a).map_err(|e| Error::io(a, e))?.len();
+    let b_len = fs::metadata(b).map_err(|e| Error::io(b, e))?.len();
+    if a_len != b_len {
+        return Ok(false);
+    }
+    if ends_cover(a_len, partial) {
+        return Ok(hash_file(a)? == hash_file(b)?);
+    }
+
+    Ok(hash_ends(a, partial)? == hash_ends(b, partial)? && hash_file(a)? == hash_file(b)?)
+}
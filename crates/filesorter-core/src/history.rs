@@ -0,0 +1,247 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Every completed move, kept in a SQLite database in the data dir for `filesorter history`.
+
+use crate::error::{Error, Result};
+use crate::settings::Settings;
+
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use rusqlite::{params, Connection, ToSql};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What happened to a file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Moved,
+    Copied,
+    /// Sent to the trash by a cleanup rule
+    Trashed,
+    /// Deleted by a cleanup rule
+    Deleted,
+}
+
+impl Action {
+    fn name(self) -> &'static str {
+        match self {
+            Action::Moved => "moved",
+            Action::Copied => "copied",
+            Action::Trashed => "trashed",
+            Action::Deleted => "deleted",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [
+            Action::Moved,
+            Action::Copied,
+            Action::Trashed,
+            Action::Deleted,
+        ]
+        .iter()
+        .copied()
+        .find(|action| action.name() == name)
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Entry {
+    /// When the move completed, as a Unix timestamp
+    pub time: i64,
+    pub action: Action,
+    /// The source the file was in, if it was in one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<PathBuf>,
+    pub file: PathBuf,
+    /// Where the file went, cleaned up files have none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub destination: Option<PathBuf>,
+    /// Destination of the rule that matched
+    pub rule: String,
+    pub size: u64,
+}
+
+/// Which entries [`History::query`] returns, any unset field lets all through.
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    /// Unix timestamps, `until` excluded
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub rule: Option<String>,
+    /// Entries of files from this source, or a dir inside it
+    pub source: Option<PathBuf>,
+    /// Files that had this name before they were sorted, `*` and `?` match any characters
+    pub name: Option<String>,
+    /// Only the latest entries
+    pub limit: Option<usize>,
+}
+
+pub struct History {
+    connection: Connection,
+}
+
+impl History {
+    /// Opens the database, creating it on first use.
+    pub fn open() -> Result<Self> {
+        let path = History::get_history_path();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+        }
+
+        let connection = Connection::open(&path)?;
+        // Runs of `sort` and `watch` may record at the same time
+        connection.busy_timeout(std::time::Duration::from_secs(5))?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS moves (
+                id INTEGER PRIMARY KEY,
+                time INTEGER NOT NULL,
+                action TEXT NOT NULL,
+                source TEXT,
+                file TEXT NOT NULL,
+                destination TEXT,
+                rule TEXT NOT NULL,
+                size INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS moves_time ON moves (time);",
+        )?;
+
+        Ok(History { connection })
+    }
+
+    /// Adds the entries in a single transaction.
+    pub fn record(&mut self, entries: &[Entry]) -> Result<()> {
+        let transaction = self.connection.transaction()?;
+        {
+            let mut insert = transaction.prepare(
+                "INSERT INTO moves (time, action, source, file, destination, rule, size)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )?;
+            for entry in entries {
+                insert.execute(params![
+                    entry.time,
+                    entry.action.name(),
+                    entry.source.as_deref().map(path_text),
+                    path_text(&entry.file),
+                    entry.destination.as_deref().map(path_text),
+                    entry.rule,
+                    entry.size as i64,
+                ])?;
+            }
+        }
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    /// The entries the filter lets through, oldest first.
+    pub fn query(&self, filter: &Filter) -> Result<Vec<Entry>> {
+        let mut conditions: Vec<&str> = Vec::new();
+        let mut values: Vec<Box<dyn ToSql>> = Vec::new();
+        if let Some(since) = filter.since {
+            conditions.push("time >= ?");
+            values.push(Box::new(since));
+        }
+        if let Some(until) = filter.until {
+            conditions.push("time < ?");
+            values.push(Box::new(until));
+        }
+        if let Some(rule) = &filter.rule {
+            conditions.push("rule = ?");
+            values.push(Box::new(rule.clone()));
+        }
+        if let Some(source) = &filter.source {
+            // The file is in the dir, or one of its subdirs
+            let dir = path_text(source).trim_end_matches(['/', '\\']).to_string();
+            conditions.push("(file LIKE ? ESCAPE '\\' OR source = ?)");
+            let files = format!("{}{}", dir, std::path::MAIN_SEPARATOR);
+            values.push(Box::new(format!("{}%", escape_like(&files))));
+            values.push(Box::new(dir));
+        }
+        if let Some(name) = &filter.name {
+            // LIKE ignores case, like file names mostly do
+            let name = escape_like(name).replace('*', "%").replace('?', "_");
+            conditions.push("file LIKE ? ESCAPE '\\'");
+            values.push(Box::new(format!(
+                "%{}{}",
+                escape_like(std::path::MAIN_SEPARATOR_STR),
+                name
+            )));
+        }
+
+        let mut query =
+            String::from("SELECT time, action, source, file, destination, rule, size FROM moves");
+        if !conditions.is_empty() {
+            query.push_str(" WHERE ");
+            query.push_str(&conditions.join(" AND "));
+        }
+        query.push_str(" ORDER BY time DESC, id DESC");
+        if let Some(limit) = filter.limit {
+            query.push_str(&format!(" LIMIT {}", limit));
+        }
+
+        let mut statement = self.connection.prepare(&query)?;
+        let rows = statement.query_map(
+            rusqlite::params_from_iter(values.iter().map(|value| value.as_ref())),
+            |row| {
+                let action: String = row.get(1)?;
+                Ok(Entry {
+                    time: row.get(0)?,
+                    action: Action::from_name(&action).unwrap_or(Action::Moved),
+                    source: row.get::<_, Option<String>>(2)?.map(PathBuf::from),
+                    file: PathBuf::from(row.get::<_, String>(3)?),
+                    destination: row.get::<_, Option<String>>(4)?.map(PathBuf::from),
+                    rule: row.get(5)?,
+                    size: row.get::<_, i64>(6)? as u64,
+                })
+            },
+        )?;
+        let mut entries = rows.collect::<rusqlite::Result<Vec<Entry>>>()?;
+        entries.reverse();
+
+        Ok(entries)
+    }
+
+    pub fn get_history_path() -> PathBuf {
+        Settings::get_data_dir().join("history.db")
+    }
+}
+
+/// Parses a point in time for [`Filter`]: a date like `2024-05-14` (its midnight), a date and
+/// time like `2024-05-14 18:30`, both local, or a duration like `3d` meaning that long ago.
+pub fn parse_time(text: &str) -> std::result::Result<i64, String> {
+    let text = text.trim();
+    if let Ok(duration) = humantime::parse_duration(text) {
+        return Ok(Utc::now().timestamp() - duration.as_secs() as i64);
+    }
+
+    let time = NaiveDate::parse_from_str(text, "%Y-%m-%d")
+        .map(|date| date.and_hms_opt(0, 0, 0).unwrap_or_default())
+        .or_else(|_| NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M"))
+        .or_else(|_| NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S"))
+        .map_err(|_| {
+            format!(
+                "'{}' is neither a date like 2024-05-14 (18:30) nor a duration like 3d",
+                text
+            )
+        })?;
+
+    Local
+        .from_local_datetime(&time)
+        .earliest()
+        .map(|time| time.timestamp())
+        .ok_or_else(|| format!("'{}' doesn't exist in the local timezone", text))
+}
+
+fn path_text(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
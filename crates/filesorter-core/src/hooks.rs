@@ -0,0 +1,102 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use crate::error::{Error, Result};
+use crate::planner::PlannedMove;
+use crate::template;
+
+use std::path::Path;
+use std::process::Command;
+
+/// Placeholders available in `pre_hook` and `post_hook`.
+pub const HOOK_PLACEHOLDERS: &[&str] = &["src", "dest", "rule"];
+
+/// Runs the `pre_hook` of a move, the file mustn't be moved if it fails.
+pub fn pre_move(planned: &PlannedMove) -> Result<()> {
+    match &planned.pre_hook {
+        Some(command) => run(
+            "pre_hook",
+            command,
+            &planned.file,
+            &planned.destination_file,
+            &planned.rule,
+        ),
+        None => Ok(()),
+    }
+}
+
+/// Runs the `post_hook` of a move, the file is already in place so failures are only logged.
+pub fn post_move(planned: &PlannedMove, destination_file: &Path) {
+    if let Some(command) = &planned.post_hook {
+        if let Err(e) = run(
+            "post_hook",
+            command,
+            &planned.file,
+            destination_file,
+            &planned.rule,
+        ) {
+            warn!("{}", e);
+        }
+    }
+}
+
+fn run(hook: &'static str, command: &str, src: &Path, dest: &Path, rule: &str) -> Result<()> {
+    let command = template::expand(command, |name| match name {
+        "src" => Some(quote(&src.display().to_string())),
+        "dest" => Some(quote(&dest.display().to_string())),
+        "rule" => Some(quote(rule)),
+        _ => None,
+    });
+    let error = |reason: String| Error::Hook {
+        hook,
+        path: src.to_path_buf(),
+        reason,
+    };
+
+    debug!("Running {}: {}", hook, command);
+    let output = shell(&command).output().map_err(|e| error(e.to_string()))?;
+
+    if !output.stdout.is_empty() {
+        debug!(
+            "{} said: {}",
+            hook,
+            String::from_utf8_lossy(&output.stdout).trim_end()
+        );
+    }
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        // The last line is usually the one explaining what went wrong
+        return Err(error(match stderr.trim_end().lines().last() {
+            Some(line) => format!("{}: {}", output.status, line),
+            None => output.status.to_string(),
+        }));
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+/// Quotes a value so the shell passes it on as a single argument.
+#[cfg(unix)]
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(windows)]
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
@@ -0,0 +1,268 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use crate::error::{Error, Result};
+use crate::hash;
+use crate::planner::PlannedMove;
+use crate::settings::Settings;
+use crate::targets;
+use crate::transfer::{self, ConflictPolicy, Outcome, TransferMode};
+use crate::utils;
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// The moves a run is about to make, written before they start and removed once they're done.
+/// A journal left behind means the run was interrupted, see `resume`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Journal {
+    pub mode: TransferMode,
+    /// Journals from before it was recorded resume with the one from the settings
+    #[serde(default)]
+    pub conflict_policy: Option<ConflictPolicy>,
+    pub moves: Vec<PlannedMove>,
+    /// The names reserved for files whose destination was taken, see `record_target`
+    #[serde(skip)]
+    pub targets: HashMap<PathBuf, PathBuf>,
+}
+
+/// A line of the targets file.
+#[derive(Serialize, Deserialize)]
+struct Target {
+    file: PathBuf,
+    target: PathBuf,
+}
+
+impl Journal {
+    pub fn new(mode: TransferMode, conflict_policy: ConflictPolicy, moves: &[PlannedMove]) -> Self {
+        Journal {
+            mode,
+            conflict_policy: Some(conflict_policy),
+            moves: moves.to_vec(),
+            targets: HashMap::new(),
+        }
+    }
+
+    /// Writes the journal and flushes it to disk, so it survives a power loss.
+    pub fn save(&self) -> Result<()> {
+        let path = Journal::get_journal_path();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+        }
+        let tmp_path = PathBuf::from(format!("{}.tmp", path.display()));
+
+        let text = serde_yaml::to_string(self)?;
+        fs::File::create(&tmp_path)
+            .and_then(|mut file| {
+                file.write_all(text.as_bytes())?;
+                file.sync_all()
+            })
+            .and_then(|()| fs::rename(&tmp_path, &path))
+            .map_err(|e| Error::io(&path, e))?;
+
+        // Targets of an earlier run don't belong to these moves
+        remove_file(&Journal::get_targets_path())
+    }
+
+    /// The journal of an interrupted run, if there is one.
+    pub fn load() -> Result<Option<Self>> {
+        let path = Journal::get_journal_path();
+        let mut journal: Journal = match fs::File::open(&path) {
+            Ok(file) => serde_yaml::from_reader(file)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::io(&path, e)),
+        };
+
+        let targets_path = Journal::get_targets_path();
+        let lines = match fs::File::open(&targets_path) {
+            Ok(file) => BufReader::new(file).lines(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Some(journal)),
+            Err(e) => return Err(Error::io(&targets_path, e)),
+        };
+        for line in lines {
+            let line = line.map_err(|e| Error::io(&targets_path, e))?;
+            // The last line is cut short when the run was interrupted while writing it, its
+            // placeholder is still empty then and resuming treats it as taken
+            if let Ok(target) = serde_json::from_str::<Target>(&line) {
+                journal.targets.insert(target.file, target.target);
+            }
+        }
+
+        Ok(Some(journal))
+    }
+
+    pub fn remove() -> Result<()> {
+        remove_file(&Journal::get_journal_path())?;
+        remove_file(&Journal::get_targets_path())
+    }
+
+    /// Where `planned` was going to end up: the name reserved for it, or its destination.
+    pub fn target<'a>(&'a self, planned: &'a PlannedMove) -> &'a Path {
+        self.targets
+            .get(&planned.file)
+            .unwrap_or(&planned.destination_file)
+    }
+
+    pub fn get_journal_path() -> PathBuf {
+        Settings::get_data_dir().join("journal.yaml")
+    }
+
+    pub fn get_targets_path() -> PathBuf {
+        Settings::get_data_dir().join("journal.targets")
+    }
+}
+
+/// Records the name reserved for `file` when its destination was taken, so resuming finds it.
+/// Only done while a journal is being kept.
+pub fn record_target(file: &Path, target: &Path) -> Result<()> {
+    if !Journal::get_journal_path().exists() {
+        return Ok(());
+    }
+
+    let path = Journal::get_targets_path();
+    let mut line = serde_json::to_string(&Target {
+        file: file.to_path_buf(),
+        target: target.to_path_buf(),
+    })
+    .map_err(|e| Error::io(&path, io::Error::other(e)))?;
+    line.push('\n');
+
+    // One write per line, so moves running in parallel don't interleave theirs
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut targets| {
+            targets.write_all(line.as_bytes())?;
+            targets.sync_data()
+        })
+        .map_err(|e| Error::io(&path, e))
+}
+
+/// The copies to `target` an interrupted run may have left half-written.
+pub fn partial_files(target: &Path) -> [PathBuf; 2] {
+    [
+        transfer::temp_path(target),
+        transfer::temp_path(&transfer::staged_path(target)),
+    ]
+}
+
+fn remove_file(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(Error::io(path, e)),
+        _ => Ok(()),
+    }
+}
+
+/// Finishes the moves of an interrupted run. Half-written copies are rolled back and redone,
+/// moves that never started are carried out and finished ones are left as they are.
+/// Returns the moves that couldn't be resumed.
+pub fn resume(settings: &Settings, journal: &Journal) -> Vec<Error> {
+    let mut settings = settings.clone();
+    settings.mode = journal.mode;
+    if let Some(conflict_policy) = journal.conflict_policy {
+        settings.conflict_policy = conflict_policy;
+    }
+
+    let mut failures: Vec<Error> = Vec::new();
+    let mut completed = 0;
+
+    for planned in &journal.moves {
+        match resume_move(&settings, planned, journal.targets.get(&planned.file)) {
+            Ok(true) => {
+                info!(
+                    target: targets::SORTED,
+                    "Completed {} to {}",
+                    planned.file.display(),
+                    planned.destination_dir.display()
+                );
+                completed += 1;
+            }
+            Ok(false) => {}
+            Err(e) => {
+                error!("Failed to resume {}", e);
+                failures.push(e);
+            }
+        }
+    }
+
+    info!(
+        "Resumed interrupted run: {} of {} move(s) completed now, {} failed",
+        completed,
+        journal.moves.len(),
+        failures.len()
+    );
+
+    failures
+}
+
+/// Returns whether anything had to be done. `reserved` is the name reserved for the file when
+/// its destination was taken.
+fn resume_move(
+    settings: &Settings,
+    planned: &PlannedMove,
+    reserved: Option<&PathBuf>,
+) -> Result<bool> {
+    if let Some(action) = planned.action {
+        if !planned.file.exists() {
+            return Ok(false);
+        }
+        transfer::clean_up(settings, &planned.file, action)?;
+        return Ok(true);
+    }
+
+    let target = reserved.unwrap_or(&planned.destination_file);
+    for partial_file in &partial_files(target) {
+        remove_file(partial_file)?;
+    }
+
+    // The file was complete and waiting next to the target, only renaming it into place was left
+    let staged_file = transfer::staged_path(target);
+    if staged_file.exists() {
+        if reserved.is_some() {
+            fs::rename(&staged_file, target).map_err(|e| Error::io(target, e))?;
+        } else {
+            transfer::replace_with(settings, &staged_file, target)?;
+        }
+        if settings.mode == TransferMode::Move && planned.file.exists() {
+            fs::remove_file(&planned.file).map_err(|e| Error::io(&planned.file, e))?;
+        }
+        return Ok(true);
+    }
+
+    let source_exists = planned.file.exists();
+    let target_exists = target.exists();
+
+    match (source_exists, target_exists) {
+        (true, true) if hash::same_contents(&planned.file, target, settings.partial_hash.0)? => {
+            // A cross-device move interrupted between the copy and removing the original
+            if settings.mode == TransferMode::Move {
+                fs::remove_file(&planned.file).map_err(|e| Error::io(&planned.file, e))?;
+                return Ok(true);
+            }
+            Ok(false)
+        }
+        (true, _) => {
+            // Only the empty placeholder made it, the destination is decided again
+            let placeholder = fs::metadata(target).is_ok_and(|metadata| metadata.len() == 0);
+            if reserved.is_some() && placeholder {
+                fs::remove_file(target).map_err(|e| Error::io(target, e))?;
+            }
+            utils::create_dir(&planned.destination_dir)?;
+            let outcome = transfer::transfer(settings, &planned.file, &planned.destination_file)?;
+            Ok(outcome != Outcome::Skipped)
+        }
+        (false, false) => Err(Error::io(
+            &planned.file,
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "neither the file nor its destination exist anymore",
+            ),
+        )),
+        (false, true) => Ok(false),
+    }
+}
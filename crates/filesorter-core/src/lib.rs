@@ -0,0 +1,70 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! The sorting engine behind filesorter.
+//!
+//! [`settings::Settings`] describes the sources, the destination and the rules files are sorted by.
+//! [`planner::plan`] matches the files of a source against the rules, [`executor::execute`] carries
+//! the planned moves out, and [`sorter::SortEngine`] does both for every source, journaling the moves
+//! so an interrupted run can be resumed.
+//!
+//! Progress is reported through the `log` crate, records about sorted and skipped files use the
+//! [`targets`] below, and as typed [`events`] passed to the callback of [`sorter::SortEngine::run`].
+//!
+//! With the `async` feature, `async_engine::AsyncSortEngine` runs the same engine on tokio, and the `ffi`
+//! feature adds a C API for embedding it, declared in `include/filesorter.h`.
+
+#[macro_use]
+extern crate log;
+
+#[cfg(feature = "async")]
+pub mod async_engine;
+pub mod builder;
+pub mod date;
+pub mod doctor;
+pub mod error;
+pub mod events;
+pub mod executor;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod finder;
+pub mod hardlink;
+pub mod history;
+pub mod hooks;
+pub mod journal;
+pub mod lock;
+pub mod manifest;
+pub mod metrics;
+pub mod migrate;
+pub mod owner;
+pub mod planner;
+pub mod plugins;
+pub mod presets;
+pub mod prune;
+pub mod rules;
+pub mod schedule;
+pub mod settings;
+pub mod size;
+pub mod sorter;
+pub mod stats;
+pub mod template;
+pub mod transfer;
+pub mod validate;
+pub mod verify;
+
+mod hash;
+mod similar;
+mod tags;
+mod utils;
+
+pub use error::{Error, Result};
+pub use settings::Settings;
+
+/// Log targets of records about single files.
+pub mod targets {
+    /// Records about files that were sorted.
+    pub const SORTED: &str = "filesorter::sorted";
+    /// Records about files that were left alone.
+    pub const SKIPPED: &str = "filesorter::skipped";
+}
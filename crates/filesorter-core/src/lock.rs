@@ -0,0 +1,136 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use crate::error::{Error, Result};
+use crate::settings::Settings;
+
+use std::fs::{self, File, TryLockError};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+/// Held for the whole run, so overlapping runs (like a cron job and a manual one) don't move
+/// files from under each other. The lock is released by the OS when the process exits, even
+/// if it crashed, so it never goes stale; the pid in the file is only there for the message.
+pub struct RunLock {
+    _file: File,
+}
+
+impl RunLock {
+    /// Takes the lock, waiting for the other run to finish with `wait`, otherwise failing right away.
+    pub fn acquire(wait: bool) -> Result<Self> {
+        let path = RunLock::get_lock_path();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+        }
+
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|e| Error::io(&path, e))?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) if wait => {
+                info!("Waiting for another filesorter run to finish...");
+                file.lock().map_err(|e| Error::io(&path, e))?;
+            }
+            Err(TryLockError::WouldBlock) => {
+                let pid = fs::read_to_string(&path)
+                    .ok()
+                    .and_then(|pid| pid.trim().parse().ok());
+                return Err(Error::Locked { pid });
+            }
+            Err(TryLockError::Error(e)) => return Err(Error::io(&path, e)),
+        }
+
+        file.set_len(0)
+            .and_then(|()| file.seek(SeekFrom::Start(0)))
+            .and_then(|_| write!(file, "{}", std::process::id()))
+            .map_err(|e| Error::io(&path, e))?;
+
+        Ok(RunLock { _file: file })
+    }
+
+    pub fn get_lock_path() -> PathBuf {
+        Settings::get_runtime_dir().join("filesorter.lock")
+    }
+}
+
+/// The pid of the running `watch`, for service managers and scripts, removed once it stops.
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Fails if the pid file names a process that's still running, another `watch` most likely.
+    /// It may only have been given a reused pid though, `force` lets the file be taken over then.
+    /// The pid file of a process that's gone is stale and replaced. Call before taking the
+    /// [`RunLock`], so a running `watch` is reported as such.
+    pub fn check(force: bool) -> Result<()> {
+        let path = PidFile::get_pid_path();
+        let pid = fs::read_to_string(&path)
+            .ok()
+            .and_then(|pid| pid.trim().parse::<u32>().ok());
+        match pid {
+            Some(pid) if is_running(pid) && !force => Err(Error::Settings(format!(
+                "filesorter watch is already running (pid {}, see {}), use --force if that pid \
+                 belongs to something else now",
+                pid,
+                path.display()
+            ))),
+            Some(pid) if is_running(pid) => {
+                warn!("Taking over the pid file of pid {}", pid);
+                Ok(())
+            }
+            Some(pid) => {
+                info!("Replacing the stale pid file of pid {}", pid);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the pid of this process, once the [`RunLock`] is held.
+    pub fn create(_lock: &RunLock) -> Result<Self> {
+        let path = PidFile::get_pid_path();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+        }
+        fs::write(&path, format!("{}\n", std::process::id())).map_err(|e| Error::io(&path, e))?;
+
+        Ok(PidFile { path })
+    }
+
+    pub fn get_pid_path() -> PathBuf {
+        Settings::get_runtime_dir().join("watch.pid")
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            debug!("Failed to remove pid file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Whether a process with this pid exists, signal 0 only checks for it.
+#[cfg(unix)]
+pub(crate) fn is_running(pid: u32) -> bool {
+    if unsafe { libc::kill(pid as libc::pid_t, 0) } == 0 {
+        return true;
+    }
+    // It exists, under another user
+    std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Windows can't be asked without more bindings, so any pid counts as running and a stale pid
+/// file takes `force`.
+#[cfg(not(unix))]
+pub(crate) fn is_running(_pid: u32) -> bool {
+    true
+}
@@ -0,0 +1,184 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Checksum manifests kept next to sorted files, in the format of `sha256sum` and `b3sum`,
+//! so `sha256sum -c SHA256SUMS` checks a folder.
+
+use crate::error::{Error, Result};
+use crate::transfer;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Serializes manifest updates, files sorted to the same folder finish on different threads.
+static UPDATE: Mutex<()> = Mutex::new(());
+
+/// The hash a manifest holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ManifestFormat {
+    /// `SHA256SUMS`, checked by `sha256sum -c`
+    Sha256,
+    /// `B3SUMS`, checked by `b3sum -c`
+    Blake3,
+}
+
+impl ManifestFormat {
+    pub fn file_name(self) -> &'static str {
+        match self {
+            ManifestFormat::Sha256 => "SHA256SUMS",
+            ManifestFormat::Blake3 => "B3SUMS",
+        }
+    }
+
+    pub fn from_file_name(name: &str) -> Option<Self> {
+        [ManifestFormat::Sha256, ManifestFormat::Blake3]
+            .iter()
+            .copied()
+            .find(|format| format.file_name() == name)
+    }
+
+    /// The hex digest of the file contents.
+    pub fn hash_file(self, path: &Path) -> Result<String> {
+        let mut file = File::open(path).map_err(|e| Error::io(path, e))?;
+
+        match self {
+            ManifestFormat::Sha256 => {
+                let mut hasher = Sha256::new();
+                io::copy(&mut file, &mut hasher).map_err(|e| Error::io(path, e))?;
+                Ok(hasher
+                    .finalize()
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect())
+            }
+            ManifestFormat::Blake3 => {
+                let mut hasher = blake3::Hasher::new();
+                io::copy(&mut file, &mut hasher).map_err(|e| Error::io(path, e))?;
+                Ok(hasher.finalize().to_hex().to_string())
+            }
+        }
+    }
+}
+
+/// A line of a manifest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub hash: String,
+    /// Relative to the manifest's folder
+    pub name: String,
+}
+
+/// The manifest of the folder holding `file`.
+pub fn path_for(format: ManifestFormat, file: &Path) -> PathBuf {
+    file.with_file_name(format.file_name())
+}
+
+/// Hashes a file that was just sorted and records it in its folder's manifest, replacing an older entry
+/// of the same name.
+pub fn record(format: ManifestFormat, file: &Path) -> Result<()> {
+    let name = file
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let hash = format.hash_file(file)?;
+    let manifest = path_for(format, file);
+
+    let _update = UPDATE.lock().unwrap_or_else(|e| e.into_inner());
+    let mut entries = read(&manifest)?;
+    entries.retain(|entry| entry.name != name);
+    entries.push(Entry { hash, name });
+
+    write(&manifest, &entries)
+}
+
+/// The entries of a manifest, none if it doesn't exist.
+pub fn read(manifest: &Path) -> Result<Vec<Entry>> {
+    let contents = match fs::read_to_string(manifest) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(Error::io(manifest, e)),
+    };
+
+    contents
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            parse_line(line).ok_or_else(|| {
+                Error::io(
+                    manifest,
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid manifest line '{}'", line),
+                    ),
+                )
+            })
+        })
+        .collect()
+}
+
+/// Written next to the manifest and renamed over it, so an interrupted run never truncates it.
+fn write(manifest: &Path, entries: &[Entry]) -> Result<()> {
+    let contents: String = entries.iter().map(format_line).collect();
+    let temp_file = transfer::temp_path(manifest);
+
+    fs::write(&temp_file, contents)
+        .and_then(|()| fs::rename(&temp_file, manifest))
+        .map_err(|e| {
+            let _ = fs::remove_file(&temp_file);
+            Error::io(manifest, e)
+        })
+}
+
+/// Like `sha256sum`, names with a newline or backslash are escaped and the line starts with `\`.
+fn format_line(entry: &Entry) -> String {
+    if entry.name.contains(['\n', '\\']) {
+        let name = entry.name.replace('\\', "\\\\").replace('\n', "\\n");
+        format!("\\{}  {}\n", entry.hash, name)
+    } else {
+        format!("{}  {}\n", entry.hash, entry.name)
+    }
+}
+
+fn parse_line(line: &str) -> Option<Entry> {
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(line) => (true, line),
+        None => (false, line),
+    };
+    let (hash, name) = line.split_once(' ')?;
+    // The second separator char is ` ` for text mode and `*` for binary mode
+    let name = name.get(1..)?;
+
+    let name = if escaped {
+        unescape(name)
+    } else {
+        name.to_string()
+    };
+
+    Some(Entry {
+        hash: hash.to_string(),
+        name,
+    })
+}
+
+fn unescape(name: &str) -> String {
+    let mut unescaped = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
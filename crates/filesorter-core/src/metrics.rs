@@ -0,0 +1,200 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use crate::error::Error;
+use crate::executor::Report;
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Counters of everything sorted since filesorter started.
+struct Metrics {
+    sorted_by_rule: Mutex<BTreeMap<String, u64>>,
+    bytes: AtomicU64,
+    errors: AtomicU64,
+    runs: AtomicU64,
+    last_run: AtomicI64,
+    queue_depth: AtomicUsize,
+    last_errors: Mutex<VecDeque<RecentError>>,
+}
+
+/// How many of the latest errors are kept for [`snapshot`].
+const LAST_ERRORS: usize = 10;
+
+/// An error and when it happened, as a Unix timestamp.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecentError {
+    pub time: i64,
+    pub message: String,
+}
+
+/// The counters at one point in time.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub sorted: u64,
+    pub bytes: u64,
+    pub errors: u64,
+    pub runs: u64,
+    /// When the last run finished, as a Unix timestamp, 0 before the first one
+    pub last_run: i64,
+    pub queue_depth: usize,
+    /// Oldest first
+    pub last_errors: Vec<RecentError>,
+}
+
+static METRICS: Metrics = Metrics {
+    sorted_by_rule: Mutex::new(BTreeMap::new()),
+    bytes: AtomicU64::new(0),
+    errors: AtomicU64::new(0),
+    runs: AtomicU64::new(0),
+    last_run: AtomicI64::new(0),
+    queue_depth: AtomicUsize::new(0),
+    last_errors: Mutex::new(VecDeque::new()),
+};
+
+/// Counts a finished run.
+pub fn record_run(report: &Report) {
+    let mut sorted_by_rule = METRICS
+        .sorted_by_rule
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    for (rule, sorted) in &report.sorted_by_rule {
+        *sorted_by_rule.entry(rule.clone()).or_default() += *sorted as u64;
+    }
+
+    METRICS.bytes.fetch_add(report.bytes, Ordering::Relaxed);
+    METRICS
+        .errors
+        .fetch_add(report.failures.len() as u64, Ordering::Relaxed);
+    METRICS.runs.fetch_add(1, Ordering::Relaxed);
+    METRICS
+        .last_run
+        .store(Utc::now().timestamp(), Ordering::Relaxed);
+    for failure in &report.failures {
+        record_error(failure);
+    }
+}
+
+/// Keeps an error among the latest ones, without counting it as a file that failed.
+pub fn record_error(error: &Error) {
+    let mut last_errors = METRICS
+        .last_errors
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    if last_errors.len() == LAST_ERRORS {
+        last_errors.pop_front();
+    }
+    last_errors.push_back(RecentError {
+        time: Utc::now().timestamp(),
+        message: error.to_string(),
+    });
+}
+
+/// Files of the current run still waiting to be sorted.
+pub fn set_queue_depth(depth: usize) {
+    METRICS.queue_depth.store(depth, Ordering::Relaxed);
+}
+
+pub fn decrease_queue_depth() {
+    METRICS.queue_depth.fetch_sub(1, Ordering::Relaxed);
+}
+
+/// The counters as they are now.
+pub fn snapshot() -> Snapshot {
+    Snapshot {
+        sorted: METRICS
+            .sorted_by_rule
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+            .sum(),
+        bytes: METRICS.bytes.load(Ordering::Relaxed),
+        errors: METRICS.errors.load(Ordering::Relaxed),
+        runs: METRICS.runs.load(Ordering::Relaxed),
+        last_run: METRICS.last_run.load(Ordering::Relaxed),
+        queue_depth: METRICS.queue_depth.load(Ordering::Relaxed),
+        last_errors: METRICS
+            .last_errors
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .cloned()
+            .collect(),
+    }
+}
+
+/// The metrics in the Prometheus text format.
+pub fn render() -> String {
+    let mut body = String::new();
+
+    let _ = writeln!(
+        body,
+        "# HELP filesorter_files_sorted_total Files moved or copied, by rule."
+    );
+    let _ = writeln!(body, "# TYPE filesorter_files_sorted_total counter");
+    for (rule, sorted) in METRICS
+        .sorted_by_rule
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+    {
+        let _ = writeln!(
+            body,
+            "filesorter_files_sorted_total{{rule=\"{}\"}} {}",
+            escape_label(rule),
+            sorted
+        );
+    }
+
+    let counters = [
+        (
+            "bytes_moved_total",
+            "counter",
+            "Bytes moved or copied.",
+            METRICS.bytes.load(Ordering::Relaxed) as i64,
+        ),
+        (
+            "errors_total",
+            "counter",
+            "Files that failed to sort.",
+            METRICS.errors.load(Ordering::Relaxed) as i64,
+        ),
+        (
+            "runs_total",
+            "counter",
+            "Finished runs.",
+            METRICS.runs.load(Ordering::Relaxed) as i64,
+        ),
+        (
+            "last_run_timestamp_seconds",
+            "gauge",
+            "When the last run finished.",
+            METRICS.last_run.load(Ordering::Relaxed),
+        ),
+        (
+            "queue_depth",
+            "gauge",
+            "Files of the current run waiting to be sorted.",
+            METRICS.queue_depth.load(Ordering::Relaxed) as i64,
+        ),
+    ];
+    for (name, kind, help, value) in counters {
+        let _ = writeln!(body, "# HELP filesorter_{} {}", name, help);
+        let _ = writeln!(body, "# TYPE filesorter_{} {}", name, kind);
+        let _ = writeln!(body, "filesorter_{} {}", name, value);
+    }
+
+    body
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,59 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use serde_yaml::{Mapping, Value};
+
+/// Version written by this build. Files without a `version` key are version 1.
+pub const CURRENT_VERSION: u32 = 1;
+
+/// Each entry upgrades a document from version `index + 1` to `index + 2`. The format hasn't
+/// changed since versions were added, files from before them are read as they are.
+const MIGRATIONS: &[fn(&mut Mapping)] = &[];
+
+/// Upgrades a raw settings document to `CURRENT_VERSION` in place.
+/// Returns the version the document had before migrating.
+pub fn migrate(value: &mut Value) -> Result<u32, String> {
+    let mapping = match value {
+        Value::Mapping(mapping) => mapping,
+        _ => return Ok(CURRENT_VERSION),
+    };
+
+    let version = match mapping.get(&key("version")) {
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| format!("Invalid settings version {:?}", version))?
+            as u32,
+        None => 1,
+    };
+
+    if version > CURRENT_VERSION {
+        return Err(format!(
+            "Settings file version {} is newer than supported version {}, please update filesorter",
+            version, CURRENT_VERSION
+        ));
+    }
+
+    renamed_keys(mapping);
+    for migration in MIGRATIONS.iter().skip(version.saturating_sub(1) as usize) {
+        migration(mapping);
+    }
+    mapping.insert(key("version"), Value::from(CURRENT_VERSION));
+
+    Ok(version)
+}
+
+fn key(name: &str) -> Value {
+    Value::String(name.to_string())
+}
+
+/// Keys renamed without changing the format, still read under their old name.
+fn renamed_keys(settings: &mut Mapping) {
+    // `idle_seconds` became `min_age`, a duration like `5m`
+    if let Some(idle_seconds) = settings.remove(&key("idle_seconds")) {
+        let seconds = idle_seconds.as_u64().unwrap_or(0);
+        if seconds > 0 && !settings.contains_key(&key("min_age")) {
+            settings.insert(key("min_age"), Value::from(format!("{}s", seconds)));
+        }
+    }
+}
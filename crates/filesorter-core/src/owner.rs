@@ -0,0 +1,127 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Users and groups files can be filtered by, on Unix.
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+/// A user, by name or uid, looked up when the settings are loaded so unknown ones are reported
+/// up front.
+#[derive(Clone, Debug)]
+pub struct User {
+    name: String,
+    pub uid: u32,
+}
+
+/// A group, by name or gid, looked up like [`User`].
+#[derive(Clone, Debug)]
+pub struct Group {
+    name: String,
+    pub gid: u32,
+}
+
+impl User {
+    pub fn new(name: &str) -> Result<Self, String> {
+        let uid = match name.parse() {
+            Ok(uid) => uid,
+            Err(_) => lookup_user(name).ok_or_else(|| format!("unknown user '{}'", name))?,
+        };
+
+        Ok(User {
+            name: name.to_string(),
+            uid,
+        })
+    }
+}
+
+impl Group {
+    pub fn new(name: &str) -> Result<Self, String> {
+        let gid = match name.parse() {
+            Ok(gid) => gid,
+            Err(_) => lookup_group(name).ok_or_else(|| format!("unknown group '{}'", name))?,
+        };
+
+        Ok(Group {
+            name: name.to_string(),
+            gid,
+        })
+    }
+}
+
+#[cfg(unix)]
+fn lookup_user(name: &str) -> Option<u32> {
+    uzers::get_user_by_name(name).map(|user| user.uid())
+}
+
+#[cfg(unix)]
+fn lookup_group(name: &str) -> Option<u32> {
+    uzers::get_group_by_name(name).map(|group| group.gid())
+}
+
+// Files have no owner to compare with, only ids are taken so the settings still load
+#[cfg(not(unix))]
+fn lookup_user(_name: &str) -> Option<u32> {
+    None
+}
+
+#[cfg(not(unix))]
+fn lookup_group(_name: &str) -> Option<u32> {
+    None
+}
+
+/// A name or an id, which YAML reads as a number.
+struct Name(String);
+
+impl<'de> Deserialize<'de> for Name {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Number(u32),
+            Text(String),
+        }
+
+        Ok(Name(match Raw::deserialize(deserializer)? {
+            Raw::Number(id) => id.to_string(),
+            Raw::Text(text) => text,
+        }))
+    }
+}
+
+impl fmt::Display for User {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+impl fmt::Display for Group {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+impl Serialize for User {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.name)
+    }
+}
+
+impl<'de> Deserialize<'de> for User {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        User::new(&Name::deserialize(deserializer)?.0).map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for Group {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.name)
+    }
+}
+
+impl<'de> Deserialize<'de> for Group {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Group::new(&Name::deserialize(deserializer)?.0).map_err(de::Error::custom)
+    }
+}
@@ -0,0 +1,695 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use crate::error::{Error, Result};
+use crate::events::EventSink;
+use crate::finder::FinderTag;
+use crate::plugins::Plugins;
+use crate::rules::{FileInfo, MatchMode, MatchPolicy, NameGlobs};
+use crate::settings::{
+    DirectoryOverride, Settings, SortPattern, Source, DIRECTORY_OVERRIDE_FILE, IGNORE_FILE,
+};
+use crate::similar::SimilarIndex;
+use crate::size::ByteSize;
+use crate::targets;
+use crate::transfer::{CleanupAction, SymlinkPolicy, TEMP_PREFIX};
+use crate::utils;
+
+use ignore::gitignore::Gitignore;
+use log::Level;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// A single file operation decided by the planner and carried out by the executor.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PlannedMove {
+    pub file: PathBuf,
+    pub destination_dir: PathBuf,
+    pub destination_file: PathBuf,
+    /// Destination of the rule that matched, to tell rules apart in reports
+    #[serde(default)]
+    pub rule: String,
+    /// The rule's hooks, or the global ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_hook: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_hook: Option<String>,
+    /// The rule's Finder tags
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub finder_tags: Vec<FinderTag>,
+    /// Set when a cleanup rule matched, the file is deleted or trashed instead and there's no
+    /// destination
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<CleanupAction>,
+}
+
+impl PlannedMove {
+    fn new(
+        settings: &Settings,
+        source: &Source,
+        pattern: &SortPattern,
+        info: &FileInfo,
+        destination: &str,
+    ) -> Result<Self> {
+        let file = info.path;
+        if let Some(action) = pattern.action {
+            return Ok(PlannedMove {
+                file: file.to_path_buf(),
+                destination_dir: PathBuf::new(),
+                destination_file: PathBuf::new(),
+                rule: if pattern.destination.is_empty() {
+                    String::from(CLEANUP_RULE)
+                } else {
+                    pattern.destination.clone()
+                },
+                pre_hook: None,
+                post_hook: None,
+                finder_tags: Vec::new(),
+                action: Some(action),
+            });
+        }
+
+        let captures = pattern.captures(info);
+        let destination_dir = utils::get_destination_dir(
+            settings,
+            source,
+            pattern,
+            file,
+            destination,
+            captures.as_ref(),
+        )?;
+        let file_name = match &pattern.rename {
+            Some(rename) => {
+                utils::expand_destination(settings, source, file, rename, captures.as_ref())?
+            }
+            None => String::new(),
+        };
+        let destination_file = if file_name.is_empty() {
+            destination_dir.join(file.file_name().unwrap())
+        } else {
+            destination_dir.join(file_name)
+        };
+        let destination_file = utils::apply_windows_names(
+            settings,
+            file,
+            settings.destination_for(source),
+            destination_file,
+        )?;
+        utils::check_inside(settings.destination_for(source), file, &destination_file)?;
+        let destination_dir = destination_file
+            .parent()
+            .map_or(destination_dir, Path::to_path_buf);
+
+        Ok(PlannedMove {
+            file: file.to_path_buf(),
+            destination_dir,
+            destination_file,
+            rule: pattern.destination.clone(),
+            pre_hook: pattern
+                .pre_hook
+                .clone()
+                .or_else(|| settings.pre_hook.clone()),
+            post_hook: pattern
+                .post_hook
+                .clone()
+                .or_else(|| settings.post_hook.clone()),
+            finder_tags: pattern.finder_tags.clone(),
+            action: None,
+        })
+    }
+}
+
+/// What reports call the rule of files deleted or trashed by `cleanup` or a cleanup rule without
+/// a destination.
+pub const CLEANUP_RULE: &str = "cleanup";
+
+/// Names of the leftovers sources with `cleanup` get rid of, along with empty files: Office and
+/// LibreOffice lock files, and what Finder and Explorer leave behind.
+pub const LEFTOVER_FILES: &[&str] = &[
+    "~$*",
+    ".~lock.*#",
+    ".DS_Store",
+    "._*",
+    "Thumbs.db",
+    "desktop.ini",
+];
+
+/// The rule behind `cleanup`, checked before all others.
+fn cleanup_pattern(action: CleanupAction) -> &'static SortPattern {
+    static PATTERNS: OnceLock<[SortPattern; 2]> = OnceLock::new();
+    let patterns = PATTERNS.get_or_init(|| {
+        let pattern = |action| SortPattern {
+            globs: NameGlobs::new(LEFTOVER_FILES.iter().map(|name| name.to_string()).collect())
+                .expect("Expected valid leftover globs"),
+            max_size: Some(ByteSize(0)),
+            match_mode: Some(MatchMode::Any),
+            action: Some(action),
+            priority: i32::MAX,
+            destination: String::from(CLEANUP_RULE),
+            ..Default::default()
+        };
+        [
+            pattern(CleanupAction::Delete),
+            pattern(CleanupAction::Trash),
+        ]
+    });
+
+    match action {
+        CleanupAction::Delete => &patterns[0],
+        CleanupAction::Trash => &patterns[1],
+    }
+}
+
+/// What the planner decided for a source, along with the files it couldn't plan.
+#[derive(Debug, Default)]
+pub struct Plan {
+    pub moves: Vec<PlannedMove>,
+    pub failures: Vec<Error>,
+}
+
+pub fn plan(settings: &Settings, source: &Source, plugins: &Plugins, sink: &dyn EventSink) -> Plan {
+    let mut plan = Plan::default();
+
+    let directory_override = match DirectoryOverride::load(&source.path) {
+        Ok(directory_override) => directory_override.unwrap_or_default(),
+        Err(e) => {
+            error!("Failed to read override file, skipping directory: {}", e);
+            plan.failures.push(Error::Settings(e));
+            return plan;
+        }
+    };
+    if directory_override.skip {
+        info!(
+            "Skipping {} as requested by its override file",
+            source.path.display()
+        );
+        return plan;
+    }
+    let patterns = ordered_patterns(settings, source, &directory_override);
+
+    // Destinations are often inside the source, don't sort what was already sorted
+    let mut destinations: Vec<&Path> = vec![settings.destination.as_path()];
+    destinations.extend(
+        settings
+            .sources
+            .iter()
+            .filter_map(|s| s.destination.as_deref()),
+    );
+
+    let ignore = match utils::ignore_matcher(settings, &source.path) {
+        Ok(ignore) => ignore,
+        Err(e) => {
+            error!("Failed to read ignore patterns, skipping directory: {}", e);
+            plan.failures.push(e);
+            return plan;
+        }
+    };
+    let excluded = |path: &Path, is_dir: bool| {
+        if is_own_file(path) {
+            return true;
+        }
+        match exclusion(settings, source, &ignore, path, is_dir) {
+            Some(reason) => {
+                debug!(target: targets::SKIPPED, "{}", reason);
+                true
+            }
+            None => false,
+        }
+    };
+    let depth = settings.depth_for(source);
+    let files = match utils::get_files(&source.path, depth, &destinations, &excluded) {
+        Ok(files) => files,
+        Err(e) => {
+            error!("Failed to read source dir, skipping it: {}", e);
+            if let Some(hint) = utils::privacy_hint(&e) {
+                warn!("{}", hint);
+            }
+            plan.failures.push(e);
+            return plan;
+        }
+    };
+
+    let mut similar = settings.similar_images.as_ref().map(SimilarIndex::new);
+    let mut planned_files = HashSet::new();
+    for file in files {
+        let file = match followed_link(settings, &file) {
+            Ok(file) => file,
+            Err(e) => {
+                error!("Failed to follow symlink {}", e);
+                plan.failures.push(e);
+                continue;
+            }
+        };
+        // A followed link may lead to a file that's in the source as well
+        if !planned_files.insert(file.clone()) {
+            continue;
+        }
+        if file.file_name().is_some() {
+            if let Some(reason) = holdup(settings, &file) {
+                debug!(target: targets::SKIPPED, "{}", reason);
+                continue;
+            }
+
+            match plan_file(settings, source, plugins, &patterns, &file) {
+                Ok(Some(mut planned)) => {
+                    if let Some(similar) = &mut similar {
+                        similar.route(settings, source, &mut planned);
+                    }
+                    sink.on_match(&planned);
+                    trace!(
+                        "Planned {} -> {}",
+                        planned.file.display(),
+                        planned.destination_file.display()
+                    );
+                    plan.moves.push(planned)
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    error!("Failed to plan {}", e);
+                    sink.on_error(&e);
+                    plan.failures.push(e);
+                }
+            }
+        } else {
+            error!("Failed to process {}", file.display());
+        }
+    }
+
+    plan
+}
+
+/// Why scanning leaves out a file or dir of the source, if it does.
+pub(crate) fn exclusion(
+    settings: &Settings,
+    source: &Source,
+    ignore: &Gitignore,
+    path: &Path,
+    is_dir: bool,
+) -> Option<String> {
+    // Leftovers are often hidden, but cleaning them up is the point
+    let is_leftover = || {
+        source.cleanup.is_some()
+            && path.file_name().is_some_and(|name| {
+                cleanup_pattern(CleanupAction::Trash)
+                    .globs
+                    .is_match(&name.to_string_lossy())
+            })
+    };
+
+    if is_skipped_link(settings, path, is_dir) {
+        Some(format!("Skipping symlink {}", path.display()))
+    } else if !settings.include_hidden && utils::is_hidden(path) && !is_leftover() {
+        Some(format!("Ignoring hidden {}", path.display()))
+    } else if settings.exclude.is_match(path) {
+        Some(format!(
+            "Excluding {}, it matches an exclude glob",
+            path.display()
+        ))
+    } else if ignore.matched(path, is_dir).is_ignore() {
+        Some(format!(
+            "Ignoring {}, it matches an ignore pattern",
+            path.display()
+        ))
+    } else {
+        None
+    }
+}
+
+/// Why a scanned file is left where it is for now, whatever the rules say, if it is.
+fn holdup(settings: &Settings, file: &Path) -> Option<String> {
+    if is_incomplete(file) {
+        return Some(format!("Skipping incomplete download {}", file.display()));
+    }
+    if is_recent(settings, file) {
+        return Some(format!(
+            "Skipping {}, it was modified less than {} ago",
+            file.display(),
+            humantime::format_duration(settings.min_age)
+        ));
+    }
+    if let Some(owner) = &settings.owner {
+        if FileInfo::new(file).uid() != Some(owner.uid) {
+            return Some(format!(
+                "Skipping {}, it isn't owned by {}",
+                file.display(),
+                owner
+            ));
+        }
+    }
+
+    outside_size_limits(settings, file).map(|size| {
+        format!(
+            "Skipping {}, its size {} is outside the file size limits",
+            file.display(),
+            size
+        )
+    })
+}
+
+/// How the planner sees a single file, for `filesorter explain`.
+#[derive(Debug)]
+pub struct Explanation {
+    /// Why a run leaves the file alone before looking at the rules, if it does
+    pub skipped: Option<String>,
+    /// The plugin that picked the file, the rules aren't checked then
+    pub plugin: Option<String>,
+    /// The rules in the order they're checked
+    pub rules: Vec<RuleExplanation>,
+    /// What the chosen rule, if any, would do with the file
+    pub planned: Option<PlannedMove>,
+}
+
+/// How a file fared against a single rule.
+#[derive(Debug)]
+pub struct RuleExplanation {
+    pub rule: String,
+    pub matched: bool,
+    /// Each check of the rule described, with whether it passed
+    pub checks: Vec<(String, bool)>,
+}
+
+/// Runs a single file through everything [`plan`] would: the scanning filters, the plugins and the
+/// rules, recording why it ends up where it does.
+pub fn explain(
+    settings: &Settings,
+    source: &Source,
+    plugins: &Plugins,
+    file: &Path,
+) -> Result<Explanation> {
+    let directory_override = DirectoryOverride::load(&source.path)
+        .map_err(Error::Settings)?
+        .unwrap_or_default();
+    let ignore = utils::ignore_matcher(settings, &source.path)?;
+
+    let mut skipped = if directory_override.skip {
+        Some(format!(
+            "Skipping {} as requested by its override file",
+            source.path.display()
+        ))
+    } else if is_own_file(file) {
+        Some(format!("{} is a file of filesorter itself", file.display()))
+    } else {
+        None
+    };
+    let relative = file.strip_prefix(&source.path).unwrap_or(file);
+    let depth = relative.components().count().saturating_sub(1);
+    if skipped.is_none() && depth > settings.depth_for(source) {
+        skipped = Some(format!(
+            "Skipping {}, it's {} dir(s) deep, deeper than scanning goes",
+            file.display(),
+            depth
+        ));
+    }
+    // The dirs on the way from the source are scanned, or not, like the file
+    let mut dirs: Vec<&Path> = file
+        .ancestors()
+        .skip(1)
+        .take_while(|dir| *dir != source.path && dir.starts_with(&source.path))
+        .collect();
+    dirs.reverse();
+    let skipped = skipped
+        .or_else(|| {
+            dirs.iter()
+                .find_map(|dir| exclusion(settings, source, &ignore, dir, true))
+        })
+        .or_else(|| exclusion(settings, source, &ignore, file, false))
+        .or_else(|| holdup(settings, file));
+
+    let info = FileInfo::new(file);
+    let plugin = plugins.find(&info).map(|(plugin, _)| plugin.name.clone());
+    let patterns = ordered_patterns(settings, source, &directory_override);
+    let rules = patterns
+        .iter()
+        .map(|pattern| RuleExplanation {
+            rule: if pattern.destination.is_empty() {
+                String::from(CLEANUP_RULE)
+            } else {
+                pattern.destination.clone()
+            },
+            matched: pattern.matches(&info).is_some(),
+            checks: pattern.explain(&info),
+        })
+        .collect();
+    let planned = plan_file(settings, source, plugins, &patterns, file)?;
+
+    Ok(Explanation {
+        skipped,
+        plugin,
+        rules,
+        planned,
+    })
+}
+
+/// Groups the moves sharing a destination file, even in a different case for filesystems ignoring
+/// it, so they run one after another instead of taking each other's place. Groups and the moves
+/// in them keep plan order.
+pub fn destination_groups(moves: &[&PlannedMove]) -> Vec<Vec<usize>> {
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut by_destination: HashMap<String, usize> = HashMap::new();
+    for (index, planned) in moves.iter().enumerate() {
+        // Files cleaned up have nowhere to go
+        if planned.action.is_some() {
+            groups.push(vec![index]);
+            continue;
+        }
+        let destination = planned.destination_file.to_string_lossy().to_lowercase();
+        match by_destination.entry(destination) {
+            Entry::Occupied(group) => groups[*group.get()].push(index),
+            Entry::Vacant(group) => {
+                group.insert(groups.len());
+                groups.push(vec![index]);
+            }
+        }
+    }
+
+    groups
+}
+
+/// Plans a single file of the source, `None` if no rule matches it. Unlike [`plan`], hidden files,
+/// downloads in progress, recent files, files outside the size limits and those of other owners
+/// aren't left out.
+pub fn plan_one(
+    settings: &Settings,
+    source: &Source,
+    plugins: &Plugins,
+    file: &Path,
+) -> Result<Option<PlannedMove>> {
+    let directory_override = DirectoryOverride::load(&source.path)
+        .map_err(Error::Settings)?
+        .unwrap_or_default();
+    let patterns = ordered_patterns(settings, source, &directory_override);
+
+    plan_file(settings, source, plugins, &patterns, file)
+}
+
+/// The patterns of the source with those of its override file first, by priority.
+fn ordered_patterns<'a>(
+    settings: &'a Settings,
+    source: &'a Source,
+    directory_override: &'a DirectoryOverride,
+) -> Vec<&'a SortPattern> {
+    let mut patterns = settings.patterns_for(source);
+    patterns.splice(0..0, directory_override.sort_patterns.iter());
+    if let Some(action) = source.cleanup {
+        patterns.insert(0, cleanup_pattern(action));
+    }
+    // Stable, so patterns of equal priority keep their order
+    patterns.sort_by_key(|pattern| Reverse(pattern.priority));
+
+    patterns
+}
+
+/// Extensions browsers and download managers give files still being downloaded.
+pub const PARTIAL_DOWNLOAD_EXTENSIONS: &[&str] = &[
+    "part",
+    "partial",
+    "crdownload",
+    "download",
+    "opdownload",
+    "!ut",
+    "!qb",
+];
+
+/// Whether the file is a download in progress: a temporary download file, or the placeholder
+/// some browsers create next to one.
+fn is_incomplete(file: &Path) -> bool {
+    let is_partial = |path: &Path| {
+        path.extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+            .is_some_and(|extension| PARTIAL_DOWNLOAD_EXTENSIONS.contains(&extension.as_str()))
+    };
+    let has_partial_sibling = || {
+        PARTIAL_DOWNLOAD_EXTENSIONS.iter().any(|extension| {
+            let mut sibling = file.as_os_str().to_owned();
+            sibling.push(".");
+            sibling.push(extension);
+            Path::new(&sibling).exists()
+        })
+    };
+
+    is_partial(file) || has_partial_sibling()
+}
+
+/// Whether the file was modified less than `min_age` ago.
+fn is_recent(settings: &Settings, file: &Path) -> bool {
+    !settings.min_age.is_zero() && FileInfo::new(file).age() < settings.min_age
+}
+
+/// Whether the file is one filesorter itself keeps in sources: an override or ignore file, or
+/// a copy in progress.
+fn is_own_file(path: &Path) -> bool {
+    path.file_name()
+        .map(|name| name.to_string_lossy())
+        .is_some_and(|name| {
+            name == DIRECTORY_OVERRIDE_FILE || name == IGNORE_FILE || name.starts_with(TEMP_PREFIX)
+        })
+}
+
+/// Whether a symlink is left out of scanning: any with `skip`, with `move-link` those to dirs, which
+/// are neither descended into nor sorted, and with `follow` those to a dir they're in, not to loop.
+fn is_skipped_link(settings: &Settings, path: &Path, is_dir: bool) -> bool {
+    let is_link = path
+        .symlink_metadata()
+        .is_ok_and(|metadata| metadata.is_symlink());
+    let is_loop = || {
+        let dir = path.parent().and_then(|dir| dir.canonicalize().ok());
+        let target = path.canonicalize().ok();
+        matches!((dir, target), (Some(dir), Some(target)) if dir.starts_with(&target))
+    };
+
+    is_link
+        && match settings.symlinks {
+            SymlinkPolicy::Skip => true,
+            SymlinkPolicy::Follow => is_dir && is_loop(),
+            SymlinkPolicy::MoveLink => is_dir,
+        }
+}
+
+/// The file a symlink points to with `follow`, otherwise the file itself.
+fn followed_link(settings: &Settings, file: &Path) -> Result<PathBuf> {
+    let is_link = file
+        .symlink_metadata()
+        .is_ok_and(|metadata| metadata.is_symlink());
+    if settings.symlinks != SymlinkPolicy::Follow || !is_link {
+        return Ok(file.to_path_buf());
+    }
+
+    file.canonicalize().map_err(|e| Error::io(file, e))
+}
+
+/// The first of the matches that rank highest. When it matched by extension, a later one matching
+/// a longer extension (`tar.gz` over `gz`) wins instead, other rules keep their order.
+fn first_match<'a, 'p>(
+    matches: &'a [(&'p SortPattern, String, usize)],
+) -> Option<&'a (&'p SortPattern, String, usize)> {
+    let rank = |pattern: &SortPattern| (pattern.priority, !pattern.is_catch_all());
+    let top = matches.iter().map(|(pattern, _, _)| rank(pattern)).max()?;
+    let mut candidates = matches
+        .iter()
+        .filter(|(pattern, _, _)| rank(pattern) == top);
+    let first = candidates.next()?;
+    if first.2 == 0 {
+        return Some(first);
+    }
+
+    Some(candidates.fold(first, |chosen, candidate| {
+        if candidate.2 > chosen.2 {
+            candidate
+        } else {
+            chosen
+        }
+    }))
+}
+
+/// The size of the file if it's under `min_file_size` or over `max_file_size`.
+fn outside_size_limits(settings: &Settings, file: &Path) -> Option<ByteSize> {
+    if settings.min_file_size.is_none() && settings.max_file_size.is_none() {
+        return None;
+    }
+    let size = ByteSize(FileInfo::new(file).size());
+    let too_small = settings.min_file_size.is_some_and(|min| size < min);
+    let too_large = settings.max_file_size.is_some_and(|max| size > max);
+
+    (too_small || too_large).then_some(size)
+}
+
+/// Picks the pattern for a file. Plugins are asked first, then higher priority wins, then anything over
+/// a catch-all (`*`) pattern, then the match policy decides.
+fn plan_file(
+    settings: &Settings,
+    source: &Source,
+    plugins: &Plugins,
+    patterns: &[&SortPattern],
+    file: &Path,
+) -> Result<Option<PlannedMove>> {
+    let info = FileInfo::new(file);
+
+    if let Some((plugin, destination)) = plugins.find(&info) {
+        let pattern = SortPattern {
+            destination: format!("plugin:{}", plugin.name),
+            ..Default::default()
+        };
+        return PlannedMove::new(settings, source, &pattern, &info, &destination).map(Some);
+    }
+    let matches: Vec<(&SortPattern, String, usize)> = patterns
+        .iter()
+        .filter_map(|pattern| {
+            pattern.matches(&info).map(|destination| {
+                let extension_len = pattern.extension_match(&info).unwrap_or(0);
+                (*pattern, destination, extension_len)
+            })
+        })
+        .collect();
+
+    // `max_by_key` picks the last of equal elements, iterate backwards to prefer the first one
+    let chosen = match settings.match_policy {
+        MatchPolicy::Best => matches
+            .iter()
+            .rev()
+            .max_by_key(|(pattern, _, extension_len)| {
+                (
+                    pattern.priority,
+                    !pattern.is_catch_all(),
+                    *extension_len,
+                    pattern.specificity(),
+                )
+            }),
+        MatchPolicy::First | MatchPolicy::Warn => first_match(&matches),
+    };
+
+    // A catch-all losing to a specific pattern isn't worth a warning
+    let specific_matches = matches
+        .iter()
+        .filter(|(pattern, _, _)| !pattern.is_catch_all())
+        .count();
+    let overlap_level = if settings.match_policy == MatchPolicy::Warn {
+        Level::Warn
+    } else {
+        Level::Debug
+    };
+    if specific_matches > 1 {
+        log!(
+            overlap_level,
+            "{} matches {} rules ({}), using '{}'",
+            file.display(),
+            matches.len(),
+            matches
+                .iter()
+                .map(|(_, destination, _)| destination.as_str())
+                .collect::<Vec<&str>>()
+                .join(", "),
+            chosen.map(|(_, d, _)| d.as_str()).unwrap_or_default()
+        );
+    }
+
+    chosen
+        .map(|(pattern, destination, _)| {
+            PlannedMove::new(settings, source, pattern, &info, destination)
+        })
+        .transpose()
+}
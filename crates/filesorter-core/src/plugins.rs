@@ -0,0 +1,216 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Matchers compiled to WebAssembly, for classification the sort patterns can't express.
+//!
+//! A plugin is a `.wasm` module exporting its `memory`, `alloc(len: i32) -> i32` and
+//! `match(ptr: i32, len: i32) -> i64`. `match` gets the JSON of a [`FileMetadata`] written to memory
+//! returned by `alloc`, and returns 0 for no match or `ptr << 32 | len` of a UTF-8 destination.
+
+use crate::error::{Error, Result};
+use crate::rules::FileInfo;
+
+use serde::Serialize;
+use std::convert::TryFrom;
+use std::fs;
+use std::path::{Component, Path};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+use wasmi::{Config, Engine, Linker, Memory, Module, Store, TypedFunc};
+
+pub const PLUGIN_EXTENSION: &str = "wasm";
+
+/// Instructions a plugin may run per file, so a stuck one can't hang the run.
+const FUEL: u64 = 100_000_000;
+
+/// What a plugin gets to look at for a single file.
+#[derive(Debug, Serialize)]
+pub struct FileMetadata<'a> {
+    pub path: String,
+    pub name: &'a str,
+    pub extension: Option<&'a str>,
+    pub size: u64,
+    /// Seconds since the Unix epoch
+    pub modified: Option<u64>,
+    pub mime: String,
+}
+
+impl<'a> FileMetadata<'a> {
+    fn new(info: &'a FileInfo) -> Self {
+        FileMetadata {
+            path: info.path.display().to_string(),
+            name: &info.name,
+            extension: info.extension.as_deref(),
+            size: info.size(),
+            modified: info
+                .metadata
+                .as_ref()
+                .and_then(|metadata| metadata.modified().ok())
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|modified| modified.as_secs()),
+            mime: tree_magic::from_filepath(info.path),
+        }
+    }
+}
+
+struct Instance {
+    store: Store<()>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    matcher: TypedFunc<(i32, i32), i64>,
+}
+
+/// A loaded plugin, calls are serialized since the module has a single memory.
+pub struct Plugin {
+    pub name: String,
+    instance: Mutex<Instance>,
+}
+
+impl Plugin {
+    fn load(engine: &Engine, path: &Path) -> std::result::Result<Self, String> {
+        let wasm = fs::read(path).map_err(|e| e.to_string())?;
+        let module = Module::new(engine, &wasm).map_err(|e| e.to_string())?;
+
+        let mut store = Store::new(engine, ());
+        // Plugins get no imports, they can only look at what they're given
+        let instance = Linker::<()>::new(engine)
+            .instantiate(&mut store, &module)
+            .and_then(|instance| instance.start(&mut store))
+            .map_err(|e| e.to_string())?;
+
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or("doesn't export its memory")?;
+        let alloc = instance
+            .get_typed_func(&store, "alloc")
+            .map_err(|e| format!("alloc: {}", e))?;
+        let matcher = instance
+            .get_typed_func(&store, "match")
+            .map_err(|e| format!("match: {}", e))?;
+
+        Ok(Plugin {
+            name: path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            instance: Mutex::new(Instance {
+                store,
+                memory,
+                alloc,
+                matcher,
+            }),
+        })
+    }
+
+    /// The destination the plugin picked, `None` if it didn't match the file.
+    pub fn matches(&self, metadata: &[u8]) -> std::result::Result<Option<String>, String> {
+        let mut instance = self.instance.lock().unwrap_or_else(|e| e.into_inner());
+        let Instance {
+            store,
+            memory,
+            alloc,
+            matcher,
+        } = &mut *instance;
+        store.set_fuel(FUEL).map_err(|e| e.to_string())?;
+
+        let len = i32::try_from(metadata.len()).map_err(|e| e.to_string())?;
+        let ptr = alloc.call(&mut *store, len).map_err(|e| e.to_string())?;
+        memory
+            .write(&mut *store, ptr as u32 as usize, metadata)
+            .map_err(|e| e.to_string())?;
+
+        let result = matcher
+            .call(&mut *store, (ptr, len))
+            .map_err(|e| e.to_string())?;
+        if result == 0 {
+            return Ok(None);
+        }
+
+        let (ptr, len) = ((result as u64 >> 32) as usize, result as u32 as usize);
+        let mut destination = vec![0; len];
+        memory
+            .read(&*store, ptr, &mut destination)
+            .map_err(|e| e.to_string())?;
+
+        String::from_utf8(destination)
+            .map(Some)
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// The plugins of the plugins dir, asked in file name order.
+#[derive(Default)]
+pub struct Plugins(Vec<Plugin>);
+
+impl Plugins {
+    /// Loads every `.wasm` module of the dir, a missing dir has no plugins.
+    /// Plugins that fail to load are logged and left out.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Plugins::default()),
+            Err(e) => return Err(Error::io(dir, e)),
+        };
+        let mut paths: Vec<_> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == PLUGIN_EXTENSION))
+            .collect();
+        paths.sort();
+
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+
+        let mut plugins = Vec::new();
+        for path in paths {
+            match Plugin::load(&engine, &path) {
+                Ok(plugin) => {
+                    debug!("Loaded plugin {}", plugin.name);
+                    plugins.push(plugin);
+                }
+                Err(e) => error!("Failed to load plugin {}: {}", path.display(), e),
+            }
+        }
+
+        Ok(Plugins(plugins))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The first plugin matching the file and its destination. A plugin failing on a file is logged
+    /// and treated as not matching it.
+    pub fn find(&self, info: &FileInfo) -> Option<(&Plugin, String)> {
+        if self.0.is_empty() {
+            return None;
+        }
+
+        let metadata = serde_json::to_vec(&FileMetadata::new(info)).ok()?;
+        self.0.iter().find_map(|plugin| match plugin.matches(&metadata) {
+            Ok(Some(destination)) if is_relative(&destination) => Some((plugin, destination)),
+            Ok(Some(destination)) => {
+                warn!(
+                    "Plugin {} picked '{}' for {}, destinations must stay inside the destination dir",
+                    plugin.name,
+                    destination,
+                    info.path.display()
+                );
+                None
+            }
+            Ok(None) => None,
+            Err(e) => {
+                warn!("Plugin {} failed on {}: {}", plugin.name, info.path.display(), e);
+                None
+            }
+        })
+    }
+}
+
+fn is_relative(destination: &str) -> bool {
+    !destination.is_empty()
+        && Path::new(destination)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
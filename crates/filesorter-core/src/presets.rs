@@ -0,0 +1,235 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Sets of rules `filesorter init --template` starts the settings with.
+
+use crate::rules::{NameGlobs, NameRegex};
+use crate::settings::SortPattern;
+
+use regex::Regex;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Preset {
+    /// A folder per kind of file, what a Downloads folder mostly holds
+    General,
+    /// Camera raws, photos and videos by month, edits and presets
+    Photographer,
+    /// Packages, disk images, code, data and screenshots
+    Developer,
+    /// Only images, videos, audio, documents and archives
+    Minimal,
+    /// Movies, TV shows by season, music by artist and album and books, laid out like media
+    /// servers scan them
+    MediaServer,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 5] = [
+        Preset::General,
+        Preset::Photographer,
+        Preset::Developer,
+        Preset::Minimal,
+        Preset::MediaServer,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::General => "general",
+            Preset::Photographer => "photographer",
+            Preset::Developer => "developer",
+            Preset::Minimal => "minimal",
+            Preset::MediaServer => "media-server",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Preset::ALL
+            .iter()
+            .copied()
+            .find(|preset| preset.name() == name)
+    }
+
+    pub fn sort_patterns(self) -> Vec<SortPattern> {
+        match self {
+            Preset::General => general(),
+            Preset::Photographer => photographer(),
+            Preset::Developer => developer(),
+            Preset::Minimal => minimal(),
+            Preset::MediaServer => media_server(),
+        }
+    }
+}
+
+const ARCHIVES: &[&str] = &["7z", "gz", "rar", "tar", "tgz", "xz", "zip", "zst"];
+const AUDIO: &[&str] = &["flac", "mp3", "ogg", "opus", "wav"];
+const IMAGES: &[&str] = &["gif", "jpeg", "jpg", "png", "tif"];
+const VIDEOS: &[&str] = &["avi", "mkv", "mp4"];
+const DOCUMENTS: &[&str] = &[
+    "csv", "djvu", "doc", "docx", "epub", "odt", "pdf", "ppt", "pptx", "txt",
+];
+const FONTS: &[&str] = &["eot", "ttf", "woff", "woff2"];
+
+fn pattern(extensions: &[&str], destination: &str) -> SortPattern {
+    SortPattern {
+        extensions: extensions
+            .iter()
+            .map(|extension| extension.to_string())
+            .collect(),
+        destination: destination.to_string(),
+        ..Default::default()
+    }
+}
+
+fn globs(globs: &[&str], destination: &str) -> SortPattern {
+    SortPattern {
+        globs: NameGlobs::new(globs.iter().map(|glob| glob.to_string()).collect())
+            .expect("Expected the preset globs to be valid"),
+        destination: destination.to_string(),
+        ..Default::default()
+    }
+}
+
+fn name_regex(regex: &str, destination: &str) -> SortPattern {
+    SortPattern {
+        name_regex: Some(NameRegex(
+            Regex::new(regex).expect("Expected the preset regex to be valid"),
+        )),
+        destination: destination.to_string(),
+        ..Default::default()
+    }
+}
+
+fn general() -> Vec<SortPattern> {
+    vec![
+        pattern(ARCHIVES, "archives"),
+        pattern(AUDIO, "audio"),
+        SortPattern {
+            mime_types: vec![
+                String::from("application/x-pie-executable"),
+                String::from("application/x-sharedlib"),
+            ],
+            ..pattern(&["exe", "bin"], "binary")
+        },
+        pattern(IMAGES, "images"),
+        pattern(VIDEOS, "videos"),
+        pattern(DOCUMENTS, "docs"),
+        // Packages
+        pattern(&["rpm", "spec"], "rpm-packages"),
+        pattern(&["deb"], "debian-packages"),
+        pattern(&["apk", "apkx"], "apks"),
+        // Other
+        pattern(&["torrent"], "torrents"),
+        pattern(&["jar"], "jars"),
+        pattern(&["xml"], "xml"),
+        pattern(&["img"], "raw"),
+        pattern(FONTS, "fonts"),
+        pattern(&["ovpn"], "openvpn-profiles"),
+        pattern(&["pcap"], "captured-packages"),
+        pattern(&["vsix"], "vscode-extensions"),
+    ]
+}
+
+fn photographer() -> Vec<SortPattern> {
+    vec![
+        // Sidecars go with their raws
+        pattern(
+            &[
+                "3fr", "arw", "cr2", "cr3", "crw", "dng", "erf", "iiq", "kdc", "mrw", "nef", "nrw",
+                "orf", "pef", "raf", "rw2", "srw", "x3f", "xmp",
+            ],
+            "raw/{year}/{month}",
+        ),
+        pattern(
+            &[
+                "avif", "heic", "heif", "jpeg", "jpg", "png", "tif", "tiff", "webp",
+            ],
+            "photos/{year}/{month}",
+        ),
+        pattern(
+            &["avi", "m2ts", "m4v", "mkv", "mov", "mp4", "mts"],
+            "videos/{year}/{month}",
+        ),
+        pattern(&["afphoto", "kra", "psb", "psd", "xcf"], "edits"),
+        pattern(&["cube", "dcp", "lrtemplate"], "presets"),
+        pattern(ARCHIVES, "archives"),
+    ]
+}
+
+fn developer() -> Vec<SortPattern> {
+    vec![
+        globs(
+            &[
+                "Screenshot*",
+                "Screen Shot*",
+                "Screencast*",
+                "Screen Recording*",
+            ],
+            "screenshots",
+        ),
+        pattern(ARCHIVES, "archives"),
+        pattern(&["deb"], "packages/deb"),
+        pattern(&["rpm", "srpm"], "packages/rpm"),
+        pattern(&["aab", "apk", "apkx"], "packages/android"),
+        pattern(
+            &["crate", "gem", "jar", "nupkg", "vsix", "whl"],
+            "packages/libraries",
+        ),
+        pattern(
+            &["appimage", "dmg", "exe", "flatpakref", "msi", "pkg"],
+            "installers",
+        ),
+        pattern(
+            &["img", "iso", "ova", "qcow2", "vdi", "vhd", "vhdx", "vmdk"],
+            "disk-images",
+        ),
+        pattern(
+            &[
+                "c", "cpp", "cs", "diff", "go", "h", "hpp", "java", "js", "kt", "patch", "py",
+                "rb", "rs", "sh", "swift", "ts",
+            ],
+            "code",
+        ),
+        pattern(
+            &[
+                "csv", "db", "json", "ndjson", "parquet", "sql", "sqlite", "toml", "xml", "yaml",
+                "yml",
+            ],
+            "data",
+        ),
+        pattern(&["crt", "csr", "key", "ovpn", "pem", "pub"], "keys"),
+        pattern(&["har", "pcap", "pcapng"], "captures"),
+        pattern(&["html", "md", "pdf", "rst", "txt"], "docs"),
+        pattern(FONTS, "fonts"),
+        pattern(IMAGES, "images"),
+    ]
+}
+
+fn minimal() -> Vec<SortPattern> {
+    vec![
+        pattern(IMAGES, "images"),
+        pattern(VIDEOS, "videos"),
+        pattern(AUDIO, "audio"),
+        pattern(DOCUMENTS, "docs"),
+        pattern(ARCHIVES, "archives"),
+    ]
+}
+
+fn media_server() -> Vec<SortPattern> {
+    vec![
+        // Episodes named like `Show.Name.S01E02.1080p.mkv`, with their subtitles
+        name_regex(
+            r"(?i)^(?P<show>.+?)[ ._-]+S(?P<season>\d{1,2})E\d{1,3}.*\.(avi|m4v|mkv|mp4|ts|webm|ass|srt|ssa|sub|vtt)$",
+            "tv/${show}/Season ${season}",
+        ),
+        pattern(&["avi", "m4v", "mkv", "mov", "mp4", "ts", "webm"], "movies"),
+        pattern(&["ass", "idx", "srt", "ssa", "sub", "vtt"], "movies"),
+        pattern(
+            &["aac", "flac", "m4a", "mp3", "ogg", "opus", "wav"],
+            "music/{artist}/{album}",
+        ),
+        pattern(&["m4b"], "audiobooks"),
+        pattern(&["azw3", "cbr", "cbz", "epub", "mobi", "pdf"], "books"),
+        pattern(&["heic", "jpeg", "jpg", "png"], "photos/{year}"),
+    ]
+}
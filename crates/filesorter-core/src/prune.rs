@@ -0,0 +1,159 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Removes the dirs sorting leaves empty in the sources.
+
+use crate::error::{Error, Result};
+use crate::planner::{self, PlannedMove};
+use crate::settings::{DirectoryOverride, Settings, Source};
+use crate::utils;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default)]
+pub struct Pruned {
+    /// Deepest first, or the ones that would be removed with `dry_run`
+    pub removed: Vec<PathBuf>,
+    pub failures: Vec<Error>,
+}
+
+/// Removes the empty dirs of the source down to the depth it's scanned to, deepest first so dirs
+/// holding only empty dirs go too. The source itself, destinations and other sources inside it and
+/// dirs scanning leaves out, like hidden or ignored ones, are kept. With `dry_run` the dirs are
+/// only listed.
+pub fn prune_source(settings: &Settings, source: &Source, dry_run: bool) -> Result<Pruned> {
+    let mut pruned = Pruned::default();
+    let directory_override = DirectoryOverride::load(&source.path)
+        .map_err(Error::Settings)?
+        .unwrap_or_default();
+    if directory_override.skip {
+        return Ok(pruned);
+    }
+
+    let ignore = utils::ignore_matcher(settings, &source.path)?;
+    let kept = kept_dirs(settings);
+    let excluded = |path: &Path| {
+        kept.contains(&path) || planner::exclusion(settings, source, &ignore, path, true).is_some()
+    };
+    prune_dir(
+        &source.path,
+        settings.depth_for(source),
+        &excluded,
+        dry_run,
+        &mut pruned,
+    );
+
+    Ok(pruned)
+}
+
+/// Removes the dirs the moves left empty, from the folder of each moved file up to its source.
+/// Dirs that still hold anything, like files that failed to move, are kept.
+pub fn prune_emptied(settings: &Settings, moves: &[PlannedMove]) -> Pruned {
+    let kept = kept_dirs(settings);
+    let mut dirs: Vec<&Path> = Vec::new();
+    for planned in moves {
+        let source = settings
+            .sources
+            .iter()
+            .filter(|source| planned.file.starts_with(&source.path))
+            .max_by_key(|source| source.path.components().count());
+        if let Some(source) = source {
+            dirs.extend(
+                planned
+                    .file
+                    .ancestors()
+                    .skip(1)
+                    .take_while(|dir| *dir != source.path && dir.starts_with(&source.path)),
+            );
+        }
+    }
+    // Deepest first, a dir may be left empty only once its subdirs are gone
+    dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+    dirs.dedup();
+
+    let mut pruned = Pruned::default();
+    for dir in dirs {
+        if kept.contains(&dir) || !is_empty(dir) {
+            continue;
+        }
+        match fs::remove_dir(dir) {
+            Ok(()) => {
+                info!("Removed empty dir {}", dir.display());
+                pruned.removed.push(dir.to_path_buf());
+            }
+            Err(e) => {
+                error!("Failed to remove empty dir {}: {}", dir.display(), e);
+                pruned.failures.push(Error::io(dir, e));
+            }
+        }
+    }
+
+    pruned
+}
+
+/// Sources and destinations, which are never removed even when they're inside another source.
+fn kept_dirs(settings: &Settings) -> Vec<&Path> {
+    let mut dirs: Vec<&Path> = vec![settings.destination.as_path()];
+    for source in &settings.sources {
+        dirs.push(&source.path);
+        dirs.extend(source.destination.as_deref());
+    }
+    dirs
+}
+
+/// Returns whether the dir is empty once its empty subdirs are removed.
+fn prune_dir(
+    dir: &Path,
+    depth: usize,
+    excluded: &dyn Fn(&Path) -> bool,
+    dry_run: bool,
+    pruned: &mut Pruned,
+) -> bool {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            pruned.failures.push(Error::io(dir, e));
+            return false;
+        }
+    };
+
+    let mut empty = true;
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                pruned.failures.push(Error::io(dir, e));
+                empty = false;
+                continue;
+            }
+        };
+        let path = entry.path();
+        // Symlinks to dirs count as files, they're never followed here
+        let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+        let kept = !is_dir
+            || depth == 0
+            || excluded(&path)
+            || !prune_dir(&path, depth - 1, excluded, dry_run, pruned);
+        if kept {
+            empty = false;
+        } else if dry_run {
+            pruned.removed.push(path);
+        } else {
+            match fs::remove_dir(&path) {
+                Ok(()) => pruned.removed.push(path),
+                Err(e) => {
+                    pruned.failures.push(Error::io(&path, e));
+                    empty = false;
+                }
+            }
+        }
+    }
+
+    empty
+}
+
+fn is_empty(dir: &Path) -> bool {
+    fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_none())
+}
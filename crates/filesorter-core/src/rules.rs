@@ -0,0 +1,644 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use crate::settings::SortPattern;
+use crate::size::ByteSize;
+use crate::utils;
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use regex::{Captures, Regex};
+use rhai::{Dynamic, Engine, Scope, AST};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fs::Metadata;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Extension that turns a pattern into a catch-all for files no other pattern matched.
+pub const WILDCARD_EXTENSION: &str = "*";
+
+/// Regex compiled once when the settings are loaded, so invalid ones are reported up front.
+#[derive(Clone, Debug)]
+pub struct NameRegex(pub Regex);
+
+impl Serialize for NameRegex {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.0.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for NameRegex {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        Regex::new(&pattern).map(NameRegex).map_err(de::Error::custom)
+    }
+}
+
+/// Operations a script may run per file, so a stuck one can't hang the run.
+const SCRIPT_MAX_OPERATIONS: u64 = 1_000_000;
+
+fn script_engine() -> &'static Engine {
+    static ENGINE: OnceLock<Engine> = OnceLock::new();
+    ENGINE.get_or_init(|| {
+        let mut engine = Engine::new();
+        engine.set_max_operations(SCRIPT_MAX_OPERATIONS);
+        engine
+    })
+}
+
+/// What a script made of a file.
+enum Verdict {
+    NoMatch,
+    /// The pattern's own destination
+    Match,
+    Destination(String),
+}
+
+/// A Rhai script deciding on files the rest of the pattern matched, compiled once when
+/// the settings are loaded. It sees `filename`, `size`, `mtime` (seconds since the Unix epoch)
+/// and `mime`, and returns a destination, `true` for the pattern's own or `false`/`()` for no match.
+#[derive(Clone, Debug)]
+pub struct Script {
+    pub source: String,
+    ast: AST,
+}
+
+impl Script {
+    pub fn new(source: String) -> Result<Self, String> {
+        let ast = script_engine()
+            .compile(&source)
+            .map_err(|e| e.to_string())?;
+        Ok(Script { source, ast })
+    }
+
+    fn run(&self, file: &FileInfo) -> Result<Verdict, String> {
+        let mut scope = Scope::new();
+        scope.push("filename", file.name.clone());
+        scope.push("size", file.size() as i64);
+        scope.push(
+            "mtime",
+            file.metadata
+                .as_ref()
+                .and_then(|m| m.modified().ok())
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |modified| modified.as_secs() as i64),
+        );
+        scope.push("mime", tree_magic::from_filepath(file.path));
+
+        let result: Dynamic = script_engine()
+            .eval_ast_with_scope(&mut scope, &self.ast)
+            .map_err(|e| e.to_string())?;
+
+        if result.is_unit() || result.as_bool() == Ok(false) {
+            Ok(Verdict::NoMatch)
+        } else if result.as_bool() == Ok(true) {
+            Ok(Verdict::Match)
+        } else if result.is_string() {
+            Ok(Verdict::Destination(result.into_string()?))
+        } else {
+            Err(format!(
+                "returned a {}, expected a destination, a bool or ()",
+                result.type_name()
+            ))
+        }
+    }
+}
+
+impl Serialize for Script {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for Script {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Script::new(String::deserialize(deserializer)?).map_err(de::Error::custom)
+    }
+}
+
+/// Case-insensitive file name globs, compiled once when the settings are loaded.
+#[derive(Clone, Debug, Default)]
+pub struct NameGlobs {
+    pub patterns: Vec<String>,
+    set: GlobSet,
+}
+
+impl NameGlobs {
+    pub fn new(patterns: Vec<String>) -> Result<Self, globset::Error> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in &patterns {
+            builder.add(GlobBuilder::new(pattern).case_insensitive(true).build()?);
+        }
+
+        Ok(NameGlobs {
+            set: builder.build()?,
+            patterns,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    pub fn is_match(&self, name: &str) -> bool {
+        self.set.is_match(name)
+    }
+}
+
+impl Serialize for NameGlobs {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.patterns.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for NameGlobs {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let patterns = Vec::<String>::deserialize(deserializer)?;
+        NameGlobs::new(patterns).map_err(de::Error::custom)
+    }
+}
+
+/// Globs matched against whole paths, where `*` stays within a dir and `**` crosses them.
+#[derive(Clone, Debug, Default)]
+pub struct PathGlobs {
+    pub patterns: Vec<String>,
+    set: GlobSet,
+}
+
+impl PathGlobs {
+    pub fn new(patterns: Vec<String>) -> Result<Self, globset::Error> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in &patterns {
+            builder.add(GlobBuilder::new(pattern).literal_separator(true).build()?);
+        }
+
+        Ok(PathGlobs {
+            set: builder.build()?,
+            patterns,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    pub fn is_match(&self, path: &Path) -> bool {
+        self.set.is_match(path)
+    }
+}
+
+impl Serialize for PathGlobs {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.patterns.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PathGlobs {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let patterns = Vec::<String>::deserialize(deserializer)?;
+        PathGlobs::new(patterns).map_err(de::Error::custom)
+    }
+}
+
+/// File name exclusions: globs, or regexes when prefixed with `regex:`.
+#[derive(Clone, Debug, Default)]
+pub struct NameExclusions {
+    pub patterns: Vec<String>,
+    globs: NameGlobs,
+    regexes: Vec<Regex>,
+}
+
+impl NameExclusions {
+    pub const REGEX_PREFIX: &'static str = "regex:";
+
+    pub fn new(patterns: Vec<String>) -> Result<Self, String> {
+        let mut globs: Vec<String> = Vec::new();
+        let mut regexes: Vec<Regex> = Vec::new();
+
+        for pattern in &patterns {
+            match pattern.strip_prefix(NameExclusions::REGEX_PREFIX) {
+                Some(regex) => regexes.push(Regex::new(regex).map_err(|e| e.to_string())?),
+                None => globs.push(pattern.clone()),
+            }
+        }
+
+        Ok(NameExclusions {
+            globs: NameGlobs::new(globs).map_err(|e| e.to_string())?,
+            regexes,
+            patterns,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    pub fn is_match(&self, name: &str) -> bool {
+        self.globs.is_match(name) || self.regexes.iter().any(|regex| regex.is_match(name))
+    }
+}
+
+impl Serialize for NameExclusions {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.patterns.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for NameExclusions {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let patterns = Vec::<String>::deserialize(deserializer)?;
+        NameExclusions::new(patterns).map_err(de::Error::custom)
+    }
+}
+
+/// What the rules get to look at for a single file.
+#[derive(Clone, Debug)]
+pub struct FileInfo<'a> {
+    pub path: &'a Path,
+    pub name: String,
+    /// Lowercased, without the dot, only the last one for names like `a.tar.gz`
+    pub extension: Option<String>,
+    name_lowercase: String,
+    pub metadata: Option<Metadata>,
+}
+
+impl<'a> FileInfo<'a> {
+    pub fn new(path: &'a Path) -> Self {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        FileInfo {
+            path,
+            name_lowercase: name.to_lowercase(),
+            name,
+            extension: path
+                .extension()
+                .map(|extension| extension.to_string_lossy().to_lowercase()),
+            metadata: std::fs::metadata(path).ok(),
+        }
+    }
+
+    /// Whether the name ends with the (possibly multi-part, like `tar.gz`) extension.
+    /// The `*` wildcard matches every file.
+    pub fn has_extension(&self, extension: &str) -> bool {
+        if extension == WILDCARD_EXTENSION {
+            return true;
+        }
+
+        let extension = extension.to_lowercase();
+        let name = &self.name_lowercase;
+
+        name.len() > extension.len() + 1
+            && name.ends_with(&extension)
+            && name[..name.len() - extension.len()].ends_with('.')
+    }
+
+    pub fn size(&self) -> u64 {
+        self.metadata.as_ref().map(|m| m.len()).unwrap_or(0)
+    }
+
+    /// The user owning the file, `None` if unknown or not on Unix.
+    #[cfg(unix)]
+    pub fn uid(&self) -> Option<u32> {
+        use std::os::unix::fs::MetadataExt;
+
+        self.metadata.as_ref().map(|m| m.uid())
+    }
+
+    #[cfg(not(unix))]
+    pub fn uid(&self) -> Option<u32> {
+        None
+    }
+
+    /// The group owning the file, like [`FileInfo::uid`].
+    #[cfg(unix)]
+    pub fn gid(&self) -> Option<u32> {
+        use std::os::unix::fs::MetadataExt;
+
+        self.metadata.as_ref().map(|m| m.gid())
+    }
+
+    #[cfg(not(unix))]
+    pub fn gid(&self) -> Option<u32> {
+        None
+    }
+
+    /// Time since the last modification, zero if unknown or in the future.
+    pub fn age(&self) -> Duration {
+        self.metadata
+            .as_ref()
+            .and_then(|m| m.modified().ok())
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .unwrap_or_default()
+    }
+}
+
+/// How the criteria of a pattern are combined.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    /// Every specified criterion must hold
+    All,
+    /// At least one specified criterion must hold
+    Any,
+}
+
+/// What to do when a file matches several patterns.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchPolicy {
+    /// The first matching pattern (by priority, then order) wins
+    #[default]
+    First,
+    /// The most specific matching pattern (by priority, then number of criteria) wins
+    Best,
+    /// Like `first`, but warn about every file matching several patterns
+    Warn,
+}
+
+/// A single thing a pattern can check, present only if the pattern specifies it.
+#[derive(Clone, Copy, Debug)]
+enum Criterion {
+    Extension,
+    MimeType,
+    NameRegex,
+    Glob,
+    MinSize,
+    MaxSize,
+    OlderThan,
+    NewerThan,
+    Owner,
+    Group,
+}
+
+impl SortPattern {
+    /// Returns the destination subdir if the file matches this pattern, its placeholders and
+    /// captures still to be filled in.
+    ///
+    /// Files matching `exclude` are rejected before anything else is checked.
+    /// Without an explicit `match` mode any of the matchers (extensions, MIME types, name regex, globs)
+    /// has to hit, MIME types are only checked for files without an extension, and all
+    /// conditions (size, age, owner) must hold. The `script` has the last word on files that got that far.
+    pub fn matches(&self, file: &FileInfo) -> Option<String> {
+        if self.exclude.is_match(&file.name) {
+            return None;
+        }
+
+        let regex_matched = self
+            .name_regex
+            .as_ref()
+            .is_some_and(|name_regex| name_regex.0.is_match(&file.name));
+
+        let matched = match self.match_mode {
+            None => {
+                self.criteria()
+                    .iter()
+                    .filter(|criterion| criterion.is_condition())
+                    .all(|criterion| self.check(*criterion, file, regex_matched))
+                    && (!self.has_matchers()
+                        || regex_matched
+                        || self.globs.is_match(&file.name)
+                        || self.check(Criterion::Extension, file, regex_matched)
+                        || (file.extension.is_none()
+                            && self.check(Criterion::MimeType, file, regex_matched)))
+            }
+            Some(MatchMode::All) => self
+                .criteria()
+                .iter()
+                .all(|criterion| self.check(*criterion, file, regex_matched)),
+            Some(MatchMode::Any) => {
+                let criteria = self.criteria();
+                criteria.is_empty()
+                    || criteria
+                        .iter()
+                        .any(|criterion| self.check(*criterion, file, regex_matched))
+            }
+        };
+
+        if !matched {
+            return None;
+        }
+
+        if let Some(script) = &self.script {
+            match script.run(file) {
+                Ok(Verdict::Destination(destination)) => return Some(destination),
+                Ok(Verdict::Match) => {}
+                Ok(Verdict::NoMatch) => return None,
+                Err(e) => {
+                    warn!(
+                        "Script of '{}' failed on {}: {}",
+                        self.destination,
+                        file.path.display(),
+                        e
+                    );
+                    return None;
+                }
+            }
+        }
+
+        Some(self.destination.clone())
+    }
+
+    /// Each criterion of the pattern described, with whether the file meets it: `exclude` first,
+    /// then the fields in the order of [`SortPattern::criteria`], then the `script`.
+    pub fn explain(&self, file: &FileInfo) -> Vec<(String, bool)> {
+        let mut checks = Vec::new();
+
+        if !self.exclude.is_empty() {
+            checks.push((
+                format!("exclude {}", self.exclude.patterns.join(", ")),
+                !self.exclude.is_match(&file.name),
+            ));
+        }
+        let regex_matched = self
+            .name_regex
+            .as_ref()
+            .is_some_and(|name_regex| name_regex.0.is_match(&file.name));
+        for criterion in self.criteria() {
+            checks.push((
+                self.describe(criterion),
+                self.check(criterion, file, regex_matched),
+            ));
+        }
+        if let Some(script) = &self.script {
+            let passed = matches!(
+                script.run(file),
+                Ok(Verdict::Match) | Ok(Verdict::Destination(_))
+            );
+            checks.push((String::from("script"), passed));
+        }
+
+        checks
+    }
+
+    fn describe(&self, criterion: Criterion) -> String {
+        match criterion {
+            Criterion::Extension => format!("extensions {}", self.extensions.join(", ")),
+            Criterion::MimeType => format!("mime types {}", self.mime_types.join(", ")),
+            Criterion::NameRegex => match &self.name_regex {
+                Some(name_regex) => format!("name_regex /{}/", name_regex.0),
+                None => String::from("name_regex"),
+            },
+            Criterion::Glob => format!("globs {}", self.globs.patterns.join(", ")),
+            Criterion::MinSize => format!("min_size {}", self.min_size.unwrap_or_default()),
+            Criterion::MaxSize => format!("max_size {}", self.max_size.unwrap_or_default()),
+            Criterion::OlderThan => format!(
+                "older_than {}",
+                humantime::format_duration(self.older_than.unwrap_or_default())
+            ),
+            Criterion::NewerThan => format!(
+                "newer_than {}",
+                humantime::format_duration(self.newer_than.unwrap_or_default())
+            ),
+            Criterion::Owner => match &self.owner {
+                Some(owner) => format!("owner {}", owner),
+                None => String::from("owner"),
+            },
+            Criterion::Group => match &self.group {
+                Some(group) => format!("group {}", group),
+                None => String::from("group"),
+            },
+        }
+    }
+
+    /// The destination the file gets from this pattern with its `name_regex` captures filled in,
+    /// `None` if it doesn't match.
+    pub fn matched_destination(&self, file: &FileInfo) -> Option<String> {
+        let destination = self.matches(file)?;
+        match self.captures(file) {
+            Some(captures) => Some(utils::expand_captures(&destination, &captures)),
+            None => Some(destination),
+        }
+    }
+
+    /// The `name_regex` captures of the file name, filled in the destination and `rename` along
+    /// with the placeholders.
+    pub fn captures<'t>(&self, file: &'t FileInfo) -> Option<Captures<'t>> {
+        self.name_regex
+            .as_ref()
+            .and_then(|name_regex| name_regex.0.captures(&file.name))
+    }
+
+    /// Length of the longest of the pattern's extensions the file has, so that
+    /// `tar.gz` can win over `gz`.
+    pub fn extension_match(&self, file: &FileInfo) -> Option<usize> {
+        self.extensions
+            .iter()
+            .filter(|extension| file.has_extension(extension))
+            .map(|extension| match extension.as_str() {
+                WILDCARD_EXTENSION => 0,
+                extension => extension.len(),
+            })
+            .max()
+    }
+
+    /// Whether the pattern only catches files no other pattern matched.
+    pub fn is_catch_all(&self) -> bool {
+        self.extensions
+            .iter()
+            .any(|extension| extension == WILDCARD_EXTENSION)
+    }
+
+    /// How specific the pattern is, used by the `best` match policy.
+    pub fn specificity(&self) -> usize {
+        self.criteria().len()
+            + usize::from(!self.exclude.is_empty())
+            + usize::from(self.script.is_some())
+    }
+
+    /// Whether the pattern selects files by name or type, rather than only by conditions.
+    pub fn has_matchers(&self) -> bool {
+        self.criteria().iter().any(|criterion| !criterion.is_condition())
+    }
+
+    pub fn has_conditions(&self) -> bool {
+        self.criteria().iter().any(|criterion| criterion.is_condition())
+    }
+
+    fn criteria(&self) -> Vec<Criterion> {
+        let mut criteria: Vec<Criterion> = Vec::new();
+
+        if !self.extensions.is_empty() {
+            criteria.push(Criterion::Extension);
+        }
+        if !self.mime_types.is_empty() {
+            criteria.push(Criterion::MimeType);
+        }
+        if self.name_regex.is_some() {
+            criteria.push(Criterion::NameRegex);
+        }
+        if !self.globs.is_empty() {
+            criteria.push(Criterion::Glob);
+        }
+        if self.min_size.is_some() {
+            criteria.push(Criterion::MinSize);
+        }
+        if self.max_size.is_some() {
+            criteria.push(Criterion::MaxSize);
+        }
+        if self.older_than.is_some() {
+            criteria.push(Criterion::OlderThan);
+        }
+        if self.newer_than.is_some() {
+            criteria.push(Criterion::NewerThan);
+        }
+        if self.owner.is_some() {
+            criteria.push(Criterion::Owner);
+        }
+        if self.group.is_some() {
+            criteria.push(Criterion::Group);
+        }
+
+        criteria
+    }
+
+    fn check(&self, criterion: Criterion, file: &FileInfo, regex_matched: bool) -> bool {
+        match criterion {
+            Criterion::Extension => self.extension_match(file).is_some(),
+            Criterion::MimeType => self
+                .mime_types
+                .iter()
+                .any(|mime_type| tree_magic::match_filepath(mime_type, file.path)),
+            Criterion::NameRegex => regex_matched,
+            Criterion::Glob => self.globs.is_match(&file.name),
+            Criterion::MinSize => self
+                .min_size
+                .is_none_or(|min_size| ByteSize(file.size()) >= min_size),
+            Criterion::MaxSize => self
+                .max_size
+                .is_none_or(|max_size| ByteSize(file.size()) <= max_size),
+            Criterion::OlderThan => self
+                .older_than
+                .is_none_or(|older_than| file.age() >= older_than),
+            Criterion::NewerThan => self
+                .newer_than
+                .is_none_or(|newer_than| file.age() <= newer_than),
+            Criterion::Owner => self
+                .owner
+                .as_ref()
+                .is_none_or(|owner| file.uid() == Some(owner.uid)),
+            Criterion::Group => self
+                .group
+                .as_ref()
+                .is_none_or(|group| file.gid() == Some(group.gid)),
+        }
+    }
+}
+
+impl Criterion {
+    /// Conditions narrow a pattern down, the rest select files by name or type.
+    fn is_condition(self) -> bool {
+        matches!(
+            self,
+            Criterion::MinSize
+                | Criterion::MaxSize
+                | Criterion::OlderThan
+                | Criterion::NewerThan
+                | Criterion::Owner
+                | Criterion::Group
+        )
+    }
+}
@@ -0,0 +1,48 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Cron schedules `watch` sorts on instead of on every change.
+
+use chrono::{DateTime, Local};
+use croner::Cron;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+/// A standard five field cron expression, like `0 3 * * *` for every night at 3, or a nickname
+/// like `@daily`, in local time.
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    expression: String,
+    cron: Cron,
+}
+
+impl Schedule {
+    /// The first time of the schedule after `time`, `None` if there's none left.
+    pub fn next_after(&self, time: &DateTime<Local>) -> Option<DateTime<Local>> {
+        self.cron.find_next_occurrence(time, false).ok()
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+impl Serialize for Schedule {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.expression)
+    }
+}
+
+impl<'de> Deserialize<'de> for Schedule {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let expression = String::deserialize(deserializer)?;
+        let cron = expression
+            .parse::<Cron>()
+            .map_err(|e| de::Error::custom(format!("invalid schedule '{}': {}", expression, e)))?;
+
+        Ok(Schedule { expression, cron })
+    }
+}
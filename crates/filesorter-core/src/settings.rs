@@ -0,0 +1,800 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+use crate::date::{DateSource, DateTimezone};
+use crate::error::{Error, Result};
+use crate::finder::FinderTag;
+use crate::manifest::ManifestFormat;
+use crate::migrate;
+use crate::owner::{Group, User};
+use crate::presets::Preset;
+use crate::rules::{
+    MatchMode, MatchPolicy, NameExclusions, NameGlobs, NameRegex, PathGlobs, Script,
+};
+use crate::schedule::Schedule;
+use crate::size::{ByteSize, SpaceReserve};
+use crate::transfer::{
+    CleanupAction, ConflictPolicy, DedupPolicy, Quarantine, SymlinkPolicy, TransferMode,
+    WindowsNames,
+};
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SortPattern {
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    /// Only checked for files without an extension
+    #[serde(default)]
+    pub mime_types: Vec<String>,
+    /// Matched against the whole file name, capture groups can be used in `destination`
+    /// as `$1` or `${name}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name_regex: Option<NameRegex>,
+    /// File name globs like `Screenshot*.png` or `*.tar.*`, case-insensitive
+    #[serde(default, skip_serializing_if = "NameGlobs::is_empty")]
+    pub globs: NameGlobs,
+    /// Size limits, a pattern with only these (and no matchers above) matches any file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_size: Option<ByteSize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<ByteSize>,
+    /// File age limits based on the modification time, like `30d` or `12h`
+    #[serde(default, with = "humantime_serde", skip_serializing_if = "Option::is_none")]
+    pub older_than: Option<Duration>,
+    #[serde(default, with = "humantime_serde", skip_serializing_if = "Option::is_none")]
+    pub newer_than: Option<Duration>,
+    /// User or group owning the file, by name or id, Unix only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<User>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<Group>,
+    /// `all` or `any`, how the fields above are combined (see `SortPattern::matches`)
+    #[serde(default, rename = "match", skip_serializing_if = "Option::is_none")]
+    pub match_mode: Option<MatchMode>,
+    /// File names this pattern never applies to, globs or `regex:` prefixed regexes
+    #[serde(default, skip_serializing_if = "NameExclusions::is_empty")]
+    pub exclude: NameExclusions,
+    /// Rhai script deciding on the files the fields above matched, see `Script`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script: Option<Script>,
+    /// Delete or trash the matched files instead of filing them, `destination` is left out then
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<CleanupAction>,
+    /// Higher priority patterns are checked first, equal ones keep their order
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub priority: i32,
+    /// Override the global `use_date_pattern` / `date_pattern` for this pattern
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub use_date_pattern: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date_pattern: Option<String>,
+    #[serde(default)]
+    pub destination: String,
+    /// New file name, with the same placeholders and `name_regex` captures as `destination`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rename: Option<String>,
+    /// Finder tags put on the sorted files, like `red` or `Invoices:green`, macOS only
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub finder_tags: Vec<FinderTag>,
+    /// Override the global `pre_hook` / `post_hook` for this pattern
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pre_hook: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_hook: Option<String>,
+}
+
+/// A source directory, optionally with its own destination root and extra rules.
+/// In the settings file it's either a plain path or a mapping with a `path` key.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(from = "SourceEntry", into = "SourceEntry")]
+pub struct Source {
+    pub path: PathBuf,
+    pub destination: Option<PathBuf>,
+    /// Overrides the global `max_depth` for this source
+    pub max_depth: Option<usize>,
+    /// Delete or trash empty files and leftovers like `Thumbs.db` instead of sorting them
+    pub cleanup: Option<CleanupAction>,
+    /// Evaluated before the global `sort_patterns`
+    pub sort_patterns: Vec<SortPattern>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum SourceEntry {
+    Path(PathBuf),
+    Detailed {
+        path: PathBuf,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        destination: Option<PathBuf>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_depth: Option<usize>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cleanup: Option<CleanupAction>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        sort_patterns: Vec<SortPattern>,
+    },
+}
+
+impl From<SourceEntry> for Source {
+    fn from(entry: SourceEntry) -> Self {
+        match entry {
+            SourceEntry::Path(path) => Source::from(path),
+            SourceEntry::Detailed {
+                path,
+                destination,
+                max_depth,
+                cleanup,
+                sort_patterns,
+            } => Source {
+                path,
+                destination,
+                max_depth,
+                cleanup,
+                sort_patterns,
+            },
+        }
+    }
+}
+
+impl From<Source> for SourceEntry {
+    fn from(source: Source) -> Self {
+        if source.destination.is_none()
+            && source.max_depth.is_none()
+            && source.cleanup.is_none()
+            && source.sort_patterns.is_empty()
+        {
+            SourceEntry::Path(source.path)
+        } else {
+            SourceEntry::Detailed {
+                path: source.path,
+                destination: source.destination,
+                max_depth: source.max_depth,
+                cleanup: source.cleanup,
+                sort_patterns: source.sort_patterns,
+            }
+        }
+    }
+}
+
+impl From<PathBuf> for Source {
+    fn from(path: PathBuf) -> Self {
+        Source {
+            path,
+            destination: None,
+            max_depth: None,
+            cleanup: None,
+            sort_patterns: vec![],
+        }
+    }
+}
+
+/// Name of the optional override file that can be dropped inside a source directory.
+pub const DIRECTORY_OVERRIDE_FILE: &str = ".filesorter.yaml";
+
+/// Patterns of files a source never sorts, in gitignore syntax, read from the source root.
+pub const IGNORE_FILE: &str = ".fsignore";
+
+/// Per-directory overrides read from `.filesorter.yaml` while scanning.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DirectoryOverride {
+    /// Leave the whole directory alone
+    pub skip: bool,
+    /// Evaluated before any other rule
+    pub sort_patterns: Vec<SortPattern>,
+}
+
+impl DirectoryOverride {
+    /// Returns `Ok(None)` if the directory has no override file.
+    pub fn load(dir: &Path) -> Result<Option<Self>, String> {
+        let path = dir.join(DIRECTORY_OVERRIDE_FILE);
+
+        match fs::File::open(&path) {
+            Ok(file) => serde_yaml::from_reader(file)
+                .map(Some)
+                .map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+}
+
+/// A file the log is copied to, rotated to `<path>.1`, `<path>.2`... once it reaches `max_size`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LogFile {
+    pub path: PathBuf,
+    #[serde(default = "LogFile::default_max_size")]
+    pub max_size: ByteSize,
+    /// How many rotated files are kept
+    #[serde(default = "LogFile::default_max_files")]
+    pub max_files: usize,
+}
+
+impl LogFile {
+    fn default_max_size() -> ByteSize {
+        ByteSize(10 * 1024 * 1024)
+    }
+
+    fn default_max_files() -> usize {
+        5
+    }
+}
+
+/// Near-duplicate images, told apart by a perceptual hash of what they show rather than their bytes,
+/// so resized or recompressed copies and repeated screenshots are caught.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SimilarImages {
+    /// How many of the 64 bits of the hashes may differ for images to count as alike
+    #[serde(default = "SimilarImages::default_threshold")]
+    pub threshold: u32,
+    /// Folder of the destination root the lookalikes go to for review
+    #[serde(default = "SimilarImages::default_folder")]
+    pub folder: String,
+}
+
+impl SimilarImages {
+    fn default_threshold() -> u32 {
+        5
+    }
+
+    fn default_folder() -> String {
+        String::from("duplicates")
+    }
+}
+
+impl Default for SimilarImages {
+    fn default() -> Self {
+        SimilarImages {
+            threshold: SimilarImages::default_threshold(),
+            folder: SimilarImages::default_folder(),
+        }
+    }
+}
+
+/// Placeholders available in `webhook_body`.
+pub const WEBHOOK_PLACEHOLDERS: &[&str] = &["sorted", "duplicates", "failed", "failures", "summary"];
+
+/// Contents of a file referenced by `include`: either a bare list of sort patterns
+/// or a mapping with a `sort_patterns` key.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+enum IncludeFile {
+    Patterns(Vec<SortPattern>),
+    Settings { sort_patterns: Vec<SortPattern> },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub version: u32,
+    pub sources: Vec<Source>,
+    pub destination: PathBuf,
+    pub use_date_pattern: bool,
+    pub date_pattern: String,
+    /// Also sort files from subdirectories of the sources
+    pub recursive: bool,
+    /// How many levels of subdirectories `recursive` descends into, all of them when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_depth: Option<usize>,
+    /// Keep the subdirectories of recursively found files under the pattern destination
+    pub preserve_structure: bool,
+    /// Files and dirs no source ever sorts, in gitignore syntax relative to each source, on top of
+    /// the `.fsignore` of the source
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ignore: Vec<String>,
+    /// Globs of whole paths no source ever sorts, whatever the rules say, like `**/Private/**`
+    #[serde(skip_serializing_if = "PathGlobs::is_empty")]
+    pub exclude: PathGlobs,
+    /// Files smaller than this are left alone, like the 0-byte placeholders some apps create
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_file_size: Option<ByteSize>,
+    /// Files larger than this are left alone, for those better moved by hand
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_file_size: Option<ByteSize>,
+    /// Also sort hidden files and look in hidden dirs: dotfiles, and on Windows those with the
+    /// hidden attribute
+    pub include_hidden: bool,
+    /// Only sort files owned by this user, by name or uid, Unix only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<User>,
+    /// What to do with symlinks in the sources: `skip`, `follow` or `move-link`
+    pub symlinks: SymlinkPolicy,
+    /// How many times files open in another program are tried again at the end of a run
+    pub locked_retries: u32,
+    /// How long to wait before each of those tries
+    #[serde(with = "humantime_serde")]
+    pub locked_retry_delay: Duration,
+    /// Leave files modified less than this long ago alone, like `5m`, they may still be in use
+    #[serde(with = "humantime_serde")]
+    pub min_age: Duration,
+    /// File timestamp used for dates: `modified`, `created` or `accessed`
+    pub date_source: DateSource,
+    /// Time zone used to format dates: `utc`, `local` or a name like `Australia/Sydney`
+    pub date_timezone: DateTimezone,
+    /// Date photos by their EXIF capture date instead of the modification time
+    pub use_exif_date: bool,
+    /// Regexes with `year`, `month` and `day` groups to take the date from the file name
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub filename_date_regexes: Vec<NameRegex>,
+    /// How a file matching several patterns is resolved
+    pub match_policy: MatchPolicy,
+    /// `move` (default) or `copy` the sorted files
+    pub mode: TransferMode,
+    /// Flush copied files to disk before they're renamed into place
+    pub fsync: bool,
+    /// Compare hashes of copies with the originals before the originals are removed
+    pub verify: bool,
+    /// Also carry extended attributes over to copies (timestamps and permissions always are)
+    pub preserve_xattrs: bool,
+    /// Also carry the SELinux context and POSIX ACLs over to copies, Linux only
+    pub preserve_security: bool,
+    /// Give sorted files the SELinux context the policy has for their new place, like `restorecon`
+    pub selinux_relabel: bool,
+    /// `keep` (default) or `strip` the quarantine attribute macOS puts on downloaded files
+    pub quarantine: Quarantine,
+    /// Space to keep free when copying to a filesystem, like `10 GiB` or `5%`
+    pub min_free_space: SpaceReserve,
+    /// `overwrite` (default), `skip` or `rename` files that already exist at the destination
+    pub conflict_policy: ConflictPolicy,
+    /// `keep` (default), `sanitize` or `fail` destination names Windows doesn't allow, like `aux.log`
+    pub windows_names: WindowsNames,
+    /// `trash` (default), `delete` or `skip` moved files identical to the one at their destination
+    pub dedup: DedupPolicy,
+    /// Bytes hashed at each end of large files before they're hashed whole to tell duplicates apart,
+    /// 0 to always hash whole files
+    pub partial_hash: ByteSize,
+    /// Send images that look like one already at their destination to a review folder
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub similar_images: Option<SimilarImages>,
+    /// Keep a `SHA256SUMS` (`sha256`) or `B3SUMS` (`blake3`) manifest in each folder files are sorted to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manifest: Option<ManifestFormat>,
+    /// Send replaced and duplicate files to the trash instead of deleting them
+    pub trash: bool,
+    /// Record every completed move in the history database, for `filesorter history`
+    pub history: bool,
+    /// Also write the log to this file, rotating it by size
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_file: Option<LogFile>,
+    /// Send the log to the systemd journal instead of the terminal (needs the `journald` feature)
+    pub journald: bool,
+    /// Show a desktop notification when a `watch` run sorted at least this many files, 0 for never
+    pub notify_threshold: usize,
+    /// How long the sources have to be quiet before `watch` sorts what changed, as one batch
+    #[serde(with = "humantime_serde")]
+    pub watch_quiet_period: Duration,
+    /// Cron expression `watch` sorts on instead of whenever something changes, like `0 3 * * *`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<Schedule>,
+    /// Shell command run before each move with `{src}`, `{dest}` and `{rule}` filled in,
+    /// files it fails for stay where they are
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pre_hook: Option<String>,
+    /// Shell command run after each move, with the same placeholders
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_hook: Option<String>,
+    /// Where a JSON report is posted after each run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webhook_url: Option<String>,
+    /// Template of the posted report, see `WEBHOOK_PLACEHOLDERS`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webhook_body: Option<String>,
+    /// Address `watch` serves Prometheus metrics on, like `127.0.0.1:9898`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics_address: Option<SocketAddr>,
+    /// Where `.wasm` matcher plugins are loaded from, `plugins` in the config dir by default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plugins_dir: Option<PathBuf>,
+    pub sort_patterns: Vec<SortPattern>,
+    /// Extra rule files, relative to the settings file, appended to `sort_patterns`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<PathBuf>,
+    /// Patterns loaded from `include`, kept apart so they're never saved back
+    #[serde(skip)]
+    pub included_patterns: Vec<SortPattern>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            version: migrate::CURRENT_VERSION,
+            sources: vec![],
+            destination: PathBuf::new(),
+            use_date_pattern: false,
+            date_pattern: String::new(),
+            recursive: false,
+            max_depth: None,
+            preserve_structure: false,
+            ignore: vec![],
+            exclude: PathGlobs::default(),
+            min_file_size: None,
+            max_file_size: None,
+            include_hidden: false,
+            owner: None,
+            symlinks: SymlinkPolicy::MoveLink,
+            locked_retries: 3,
+            locked_retry_delay: Duration::from_secs(5),
+            min_age: Duration::ZERO,
+            date_source: DateSource::Modified,
+            date_timezone: DateTimezone::Utc,
+            use_exif_date: false,
+            filename_date_regexes: vec![],
+            match_policy: MatchPolicy::First,
+            mode: TransferMode::Move,
+            fsync: false,
+            verify: false,
+            preserve_xattrs: false,
+            preserve_security: false,
+            selinux_relabel: false,
+            quarantine: Quarantine::Keep,
+            min_free_space: SpaceReserve::default(),
+            conflict_policy: ConflictPolicy::Overwrite,
+            windows_names: WindowsNames::Keep,
+            dedup: DedupPolicy::Trash,
+            partial_hash: ByteSize(1024 * 1024),
+            similar_images: None,
+            manifest: None,
+            trash: true,
+            history: true,
+            log_file: None,
+            journald: false,
+            notify_threshold: 0,
+            watch_quiet_period: Duration::from_secs(2),
+            schedule: None,
+            pre_hook: None,
+            post_hook: None,
+            webhook_url: None,
+            webhook_body: None,
+            metrics_address: None,
+            plugins_dir: None,
+            sort_patterns: Preset::General.sort_patterns(),
+            include: vec![],
+            included_patterns: vec![],
+        }
+    }
+}
+
+impl Settings {
+    pub fn load(path: &Path) -> Result<Self> {
+        if let Ok(file) = fs::File::open(path) {
+            match Settings::from_reader(file) {
+                Ok(mut s) => {
+                    s.load_includes(path)?;
+                    return Ok(s);
+                }
+                // Anything but a broken file (like one from a newer version) must not be renamed
+                Err(e @ Error::Settings(_)) => return Err(e),
+                Err(e) => {
+                    warn!("Failed to parse settings file, falling back to defaults: {}", e);
+                    // Rename the corrupted settings file
+                    let new_path = format!("{}.invalid", path.display());
+                    if let Err(err) = std::fs::rename(path, new_path) {
+                        error!("Failed to rename settings file: {}", err);
+                    }
+                }
+            }
+        }
+        // This is reached if either:
+        // - The file can't be opened (presumably it doesn't exist)
+        // - Or there was an error parsing the file
+        Ok(Self::default())
+    }
+
+    /// Parses a settings document, upgrading it from older versions if needed.
+    pub fn from_reader<R: std::io::Read>(reader: R) -> Result<Self> {
+        let mut value: Value = serde_yaml::from_reader(reader)?;
+
+        let version = migrate::migrate(&mut value).map_err(Error::Settings)?;
+        if version < migrate::CURRENT_VERSION {
+            info!(
+                "Upgraded settings from version {} to {}, run `filesorter config migrate` to save the upgrade",
+                version,
+                migrate::CURRENT_VERSION
+            );
+        }
+
+        Ok(serde_yaml::from_value(value)?)
+    }
+
+    /// Resolves an `include` entry relative to the settings file it appears in.
+    pub fn resolve_include(settings_path: &Path, include: &Path) -> PathBuf {
+        match settings_path.parent() {
+            Some(dir) => dir.join(include),
+            None => include.to_path_buf(),
+        }
+    }
+
+    pub fn read_include(path: &Path) -> Result<Vec<SortPattern>, String> {
+        let file = fs::File::open(path).map_err(|e| e.to_string())?;
+
+        match serde_yaml::from_reader(file).map_err(|e| e.to_string())? {
+            IncludeFile::Patterns(patterns) => Ok(patterns),
+            IncludeFile::Settings { sort_patterns } => Ok(sort_patterns),
+        }
+    }
+
+    pub fn load_includes(&mut self, settings_path: &Path) -> Result<&mut Self> {
+        self.included_patterns.clear();
+
+        for include in &self.include {
+            let path = Settings::resolve_include(settings_path, include);
+            let patterns = Settings::read_include(&path).map_err(|e| {
+                Error::Settings(format!(
+                    "Failed to load included file {}: {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+            self.included_patterns.extend(patterns);
+        }
+
+        Ok(self)
+    }
+
+    /// Own patterns followed by the ones loaded from `include`.
+    pub fn all_patterns(&self) -> impl Iterator<Item = &SortPattern> {
+        self.sort_patterns
+            .iter()
+            .chain(self.included_patterns.iter())
+    }
+
+    /// Destination root for the given source, falling back to the global one.
+    pub fn destination_for<'a>(&'a self, source: &'a Source) -> &'a Path {
+        source.destination.as_deref().unwrap_or(&self.destination)
+    }
+
+    /// The source a canonicalized `path` is in, the innermost one if sources are nested, with its
+    /// own path canonicalized too.
+    pub fn source_of(&self, path: &Path) -> Option<Source> {
+        self.sources
+            .iter()
+            .filter_map(|source| {
+                let source_path = source.path.canonicalize().ok()?;
+                path.starts_with(&source_path).then(|| Source {
+                    path: source_path,
+                    ..source.clone()
+                })
+            })
+            .max_by_key(|source| source.path.components().count())
+    }
+
+    /// The destination dirs of the settings, leaving out those inside another one.
+    pub fn destination_roots(&self) -> Vec<PathBuf> {
+        let mut destinations = vec![self.destination.clone()];
+        destinations.extend(self.sources.iter().filter_map(|s| s.destination.clone()));
+
+        let mut roots: Vec<PathBuf> = destinations
+            .iter()
+            .filter(|dir| {
+                !destinations
+                    .iter()
+                    .any(|other| other != *dir && dir.starts_with(other))
+            })
+            .cloned()
+            .collect();
+        roots.sort();
+        roots.dedup();
+        roots
+    }
+
+    /// How many levels of subdirectories of the source are scanned, 0 without `recursive`.
+    pub fn depth_for(&self, source: &Source) -> usize {
+        if !self.recursive {
+            return 0;
+        }
+
+        source
+            .max_depth
+            .or(self.max_depth)
+            .unwrap_or(usize::MAX)
+    }
+
+    /// Rules for the given source: its own ones first, then the global ones.
+    pub fn patterns_for<'a>(&'a self, source: &'a Source) -> Vec<&'a SortPattern> {
+        source
+            .sort_patterns
+            .iter()
+            .chain(self.all_patterns())
+            .collect()
+    }
+
+    /// Layers `FILESORTER_*` environment variables over the loaded settings.
+    /// Only meant for the running process, the result shouldn't be saved back.
+    pub fn apply_env_overrides(&mut self) -> Result<&mut Self> {
+        if let Some(sources) = env::var_os("FILESORTER_SOURCES") {
+            self.sources = env::split_paths(&sources).map(Source::from).collect();
+        }
+        if let Some(destination) = env::var_os("FILESORTER_DESTINATION") {
+            self.destination = PathBuf::from(destination);
+        }
+        if let Ok(use_date_pattern) = env::var("FILESORTER_USE_DATE_PATTERN") {
+            self.use_date_pattern =
+                parse_env_bool("FILESORTER_USE_DATE_PATTERN", &use_date_pattern)?;
+        }
+        if let Ok(date_pattern) = env::var("FILESORTER_DATE_PATTERN") {
+            self.date_pattern = date_pattern;
+        }
+
+        Ok(self)
+    }
+
+    pub fn add_source(&mut self, source: PathBuf) -> &mut Self {
+        self.sources.push(Source::from(source));
+        self
+    }
+
+    pub fn destination(&mut self, destination: PathBuf) -> &mut Self {
+        self.destination = destination;
+        self
+    }
+
+    pub fn use_date_pattern(&mut self, use_date_pattern: bool) -> &mut Self {
+        self.use_date_pattern = use_date_pattern;
+        self
+    }
+
+    pub fn date_pattern(&mut self, date_pattern: String) -> &mut Self {
+        self.date_pattern = date_pattern;
+        self
+    }
+
+    pub fn sort_patterns(&mut self, sort_patterns: Vec<SortPattern>) -> &mut Self {
+        self.sort_patterns = sort_patterns;
+        self
+    }
+
+    pub fn add_extension(&mut self, extension: &str, destination: &str) -> &mut Self {
+        let extension = extension.trim_start_matches('.').to_lowercase();
+
+        match self
+            .sort_patterns
+            .iter_mut()
+            .find(|pattern| pattern.destination == destination)
+        {
+            Some(pattern) => pattern.extensions.push(extension),
+            None => self.sort_patterns.push(SortPattern {
+                extensions: vec![extension],
+                destination: destination.to_string(),
+                ..Default::default()
+            }),
+        }
+
+        self
+    }
+
+    /// Removes the extension from every pattern, dropping patterns left without matchers.
+    /// Returns `false` if no pattern contained it.
+    pub fn remove_extension(&mut self, extension: &str) -> bool {
+        let extension = extension.trim_start_matches('.').to_lowercase();
+        let mut removed = false;
+
+        for pattern in &mut self.sort_patterns {
+            let len = pattern.extensions.len();
+            pattern.extensions.retain(|e| e.to_lowercase() != extension);
+            removed |= pattern.extensions.len() != len;
+        }
+
+        self.sort_patterns
+            .retain(|pattern| pattern.has_matchers() || pattern.has_conditions());
+
+        removed
+    }
+
+    pub fn find_extension(&self, extension: &str) -> Option<&SortPattern> {
+        let extension = extension.trim_start_matches('.').to_lowercase();
+
+        self.all_patterns().find(|pattern| {
+            pattern
+                .extensions
+                .iter()
+                .any(|e| e.to_lowercase() == extension)
+        })
+    }
+
+    pub fn save_to_file_warn(&self, path: &Path) {
+        if let Err(err) = self.save_to_file(path) {
+            panic!("Failed to save settings: {:?}", err);
+        }
+    }
+
+    /// Writes the settings to a temporary file next to `path` and renames it into place,
+    /// so an interrupted save never leaves a truncated settings file behind.
+    pub fn save_to_file(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp_path = PathBuf::from(format!("{}.tmp", path.display()));
+        let mut config_file = fs::File::create(&tmp_path)?;
+
+        let s: &str = &serde_yaml::to_string(self).unwrap();
+        config_file.write_all(s.as_bytes())?;
+        config_file.sync_all()?;
+
+        fs::rename(tmp_path, path)
+    }
+
+    pub fn backup_old_config(&self, settings_file: &Path) -> &Self {
+        let settings_file_old = format!("{}.old", settings_file.display());
+        if settings_file.exists() {
+            match std::fs::rename(settings_file, &settings_file_old) {
+                Ok(_o) => {
+                    info!(
+                        "Moved old settings file to {}",
+                        &settings_file_old
+                    );
+                }
+                Err(e) => panic!("Error {}", e),
+            }
+        }
+
+        self
+    }
+
+    pub fn get_config_dir() -> PathBuf {
+        let proj_dirs = ProjectDirs::from("com", "elxreno", "filesorter")
+            .expect("System's $HOME directory path not found!");
+
+        proj_dirs.config_dir().to_path_buf()
+    }
+
+    /// Where filesorter keeps its own state between runs.
+    pub fn get_data_dir() -> PathBuf {
+        let proj_dirs = ProjectDirs::from("com", "elxreno", "filesorter")
+            .expect("System's $HOME directory path not found!");
+
+        proj_dirs.data_local_dir().to_path_buf()
+    }
+
+    /// Where files that only matter while filesorter runs (like its lock) live,
+    /// the temp dir on platforms without a runtime dir.
+    pub fn get_runtime_dir() -> PathBuf {
+        ProjectDirs::from("com", "elxreno", "filesorter")
+            .and_then(|proj_dirs| proj_dirs.runtime_dir().map(Path::to_path_buf))
+            .unwrap_or_else(|| env::temp_dir().join("filesorter"))
+    }
+
+    pub fn get_plugins_dir(&self) -> PathBuf {
+        self.plugins_dir
+            .clone()
+            .unwrap_or_else(|| Settings::get_config_dir().join("plugins"))
+    }
+
+    pub fn get_settings_path() -> PathBuf {
+        Settings::get_config_dir()
+            .join("settings")
+            .with_extension("yaml")
+    }
+
+    pub fn get_profile_path(profile: &str) -> PathBuf {
+        Settings::get_config_dir()
+            .join("profiles")
+            .join(profile)
+            .with_extension("yaml")
+    }
+}
+
+fn is_zero(value: &i32) -> bool {
+    *value == 0
+}
+
+fn parse_env_bool(name: &str, value: &str) -> Result<bool> {
+    match value.to_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(Error::Settings(format!(
+            "{} should be a boolean, got '{}'",
+            name, value
+        ))),
+    }
+}
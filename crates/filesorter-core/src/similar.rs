@@ -0,0 +1,127 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Near-duplicate images, found by the difference hash (dHash) of what they show.
+
+use crate::error::{Error, Result};
+use crate::planner::PlannedMove;
+use crate::settings::{Settings, SimilarImages, Source};
+
+use image::imageops::FilterType;
+use image::ImageFormat;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The images already at each destination folder and those planned to go there, by their hash.
+pub(crate) struct SimilarIndex<'a> {
+    similar_images: &'a SimilarImages,
+    folders: HashMap<PathBuf, Vec<(u64, PathBuf)>>,
+}
+
+impl<'a> SimilarIndex<'a> {
+    pub(crate) fn new(similar_images: &'a SimilarImages) -> Self {
+        SimilarIndex {
+            similar_images,
+            folders: HashMap::new(),
+        }
+    }
+
+    /// Sends the planned image to the review folder if it looks like one in its destination folder.
+    pub(crate) fn route(
+        &mut self,
+        settings: &Settings,
+        source: &Source,
+        planned: &mut PlannedMove,
+    ) {
+        if !is_image(&planned.file) {
+            return;
+        }
+        let hash = match dhash(&planned.file) {
+            Ok(hash) => hash,
+            Err(e) => {
+                debug!("Failed to hash image {}", e);
+                return;
+            }
+        };
+
+        let threshold = self.similar_images.threshold;
+        let images = self
+            .folders
+            .entry(planned.destination_dir.clone())
+            .or_insert_with_key(|folder| hash_folder(folder));
+        let lookalike = images
+            .iter()
+            .find(|(other, _)| (hash ^ other).count_ones() <= threshold)
+            .map(|(_, path)| path.clone());
+
+        match lookalike {
+            Some(lookalike) => {
+                let review_dir = settings
+                    .destination_for(source)
+                    .join(&self.similar_images.folder);
+                info!(
+                    "{} looks like {}, sending it to {} for review",
+                    planned.file.display(),
+                    lookalike.display(),
+                    review_dir.display()
+                );
+                planned.destination_file = review_dir.join(planned.file.file_name().unwrap());
+                planned.destination_dir = review_dir;
+            }
+            None => images.push((hash, planned.destination_file.clone())),
+        }
+    }
+}
+
+/// Whether the file is of a format [`dhash`] can read, by its extension.
+fn is_image(path: &Path) -> bool {
+    ImageFormat::from_path(path).is_ok_and(|format| format.reading_enabled())
+}
+
+/// The images directly inside `folder`, none if it doesn't exist yet.
+fn hash_folder(folder: &Path) -> Vec<(u64, PathBuf)> {
+    let entries = match fs::read_dir(folder) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && is_image(path))
+        .collect();
+    paths.sort();
+
+    paths
+        .into_iter()
+        .filter_map(|path| match dhash(&path) {
+            Ok(hash) => Some((hash, path)),
+            Err(e) => {
+                debug!("Failed to hash image {}", e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Shrinks the image to 9x8 grays and sets a bit for each pixel brighter than its right neighbour,
+/// so the hash survives resizing, recompression and small edits.
+pub(crate) fn dhash(path: &Path) -> Result<u64> {
+    let image = image::open(path)
+        .map_err(|e| Error::io(path, io::Error::new(io::ErrorKind::InvalidData, e)))?;
+    let pixels = image.resize_exact(9, 8, FilterType::Triangle).into_luma8();
+
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if pixels.get_pixel(x, y)[0] > pixels.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+
+    Ok(hash)
+}
@@ -0,0 +1,167 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+const UNITS: &[(&str, u64)] = &[
+    ("b", 1),
+    ("k", 1 << 10),
+    ("kb", 1_000),
+    ("kib", 1 << 10),
+    ("m", 1 << 20),
+    ("mb", 1_000_000),
+    ("mib", 1 << 20),
+    ("g", 1 << 30),
+    ("gb", 1_000_000_000),
+    ("gib", 1 << 30),
+    ("t", 1 << 40),
+    ("tb", 1_000_000_000_000),
+    ("tib", 1 << 40),
+];
+
+/// A number of bytes, written in settings either as a plain number or with a unit
+/// like `500 KB`, `4GiB` or `1.5G` (single letter units are binary).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(pub u64);
+
+impl FromStr for ByteSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split = s
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+
+        let number: f64 = number
+            .parse()
+            .map_err(|_| format!("Invalid size '{}'", s))?;
+        let unit = unit.trim().to_lowercase();
+        let multiplier = match UNITS.iter().find(|(name, _)| *name == unit) {
+            Some((_, multiplier)) => *multiplier,
+            None if unit.is_empty() => 1,
+            None => return Err(format!("Unknown size unit '{}' in '{}'", unit, s)),
+        };
+
+        Ok(ByteSize((number * multiplier as f64) as u64))
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+        let mut size = self.0 as f64;
+        let mut unit = 0;
+        while size >= 1024.0 && unit < units.len() - 1 {
+            size /= 1024.0;
+            unit += 1;
+        }
+
+        if unit == 0 {
+            write!(f, "{} B", self.0)
+        } else {
+            write!(f, "{:.1} {}", size, units[unit])
+        }
+    }
+}
+
+impl Serialize for ByteSize {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for ByteSize {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Number(u64),
+            Text(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Number(bytes) => Ok(ByteSize(bytes)),
+            Raw::Text(text) => text.parse().map_err(de::Error::custom),
+        }
+    }
+}
+
+/// Space to keep free on a destination filesystem, either a size or a percentage like `5%`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpaceReserve {
+    Bytes(ByteSize),
+    Percent(f64),
+}
+
+impl Default for SpaceReserve {
+    fn default() -> Self {
+        SpaceReserve::Bytes(ByteSize(0))
+    }
+}
+
+impl SpaceReserve {
+    /// The reserve in bytes on a filesystem of `total` bytes.
+    pub fn bytes(&self, total: u64) -> u64 {
+        match self {
+            SpaceReserve::Bytes(bytes) => bytes.0,
+            SpaceReserve::Percent(percent) => (total as f64 * percent / 100.0) as u64,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.bytes(u64::MAX) == 0
+    }
+}
+
+impl FromStr for SpaceReserve {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().strip_suffix('%') {
+            Some(percent) => match percent.trim().parse::<f64>() {
+                Ok(percent) if (0.0..=100.0).contains(&percent) => Ok(SpaceReserve::Percent(percent)),
+                _ => Err(format!("Invalid percentage '{}'", s)),
+            },
+            None => s.parse().map(SpaceReserve::Bytes),
+        }
+    }
+}
+
+impl fmt::Display for SpaceReserve {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpaceReserve::Bytes(bytes) => write!(f, "{}", bytes),
+            SpaceReserve::Percent(percent) => write!(f, "{}%", percent),
+        }
+    }
+}
+
+impl Serialize for SpaceReserve {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            SpaceReserve::Bytes(bytes) => bytes.serialize(serializer),
+            SpaceReserve::Percent(_) => serializer.collect_str(self),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for SpaceReserve {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Number(u64),
+            Text(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Number(bytes) => Ok(SpaceReserve::Bytes(ByteSize(bytes))),
+            Raw::Text(text) => text.parse().map_err(de::Error::custom),
+        }
+    }
+}
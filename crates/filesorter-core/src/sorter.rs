@@ -0,0 +1,318 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use crate::error::{Error, Result};
+use crate::events::EventSink;
+use crate::executor::{self, Report};
+use crate::journal;
+use crate::metrics;
+use crate::planner;
+use crate::plugins::Plugins;
+use crate::prune;
+use crate::settings::{Settings, Source};
+
+use std::path::{Path, PathBuf};
+
+/// Command line options shared by `sort` and `watch`.
+#[derive(Clone, Debug, Default)]
+pub struct SortOptions {
+    /// Parallel jobs, 0 for the number of CPUs
+    pub jobs: usize,
+    pub fail_fast: bool,
+    /// Wait for the run lock instead of exiting
+    pub wait: bool,
+    /// Finish an interrupted run first
+    pub resume: bool,
+    pub no_trash: bool,
+    /// Also sort hidden files
+    pub hidden: bool,
+    /// Remove the source dirs the run leaves empty
+    pub prune_empty: bool,
+    /// Draw a progress bar when stdout is a terminal
+    pub progress: bool,
+    /// Start from the default settings instead of loading the settings file
+    pub no_config: bool,
+    /// Sort these dirs instead of the sources of the settings
+    pub sources: Vec<PathBuf>,
+    /// Sort into this dir instead of the destination of the settings
+    pub destination: Option<PathBuf>,
+}
+
+/// Loads the settings for a run, with environment and command line overrides applied.
+pub fn load_settings(settings_path: &Path, options: &SortOptions) -> Result<Settings> {
+    let mut settings = if options.no_config {
+        Settings::default()
+    } else {
+        Settings::load(settings_path)?
+    };
+    settings.apply_env_overrides()?;
+    if !options.sources.is_empty() {
+        settings.sources = options.sources.iter().cloned().map(Source::from).collect();
+    }
+    if let Some(destination) = &options.destination {
+        settings.destination = destination.clone();
+    }
+    if options.no_trash {
+        settings.trash = false;
+    }
+    if options.hidden {
+        settings.include_hidden = true;
+    }
+
+    Ok(settings)
+}
+
+/// Deals with the journal of an interrupted run: with `resume` it's finished and its failures returned,
+/// otherwise the run can't start until it is.
+pub fn check_journal(settings: &Settings, resume: bool) -> Result<Option<Vec<Error>>> {
+    match (journal::Journal::load()?, resume) {
+        (Some(journal), true) => {
+            let failures = journal::resume(settings, &journal);
+            journal::Journal::remove()?;
+            Ok(Some(failures))
+        }
+        (None, true) => {
+            info!("No interrupted run to resume");
+            Ok(Some(vec![]))
+        }
+        (Some(_), false) => Err(Error::Settings(format!(
+            "A previous run was interrupted (see {}), run `filesorter sort --resume` first",
+            journal::Journal::get_journal_path().display()
+        ))),
+        (None, false) => Ok(None),
+    }
+}
+
+/// Sorts the sources of a [`Settings`], passing what happens to each file to a callback.
+///
+/// ```no_run
+/// use filesorter_core::events::Event;
+/// use filesorter_core::settings::Settings;
+/// use filesorter_core::sorter::SortEngine;
+///
+/// let settings = Settings::load("filesorter.yaml".as_ref())?;
+/// let report = SortEngine::new(settings).run(|event: &Event| {
+///     if let Event::Moved { file, destination } = event {
+///         println!("{} -> {}", file, destination);
+///     }
+/// })?;
+/// println!("{}", report.summary());
+/// # Ok::<(), filesorter_core::Error>(())
+/// ```
+pub struct SortEngine {
+    pub(crate) settings: Settings,
+    pub(crate) options: SortOptions,
+    pub(crate) plugins: Plugins,
+}
+
+impl SortEngine {
+    /// Loads the plugins of the plugins dir along with the settings.
+    pub fn new(settings: Settings) -> Self {
+        let plugins = Plugins::load(&settings.get_plugins_dir()).unwrap_or_else(|e| {
+            error!("Failed to load plugins: {}", e);
+            Plugins::default()
+        });
+
+        SortEngine {
+            settings,
+            options: SortOptions::default(),
+            plugins,
+        }
+    }
+
+    pub fn options(mut self, options: SortOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// Plans and carries out the moves of every source, telling `sink` what happens. Files that fail
+    /// are in the report, errors are the ones that stopped the run.
+    pub fn run(&self, sink: impl EventSink) -> Result<Report> {
+        run(&self.settings, &self.options, &self.plugins, &sink)
+    }
+
+    /// Sorts just these files and dirs instead of the sources, with the rules of the source each
+    /// is in, if any. Dirs are scanned like a source, files are sorted whatever scanning would
+    /// leave out, like hidden or recent files.
+    pub fn run_paths(&self, paths: &[PathBuf], sink: impl EventSink) -> Result<Report> {
+        run_paths(&self.settings, &self.options, &self.plugins, paths, &sink)
+    }
+}
+
+fn run(
+    settings: &Settings,
+    options: &SortOptions,
+    plugins: &Plugins,
+    sink: &dyn EventSink,
+) -> Result<Report> {
+    check_sources(settings)?;
+
+    let mut moves: Vec<planner::PlannedMove> = Vec::new();
+    let mut failures: Vec<Error> = Vec::new();
+
+    for source in &settings.sources {
+        sink.on_scan_start(source);
+        let plan = plan_source(settings, options, plugins, source, sink)?;
+        moves.extend(plan.moves);
+        failures.extend(plan.failures);
+    }
+
+    execute(settings, options, moves, failures, sink)
+}
+
+fn run_paths(
+    settings: &Settings,
+    options: &SortOptions,
+    plugins: &Plugins,
+    paths: &[PathBuf],
+    sink: &dyn EventSink,
+) -> Result<Report> {
+    let mut moves: Vec<planner::PlannedMove> = Vec::new();
+    let mut failures: Vec<Error> = Vec::new();
+
+    for path in paths {
+        let path = path.canonicalize().map_err(|e| Error::io(path, e))?;
+        if path.is_dir() {
+            let source = match settings.source_of(&path) {
+                Some(source) => Source {
+                    path: path.clone(),
+                    ..source
+                },
+                None => Source::from(path.clone()),
+            };
+            sink.on_scan_start(&source);
+            let plan = plan_source(settings, options, plugins, &source, sink)?;
+            moves.extend(plan.moves);
+            failures.extend(plan.failures);
+            continue;
+        }
+
+        let source = settings
+            .source_of(&path)
+            .unwrap_or_else(|| Source::from(path.parent().unwrap_or(&path).to_path_buf()));
+        check_dir("Destination", settings.destination_for(&source))?;
+        match planner::plan_one(settings, &source, plugins, &path) {
+            Ok(Some(planned)) => {
+                sink.on_match(&planned);
+                moves.push(planned);
+            }
+            Ok(None) => info!("No rule matches {}, leaving it", path.display()),
+            Err(e) if options.fail_fast => return Err(Error::Aborted(Box::new(e))),
+            Err(e) => {
+                error!("Failed to plan {}", e);
+                sink.on_error(&e);
+                failures.push(e);
+            }
+        }
+    }
+
+    execute(settings, options, moves, failures, sink)
+}
+
+/// Carries out the planned moves, journaled so an interrupted run can be resumed.
+fn execute(
+    settings: &Settings,
+    options: &SortOptions,
+    moves: Vec<planner::PlannedMove>,
+    failures: Vec<Error>,
+    sink: &dyn EventSink,
+) -> Result<Report> {
+    journal::Journal::new(settings.mode, settings.conflict_policy, &moves).save()?;
+    let result = executor::execute(
+        settings,
+        &moves,
+        options.jobs,
+        options.fail_fast,
+        options.progress,
+        sink,
+    );
+    journal::Journal::remove()?;
+    let result = prune(settings, options, &moves, result);
+
+    finish(result, failures, sink)
+}
+
+pub(crate) fn check_sources(settings: &Settings) -> Result<()> {
+    if settings.sources.is_empty() {
+        return Err(Error::Settings(String::from(
+            "Config file not initialized, you should initialize them! Run `filesorter help init` for help.",
+        )));
+    }
+
+    Ok(())
+}
+
+/// Plans the moves of a source once its dirs are checked, the first failure aborts with `fail_fast`.
+pub(crate) fn plan_source(
+    settings: &Settings,
+    options: &SortOptions,
+    plugins: &Plugins,
+    source: &Source,
+    sink: &dyn EventSink,
+) -> Result<planner::Plan> {
+    check_dir("Source", &source.path)?;
+    check_dir("Destination", settings.destination_for(source))?;
+
+    let mut plan = planner::plan(settings, source, plugins, sink);
+    if options.fail_fast && !plan.failures.is_empty() {
+        return Err(Error::Aborted(Box::new(plan.failures.remove(0))));
+    }
+
+    Ok(plan)
+}
+
+/// Removes the source dirs the moves left empty, with `prune_empty`.
+pub(crate) fn prune(
+    settings: &Settings,
+    options: &SortOptions,
+    moves: &[planner::PlannedMove],
+    result: Result<Report>,
+) -> Result<Report> {
+    let mut report = result?;
+    if options.prune_empty {
+        let mut pruned = prune::prune_emptied(settings, moves);
+        report.pruned += pruned.removed.len();
+        report.failures.append(&mut pruned.failures);
+    }
+
+    Ok(report)
+}
+
+/// Adds the planning failures to the report of the moves and records the run.
+pub(crate) fn finish(
+    result: Result<Report>,
+    mut failures: Vec<Error>,
+    sink: &dyn EventSink,
+) -> Result<Report> {
+    let mut report = result?;
+    failures.append(&mut report.failures);
+    report.failures = failures;
+    metrics::record_run(&report);
+    sink.on_finish(&report);
+
+    Ok(report)
+}
+
+fn check_dir(kind: &str, path: &Path) -> Result<()> {
+    if !path.exists() {
+        return Err(Error::Settings(format!(
+            "{} dir '{}' doesn't exists!",
+            kind,
+            path.display()
+        )));
+    }
+    if !path.is_dir() {
+        return Err(Error::Settings(format!(
+            "{} dir '{}' exists but is not a directory",
+            kind,
+            path.display()
+        )));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,264 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! How many files the destinations hold and how much space they take, by category.
+
+use crate::error::{Error, Result};
+use crate::manifest::ManifestFormat;
+use crate::settings::Settings;
+use crate::utils;
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Name of the category of files not under any rule's destination, right in a destination root.
+pub const UNSORTED: &str = "(root)";
+
+/// A file and its modification time, as a Unix timestamp.
+#[derive(Clone, Debug, Serialize)]
+pub struct DatedFile {
+    pub path: PathBuf,
+    pub modified: i64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CategoryStats {
+    /// The destination of the rule, or the first dir under the destination root for files no rule
+    /// would have put there
+    pub name: String,
+    pub files: u64,
+    pub bytes: u64,
+    pub oldest: Option<DatedFile>,
+    pub newest: Option<DatedFile>,
+    /// Change since the totals were last saved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub growth: Option<Growth>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Growth {
+    pub files: i64,
+    pub bytes: i64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct StatsReport {
+    /// Largest first
+    pub categories: Vec<CategoryStats>,
+    /// When the previous stats were taken, as a Unix timestamp, if they ever were
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous: Option<i64>,
+    #[serde(skip)]
+    pub failures: Vec<Error>,
+}
+
+/// The totals kept between runs of `stats`, to tell how the categories grew.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Snapshot {
+    taken: i64,
+    /// Files and bytes by category
+    categories: BTreeMap<String, (u64, u64)>,
+}
+
+impl StatsReport {
+    pub fn files(&self) -> u64 {
+        self.categories.iter().map(|category| category.files).sum()
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.categories.iter().map(|category| category.bytes).sum()
+    }
+
+    /// Fills in the growth of each category since the totals were last saved, if they ever were.
+    pub fn compare_with_saved(&mut self) -> Result<()> {
+        let previous = match load_snapshot()? {
+            Some(previous) => previous,
+            None => return Ok(()),
+        };
+
+        self.previous = Some(previous.taken);
+        for category in &mut self.categories {
+            let (files, bytes) = previous
+                .categories
+                .get(&category.name)
+                .copied()
+                .unwrap_or_default();
+            category.growth = Some(Growth {
+                files: category.files as i64 - files as i64,
+                bytes: category.bytes as i64 - bytes as i64,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Keeps the totals for [`StatsReport::compare_with_saved`] of a later run.
+    pub fn save(&self) -> Result<()> {
+        let snapshot = Snapshot {
+            taken: chrono::Utc::now().timestamp(),
+            categories: self
+                .categories
+                .iter()
+                .map(|category| (category.name.clone(), (category.files, category.bytes)))
+                .collect(),
+        };
+
+        let path = get_snapshot_path();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+        }
+        let text = serde_yaml::to_string(&snapshot)?;
+        fs::File::create(&path)
+            .and_then(|mut file| file.write_all(text.as_bytes()))
+            .map_err(|e| Error::io(&path, e))
+    }
+}
+
+/// Walks `dirs`, the destination roots, and sums up their files by category. Hidden files and
+/// manifests aren't counted.
+pub fn stats(settings: &Settings, dirs: &[PathBuf]) -> Result<StatsReport> {
+    let rule_dirs = rule_dirs(settings);
+    let mut categories: BTreeMap<String, CategoryStats> = BTreeMap::new();
+    let mut report = StatsReport::default();
+
+    for dir in dirs {
+        let files = utils::get_files(dir, usize::MAX, &[], &|path, _| utils::is_hidden(path))?;
+        for file in files {
+            let is_manifest = file
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(ManifestFormat::from_file_name)
+                .is_some();
+            if is_manifest {
+                continue;
+            }
+
+            let metadata = match file.metadata() {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    report.failures.push(Error::io(&file, e));
+                    continue;
+                }
+            };
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |age| age.as_secs() as i64);
+
+            let name = category_of(&rule_dirs, file.strip_prefix(dir).unwrap_or(&file));
+            let category = categories
+                .entry(name.clone())
+                .or_insert_with(|| CategoryStats {
+                    name,
+                    files: 0,
+                    bytes: 0,
+                    oldest: None,
+                    newest: None,
+                    growth: None,
+                });
+            category.files += 1;
+            category.bytes += metadata.len();
+            if category
+                .oldest
+                .as_ref()
+                .is_none_or(|oldest| modified < oldest.modified)
+            {
+                category.oldest = Some(DatedFile {
+                    path: file.clone(),
+                    modified,
+                });
+            }
+            if category
+                .newest
+                .as_ref()
+                .is_none_or(|newest| modified > newest.modified)
+            {
+                category.newest = Some(DatedFile {
+                    path: file,
+                    modified,
+                });
+            }
+        }
+    }
+
+    report.categories = categories.into_values().collect();
+    report
+        .categories
+        .sort_by_key(|category| std::cmp::Reverse(category.bytes));
+
+    Ok(report)
+}
+
+/// The dirs rules sort into, as components, up to the first placeholder.
+fn rule_dirs(settings: &Settings) -> Vec<(String, Vec<String>)> {
+    let patterns = settings
+        .sources
+        .iter()
+        .flat_map(|source| source.sort_patterns.iter())
+        .chain(settings.sort_patterns.iter());
+
+    let mut dirs: Vec<(String, Vec<String>)> = Vec::new();
+    for pattern in patterns {
+        let fixed = pattern
+            .destination
+            .split(['$', '{'])
+            .next()
+            .unwrap_or_default();
+        let components: Vec<String> = Path::new(fixed)
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+                _ => None,
+            })
+            .collect();
+        if !components.is_empty() && !dirs.iter().any(|(_, dir)| *dir == components) {
+            dirs.push((components.join("/"), components));
+        }
+    }
+    // The longest first, so `photos/raw` wins over `photos`
+    dirs.sort_by_key(|(_, components)| std::cmp::Reverse(components.len()));
+    dirs
+}
+
+/// The rule dir anywhere in the path of the file, as date folders or a kept source structure can
+/// come before or after it, otherwise the dir right under the root.
+fn category_of(rule_dirs: &[(String, Vec<String>)], relative: &Path) -> String {
+    let dirs: Vec<String> = relative
+        .parent()
+        .map(|parent| {
+            parent
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    rule_dirs
+        .iter()
+        .find(|(_, components)| {
+            dirs.windows(components.len())
+                .any(|window| window == components.as_slice())
+        })
+        .map(|(name, _)| name.clone())
+        .or_else(|| dirs.first().cloned())
+        .unwrap_or_else(|| String::from(UNSORTED))
+}
+
+fn load_snapshot() -> Result<Option<Snapshot>> {
+    let path = get_snapshot_path();
+    match fs::File::open(&path) {
+        Ok(file) => Ok(Some(serde_yaml::from_reader(file)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Error::io(&path, e)),
+    }
+}
+
+fn get_snapshot_path() -> PathBuf {
+    Settings::get_data_dir().join("stats.yaml")
+}
@@ -0,0 +1,69 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use id3::TagLike;
+use std::fs::File;
+use std::path::Path;
+
+/// Artist and album from the embedded tags of an audio file.
+#[derive(Clone, Debug, Default)]
+pub struct AudioTags {
+    pub artist: Option<String>,
+    pub album: Option<String>,
+}
+
+/// Reads ID3 tags from MP3s and Vorbis comments from FLAC and Ogg Vorbis files.
+pub fn read_audio_tags(file: &Path) -> AudioTags {
+    let extension = file
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    let tags = match extension.as_str() {
+        "mp3" => read_id3(file),
+        "flac" => read_flac(file),
+        "ogg" | "oga" => read_ogg(file),
+        _ => None,
+    };
+
+    tags.unwrap_or_default()
+}
+
+fn read_id3(file: &Path) -> Option<AudioTags> {
+    let tag = id3::Tag::read_from_path(file).ok()?;
+
+    Some(AudioTags {
+        artist: tag
+            .album_artist()
+            .or_else(|| tag.artist())
+            .map(String::from),
+        album: tag.album().map(String::from),
+    })
+}
+
+fn read_flac(file: &Path) -> Option<AudioTags> {
+    let reader = claxon::FlacReader::open(file).ok()?;
+    let tag = |name| reader.get_tag(name).next().map(String::from);
+
+    Some(AudioTags {
+        artist: tag("ALBUMARTIST").or_else(|| tag("ARTIST")),
+        album: tag("ALBUM"),
+    })
+}
+
+fn read_ogg(file: &Path) -> Option<AudioTags> {
+    let reader = lewton::inside_ogg::OggStreamReader::new(File::open(file).ok()?).ok()?;
+    let comments = &reader.comment_hdr.comment_list;
+    let tag = |name: &str| {
+        comments
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.clone())
+    };
+
+    Some(AudioTags {
+        artist: tag("ALBUMARTIST").or_else(|| tag("ARTIST")),
+        album: tag("ALBUM"),
+    })
+}
@@ -0,0 +1,42 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+/// Replaces `{name}` placeholders using `resolve`. Unknown placeholders are kept as is
+/// and `{{` / `}}` produce literal braces.
+pub fn expand<F>(template: &str, mut resolve: F) -> String
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find(['{', '}']) {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if rest.starts_with("{{") || rest.starts_with("}}") {
+            result.push_str(&rest[..1]);
+            rest = &rest[2..];
+            continue;
+        }
+
+        match (rest.starts_with('{'), rest.find('}')) {
+            (true, Some(end)) => {
+                let name = &rest[1..end];
+                match resolve(name) {
+                    Some(value) => result.push_str(&value),
+                    None => result.push_str(&rest[..=end]),
+                }
+                rest = &rest[end + 1..];
+            }
+            _ => {
+                result.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+
+    result
+}
@@ -0,0 +1,845 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use crate::error::{Error, Result};
+use crate::hash;
+use crate::journal;
+use crate::settings::Settings;
+use crate::size::ByteSize;
+
+use filetime::FileTime;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Prefix of the temporary files copies are written to, hidden so they're never sorted.
+pub const TEMP_PREFIX: &str = ".filesorter.tmp.";
+
+/// What happens to the sorted files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferMode {
+    /// Move files, copying and deleting them when the destination is on another filesystem
+    #[default]
+    Move,
+    /// Copy files, leaving the originals in place
+    Copy,
+}
+
+/// What happens when a file with the same name already exists at the destination.
+/// Identical files are never duplicated, whatever the policy: `dedup` decides what becomes of a moved one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Replace the existing file, sending it to the trash
+    #[default]
+    Overwrite,
+    /// Leave both files where they are
+    Skip,
+    /// Keep both, numbering the new one like `name (1).ext`
+    Rename,
+}
+
+/// What happens to files whose names Windows doesn't allow at the destination, like `aux.log` or
+/// `report.`, which break on NTFS and SMB shares.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WindowsNames {
+    /// Leave the names as they are
+    #[default]
+    Keep,
+    /// Replace the characters Windows doesn't allow with `_`, drop trailing dots and spaces and
+    /// add `_` to reserved names, like `aux_.log`
+    Sanitize,
+    /// Leave such files where they are and report them as failed
+    Fail,
+}
+
+/// What becomes of the `com.apple.quarantine` attribute macOS puts on downloaded files, which has
+/// Gatekeeper check them when they're first opened.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Quarantine {
+    /// Keep it, also on copies, which drop the other attributes without `preserve_xattrs`
+    #[default]
+    Keep,
+    /// Remove it from sorted files, moved ones included
+    Strip,
+}
+
+/// What happens to symlinks found in the sources.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SymlinkPolicy {
+    /// Leave links to files and dirs alone
+    Skip,
+    /// Sort the files links point to, and look in linked dirs
+    Follow,
+    /// Sort links to files as they are, still pointing at the same file from their new place
+    #[default]
+    MoveLink,
+}
+
+/// What a cleanup rule does with the files it matches instead of filing them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CleanupAction {
+    Delete,
+    /// Send the file to the trash, or delete it with `trash: false`
+    Trash,
+}
+
+/// What happens to a moved file identical to the one already at its destination.
+/// Copied files are always left alone.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DedupPolicy {
+    /// Leave the file where it is
+    Skip,
+    /// Delete the file
+    Delete,
+    /// Send the file to the trash, or delete it with `trash: false`
+    #[default]
+    Trash,
+}
+
+impl DedupPolicy {
+    /// Whether duplicates are removed from the source in this mode.
+    pub fn removes(self, mode: TransferMode) -> bool {
+        mode == TransferMode::Move && self != DedupPolicy::Skip
+    }
+}
+
+/// How a transfer ended.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The file was moved or copied, to this path
+    Transferred(PathBuf),
+    /// The destination exists and `conflict_policy` is `skip`
+    Skipped,
+    /// The destination has the same contents, the file was dealt with by `dedup`
+    Duplicate,
+    /// A cleanup rule deleted or trashed the file
+    CleanedUp,
+}
+
+/// How a transferred file takes its place at the destination.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Placement {
+    /// Nothing is there, and nothing that shows up meanwhile is replaced
+    New,
+    /// The file there is replaced, and only discarded once the new one is next to it, so a failed
+    /// transfer keeps it
+    Replace,
+    /// An empty file reserves the name for the new one, see [`reserve_free_path`]
+    Reserved,
+}
+
+/// Times a transfer is decided again when the destination is taken while it's under way.
+const TAKEN_RETRIES: usize = 3;
+
+/// Moves or copies `file` to `destination_file` according to the settings,
+/// resolving an existing destination with `conflict_policy`. A file showing up at the
+/// destination meanwhile, from another program or another move, is never replaced unless the
+/// policy says so.
+pub fn transfer(settings: &Settings, file: &Path, destination_file: &Path) -> Result<Outcome> {
+    for _ in 0..TAKEN_RETRIES {
+        if let Some(outcome) = try_transfer(settings, file, destination_file)? {
+            return Ok(outcome);
+        }
+    }
+
+    Err(Error::io(
+        destination_file,
+        io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "kept being taken while the file was transferred",
+        ),
+    ))
+}
+
+/// A single go at [`transfer`], `None` if the destination was taken in the meantime.
+fn try_transfer(
+    settings: &Settings,
+    file: &Path,
+    destination_file: &Path,
+) -> Result<Option<Outcome>> {
+    let mut destination_file = destination_file.to_path_buf();
+    let mut placement = Placement::New;
+
+    if destination_file.exists() {
+        if hash::same_contents(file, &destination_file, settings.partial_hash.0)? {
+            if settings.mode == TransferMode::Move {
+                match settings.dedup {
+                    DedupPolicy::Skip => {}
+                    DedupPolicy::Delete => fs::remove_file(file).map_err(|e| Error::io(file, e))?,
+                    DedupPolicy::Trash => discard(settings, file)?,
+                }
+            }
+            return Ok(Some(Outcome::Duplicate));
+        }
+
+        match settings.conflict_policy {
+            ConflictPolicy::Overwrite => placement = Placement::Replace,
+            ConflictPolicy::Skip => return Ok(Some(Outcome::Skipped)),
+            ConflictPolicy::Rename => {
+                destination_file = reserve_free_path(&destination_file)?;
+                placement = Placement::Reserved;
+                if let Err(e) = journal::record_target(file, &destination_file) {
+                    let _ = fs::remove_file(&destination_file);
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    let is_link = fs::symlink_metadata(file)
+        .map_err(|e| Error::io(file, e))?
+        .file_type()
+        .is_symlink();
+    // A file already there is replaced by renaming the new one over it from next to it
+    let target = if placement == Placement::New {
+        destination_file.clone()
+    } else {
+        let target = staged_path(&destination_file);
+        // Left by an interrupted run
+        let _ = fs::remove_file(&target);
+        target
+    };
+    let mut remove_source = false;
+    let placed = match settings.mode {
+        TransferMode::Copy if is_link => copy_link(file, &target),
+        TransferMode::Move if is_link => copy_link(file, &target).map(|()| remove_source = true),
+        TransferMode::Copy => copy_atomic(settings, file, &target),
+        TransferMode::Move => match rename_new(file, &target) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                copy_atomic(settings, file, &target).map(|()| remove_source = true)
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                Err(Error::io(&destination_file, e))
+            }
+            Err(e) => Err(Error::io(file, e)),
+        },
+    };
+    if let Err(e) = placed {
+        if placement == Placement::Reserved {
+            let _ = fs::remove_file(&destination_file);
+        }
+        return match e {
+            Error::Io { source, .. }
+                if placement == Placement::New && source.kind() == io::ErrorKind::AlreadyExists =>
+            {
+                Ok(None)
+            }
+            e => Err(e),
+        };
+    }
+
+    let finished = match placement {
+        Placement::New => Ok(()),
+        Placement::Replace => replace_with(settings, &target, &destination_file),
+        Placement::Reserved => {
+            fs::rename(&target, &destination_file).map_err(|e| Error::io(&destination_file, e))
+        }
+    };
+    if let Err(e) = finished {
+        if settings.mode == TransferMode::Move && !remove_source {
+            let _ = fs::rename(&target, file);
+        } else {
+            let _ = fs::remove_file(&target);
+        }
+        if placement == Placement::Reserved {
+            let _ = fs::remove_file(&destination_file);
+        }
+        return Err(e);
+    }
+    if remove_source {
+        fs::remove_file(file).map_err(|e| Error::io(file, e))?;
+    }
+    if settings.quarantine == Quarantine::Strip && !is_link {
+        // The file is sorted already, an attribute left on it doesn't undo that
+        if let Err(e) = strip_quarantine(&destination_file) {
+            warn!("Failed to strip the quarantine attribute {}", e);
+        }
+    }
+    if settings.selinux_relabel {
+        if let Err(e) = relabel(&destination_file) {
+            warn!("Failed to relabel {}", e);
+        }
+    }
+
+    Ok(Some(Outcome::Transferred(destination_file)))
+}
+
+/// Renames `from` to `to` unless something is there already, failing with `AlreadyExists` then
+/// where `fs::rename` would replace it.
+fn rename_new(from: &Path, to: &Path) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    match rename_noreplace(from, to) {
+        // Filesystems and kernels that can't do it
+        Err(e) if matches!(e.raw_os_error(), Some(libc::EINVAL) | Some(libc::ENOSYS)) => {}
+        result => return result,
+    }
+
+    // Linking fails when the name is taken, unlike renaming
+    match fs::hard_link(from, to) {
+        Ok(()) => fs::remove_file(from).inspect_err(|_| {
+            let _ = fs::remove_file(to);
+        }),
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::AlreadyExists | io::ErrorKind::CrossesDevices
+            ) =>
+        {
+            Err(e)
+        }
+        // Filesystems without hard links
+        Err(_) if fs::symlink_metadata(to).is_ok() => {
+            Err(io::Error::from(io::ErrorKind::AlreadyExists))
+        }
+        Err(_) => fs::rename(from, to),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn rename_noreplace(from: &Path, to: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let from = CString::new(from.as_os_str().as_bytes())?;
+    let to = CString::new(to.as_os_str().as_bytes())?;
+    let result = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            from.as_ptr(),
+            libc::AT_FDCWD,
+            to.as_ptr(),
+            libc::RENAME_NOREPLACE,
+        )
+    };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Puts `new_file` in place of `destination_file`, sending that one to the trash first; without
+/// the trash the rename replaces it.
+pub(crate) fn replace_with(
+    settings: &Settings,
+    new_file: &Path,
+    destination_file: &Path,
+) -> Result<()> {
+    if settings.trash {
+        discard(settings, destination_file)?;
+    }
+
+    fs::rename(new_file, destination_file).map_err(|e| Error::io(destination_file, e))
+}
+
+/// Creates a link at `destination_file` to where the link `file` points, a relative target made
+/// absolute so it doesn't break in the new place.
+fn copy_link(file: &Path, destination_file: &Path) -> Result<()> {
+    let target = fs::read_link(file).map_err(|e| Error::io(file, e))?;
+    // Sources are kept as given, so the link itself may be relative too
+    let file = std::path::absolute(file).map_err(|e| Error::io(file, e))?;
+    let target = match file.parent() {
+        Some(dir) if target.is_relative() => dir.join(target),
+        _ => target,
+    };
+
+    symlink(&target, destination_file).map_err(|e| Error::io(destination_file, e))
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    if target.is_dir() {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+}
+
+/// Deletes or trashes a file matched by a cleanup rule, in any mode.
+pub fn clean_up(settings: &Settings, file: &Path, action: CleanupAction) -> Result<()> {
+    match action {
+        CleanupAction::Delete => fs::remove_file(file).map_err(|e| Error::io(file, e)),
+        CleanupAction::Trash => discard(settings, file),
+    }
+}
+
+/// Sends a replaced or redundant file to the trash, or deletes it when `trash` is off.
+pub fn discard(settings: &Settings, file: &Path) -> Result<()> {
+    if !settings.trash {
+        return fs::remove_file(file).map_err(|e| Error::io(file, e));
+    }
+
+    trash::delete(file).map_err(|e| {
+        Error::io(
+            file,
+            io::Error::other(format!("failed to move to trash: {}", e)),
+        )
+    })
+}
+
+/// Reserves the first free name like `name (1).ext` with an empty file, so nothing else takes it
+/// before the file is put there.
+fn reserve_free_path(destination_file: &Path) -> Result<PathBuf> {
+    let stem = destination_file
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = destination_file
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+
+    let mut n = 1;
+    loop {
+        let path = destination_file.with_file_name(format!("{} ({}){}", stem, n, extension));
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(_) => return Ok(path),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => n += 1,
+            Err(e) => return Err(Error::io(&path, e)),
+        }
+    }
+}
+
+/// Copies into a temporary file next to `destination_file` and renames it into place once complete
+/// (and verified, with `verify`), so an interrupted copy never leaves a truncated file that looks valid.
+/// The rename never replaces a file that showed up at the destination meanwhile.
+/// The copy keeps the timestamps and permissions of the original. On copy-on-write filesystems the
+/// copy is a clone sharing the blocks of the original, made instantly and taking no room.
+pub fn copy_atomic(settings: &Settings, file: &Path, destination_file: &Path) -> Result<()> {
+    let temp_file = temp_path(destination_file);
+
+    let copied = if clone_to(file, &temp_file) {
+        sync(&temp_file, settings.fsync)
+    } else {
+        check_free_space(settings, file, destination_file)
+            .and_then(|()| copy_to(file, &temp_file, settings.fsync))
+    };
+    let result = copied
+        .and_then(|()| {
+            if settings.verify {
+                verify_copy(file, &temp_file)
+            } else {
+                Ok(())
+            }
+        })
+        .and_then(|()| preserve_metadata(file, &temp_file, settings.preserve_xattrs))
+        .and_then(|()| keep_quarantine(settings, file, &temp_file))
+        .and_then(|()| preserve_security(settings, file, &temp_file))
+        .and_then(|()| {
+            rename_new(&temp_file, destination_file).map_err(|e| Error::io(destination_file, e))
+        });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_file);
+    }
+
+    result
+}
+
+/// Fails if copying the file would leave less than `min_free_space` free at the destination.
+fn check_free_space(settings: &Settings, file: &Path, destination_file: &Path) -> Result<()> {
+    let destination_dir = destination_file.parent().unwrap_or(destination_file);
+    let size = allocated_size(&fs::metadata(file).map_err(|e| Error::io(file, e))?);
+    let available =
+        fs2::available_space(destination_dir).map_err(|e| Error::io(destination_dir, e))?;
+    let reserve = if settings.min_free_space.is_zero() {
+        0
+    } else {
+        let total = fs2::total_space(destination_dir).map_err(|e| Error::io(destination_dir, e))?;
+        settings.min_free_space.bytes(total)
+    };
+
+    if available >= size.saturating_add(reserve) {
+        return Ok(());
+    }
+
+    Err(Error::io(
+        file,
+        io::Error::new(
+            io::ErrorKind::StorageFull,
+            format!(
+                "not enough free space in {} ({} needed, {} available, keeping {} free)",
+                destination_dir.display(),
+                ByteSize(size),
+                ByteSize(available),
+                settings.min_free_space
+            ),
+        ),
+    ))
+}
+
+/// Clones `file` to `temp_file` with FICLONE on Linux, clonefile on macOS or block cloning on ReFS,
+/// false where the filesystem can't, or the file is on another one.
+fn clone_to(file: &Path, temp_file: &Path) -> bool {
+    // Unlike copies, clones can't overwrite a temporary file left by an interrupted run
+    let _ = fs::remove_file(temp_file);
+
+    match reflink_copy::reflink(file, temp_file) {
+        Ok(()) => {
+            debug!("Cloned {} to {}", file.display(), temp_file.display());
+            true
+        }
+        Err(e) => {
+            trace!("Can't clone {}, copying it: {}", file.display(), e);
+            false
+        }
+    }
+}
+
+/// Flushes a file written through its path to disk, with `fsync`.
+fn sync(temp_file: &Path, fsync: bool) -> Result<()> {
+    if !fsync {
+        return Ok(());
+    }
+
+    File::open(temp_file)
+        .and_then(|temp| temp.sync_all())
+        .map_err(|e| Error::io(temp_file, e))
+}
+
+#[cfg(not(windows))]
+fn copy_to(file: &Path, temp_file: &Path, fsync: bool) -> Result<()> {
+    let mut source = File::open(file).map_err(|e| Error::io(file, e))?;
+    let mut temp = File::create(temp_file).map_err(|e| Error::io(temp_file, e))?;
+    copy_contents(&mut source, &mut temp).map_err(|e| Error::io(file, e))?;
+
+    if fsync {
+        temp.sync_all().map_err(|e| Error::io(temp_file, e))?;
+    }
+
+    Ok(())
+}
+
+/// `fs::copy` is CopyFileExW here, copying in the kernel along with the attributes of the file.
+#[cfg(windows)]
+fn copy_to(file: &Path, temp_file: &Path, fsync: bool) -> Result<()> {
+    fs::copy(file, temp_file).map_err(|e| Error::io(file, e))?;
+
+    // A read-only attribute carried over would keep the rest of the metadata from being set
+    let mut permissions = fs::metadata(temp_file)
+        .map_err(|e| Error::io(temp_file, e))?
+        .permissions();
+    if permissions.readonly() {
+        permissions.set_readonly(false);
+        fs::set_permissions(temp_file, permissions).map_err(|e| Error::io(temp_file, e))?;
+    }
+
+    sync(temp_file, fsync)
+}
+
+/// Copies with copy_file_range, in the kernel, which lets network filesystems copy on the server and
+/// others share blocks. Only the data of sparse files is copied, so their copies keep the holes.
+#[cfg(target_os = "linux")]
+fn copy_contents(source: &mut File, temp: &mut File) -> io::Result<()> {
+    let metadata = source.metadata()?;
+    if allocated_size(&metadata) < metadata.len() {
+        return copy_sparse(source, temp, metadata.len());
+    }
+
+    copy_range(source, temp, u64::MAX)
+}
+
+/// Copies the data between holes, found with SEEK_DATA and SEEK_HOLE, and sets the length of the copy
+/// so a hole at the end is kept too.
+#[cfg(target_os = "linux")]
+fn copy_sparse(source: &mut File, temp: &mut File, len: u64) -> io::Result<()> {
+    use std::io::{Seek, SeekFrom};
+    use std::os::unix::io::AsRawFd;
+
+    let fd = source.as_raw_fd();
+    let mut offset: libc::off_t = 0;
+    while (offset as u64) < len {
+        let data = unsafe { libc::lseek(fd, offset, libc::SEEK_DATA) };
+        if data < 0 {
+            let e = io::Error::last_os_error();
+            // No data past the offset, the rest is a hole
+            if e.raw_os_error() == Some(libc::ENXIO) {
+                break;
+            }
+            return Err(e);
+        }
+        let hole = unsafe { libc::lseek(fd, data, libc::SEEK_HOLE) };
+        if hole < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        source.seek(SeekFrom::Start(data as u64))?;
+        temp.seek(SeekFrom::Start(data as u64))?;
+        copy_range(source, temp, (hole - data) as u64)?;
+        offset = hole;
+    }
+
+    temp.set_len(len)
+}
+
+/// Copies up to `len` bytes from the positions of the files, falling back to `io::copy` where
+/// copy_file_range isn't supported, like across some filesystems.
+#[cfg(target_os = "linux")]
+fn copy_range(source: &mut File, temp: &mut File, len: u64) -> io::Result<()> {
+    use std::io::Read;
+    use std::os::unix::io::AsRawFd;
+
+    // Capped so it fits in a ssize_t on 32 bit systems
+    const CHUNK: u64 = 1 << 30;
+
+    let mut remaining = len;
+    while remaining > 0 {
+        // Null offsets use and advance the positions of the files, which the fallback picks up from
+        let copied = unsafe {
+            libc::copy_file_range(
+                source.as_raw_fd(),
+                std::ptr::null_mut(),
+                temp.as_raw_fd(),
+                std::ptr::null_mut(),
+                remaining.min(CHUNK) as usize,
+                0,
+            )
+        };
+        match copied {
+            0 => break,
+            copied if copied > 0 => {
+                remaining -= copied as u64;
+                continue;
+            }
+            _ => {}
+        }
+
+        let e = io::Error::last_os_error();
+        match e.raw_os_error() {
+            Some(libc::EINTR) => continue,
+            Some(libc::ENOSYS | libc::EXDEV | libc::EOPNOTSUPP | libc::EINVAL | libc::EPERM) => {
+                trace!(
+                    "copy_file_range isn't supported, copying in userspace: {}",
+                    e
+                );
+                break;
+            }
+            _ => return Err(e),
+        }
+    }
+
+    // Also picks up data some filesystems like procfs don't report to copy_file_range
+    io::copy(&mut source.by_ref().take(remaining), temp).map(|_| ())
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn copy_contents(source: &mut File, temp: &mut File) -> io::Result<()> {
+    io::copy(source, temp).map(|_| ())
+}
+
+/// The space the file takes on disk, less than its length when it's sparse.
+#[cfg(unix)]
+fn allocated_size(metadata: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+
+    // Blocks are always counted in 512 bytes, whatever the block size of the filesystem
+    metadata.len().min(metadata.blocks() * 512)
+}
+
+#[cfg(not(unix))]
+fn allocated_size(metadata: &fs::Metadata) -> u64 {
+    metadata.len()
+}
+
+/// Carries the timestamps, extended attributes (with `xattrs`) and permissions of `file` over to its copy.
+/// Permissions go last, as a read-only copy couldn't take the rest.
+fn preserve_metadata(file: &Path, copy: &Path, xattrs: bool) -> Result<()> {
+    let metadata = fs::metadata(file).map_err(|e| Error::io(file, e))?;
+
+    filetime::set_file_times(
+        copy,
+        FileTime::from_last_access_time(&metadata),
+        FileTime::from_last_modification_time(&metadata),
+    )
+    .map_err(|e| Error::io(copy, e))?;
+
+    if xattrs {
+        copy_xattrs(file, copy)?;
+    }
+
+    fs::set_permissions(copy, metadata.permissions()).map_err(|e| Error::io(copy, e))
+}
+
+#[cfg(unix)]
+fn copy_xattrs(file: &Path, copy: &Path) -> Result<()> {
+    for name in xattr::list(file).map_err(|e| Error::io(file, e))? {
+        if let Some(value) = xattr::get(file, &name).map_err(|e| Error::io(file, e))? {
+            xattr::set(copy, &name, &value).map_err(|e| Error::io(copy, e))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn copy_xattrs(_file: &Path, _copy: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+const QUARANTINE_ATTRIBUTE: &str = "com.apple.quarantine";
+
+/// Carries the quarantine attribute of `file` over to its copy with `quarantine: keep`.
+#[cfg(target_os = "macos")]
+fn keep_quarantine(settings: &Settings, file: &Path, copy: &Path) -> Result<()> {
+    if settings.quarantine != Quarantine::Keep {
+        return Ok(());
+    }
+
+    match xattr::get(file, QUARANTINE_ATTRIBUTE).map_err(|e| Error::io(file, e))? {
+        Some(value) => {
+            xattr::set(copy, QUARANTINE_ATTRIBUTE, &value).map_err(|e| Error::io(copy, e))
+        }
+        None => Ok(()),
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn strip_quarantine(file: &Path) -> Result<()> {
+    if xattr::get(file, QUARANTINE_ATTRIBUTE)
+        .map_err(|e| Error::io(file, e))?
+        .is_some()
+    {
+        xattr::remove(file, QUARANTINE_ATTRIBUTE).map_err(|e| Error::io(file, e))?;
+    }
+
+    Ok(())
+}
+
+// Only macOS quarantines files
+#[cfg(not(target_os = "macos"))]
+fn keep_quarantine(_settings: &Settings, _file: &Path, _copy: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(not(target_os = "macos"))]
+fn strip_quarantine(_file: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// The SELinux context and the POSIX ACL of a file, as the extended attributes Linux keeps them in.
+#[cfg(target_os = "linux")]
+const SECURITY_ATTRIBUTES: &[&str] = &["security.selinux", "system.posix_acl_access"];
+
+/// Carries the SELinux context and POSIX ACL of `file` over to its copy with `preserve_security`,
+/// copies get the ones of the dir they're made in otherwise. Filesystems without support for
+/// either are left out.
+#[cfg(target_os = "linux")]
+fn preserve_security(settings: &Settings, file: &Path, copy: &Path) -> Result<()> {
+    if !settings.preserve_security {
+        return Ok(());
+    }
+
+    let unsupported = |e: &io::Error| e.raw_os_error() == Some(libc::EOPNOTSUPP);
+    for name in SECURITY_ATTRIBUTES {
+        let value = match xattr::get(file, name) {
+            Ok(Some(value)) => value,
+            Ok(None) => continue,
+            Err(e) if unsupported(&e) => continue,
+            Err(e) => return Err(Error::io(file, e)),
+        };
+        match xattr::set(copy, name, &value) {
+            Ok(()) => {}
+            Err(e) if unsupported(&e) => {
+                debug!("{} can't keep {}: {}", copy.display(), name, e);
+            }
+            Err(e) => return Err(Error::io(copy, e)),
+        }
+    }
+
+    Ok(())
+}
+
+/// Gives the file the SELinux context the policy has for its new place by running `restorecon`
+/// on it, with `selinux_relabel`.
+#[cfg(target_os = "linux")]
+fn relabel(file: &Path) -> Result<()> {
+    let output = std::process::Command::new("restorecon")
+        .arg("--")
+        .arg(file)
+        .output()
+        .map_err(|e| {
+            Error::io(
+                file,
+                io::Error::new(e.kind(), format!("failed to run restorecon: {}", e)),
+            )
+        })?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(Error::io(
+            file,
+            io::Error::other(format!(
+                "restorecon failed with {}: {}",
+                output.status,
+                stderr.trim_end()
+            )),
+        ));
+    }
+
+    Ok(())
+}
+
+// SELinux and these ACLs are Linux only
+#[cfg(not(target_os = "linux"))]
+fn preserve_security(_settings: &Settings, _file: &Path, _copy: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn relabel(_file: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Compares the hashes of the original and the copy.
+fn verify_copy(file: &Path, copy: &Path) -> Result<()> {
+    if hash::hash_file(file)? == hash::hash_file(copy)? {
+        return Ok(());
+    }
+
+    Err(Error::io(
+        file,
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "copy doesn't match the original, keeping it",
+        ),
+    ))
+}
+
+/// The temporary file a copy to `destination_file` is written to.
+pub fn temp_path(destination_file: &Path) -> PathBuf {
+    let name = destination_file
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    destination_file.with_file_name(format!("{}{}", TEMP_PREFIX, name))
+}
+
+/// Where a complete file replacing `destination_file`, or taking its reserved name, waits next to
+/// it to be renamed into place.
+pub fn staged_path(destination_file: &Path) -> PathBuf {
+    let name = destination_file
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    destination_file.with_file_name(format!("{}staged.{}", TEMP_PREFIX, name))
+}
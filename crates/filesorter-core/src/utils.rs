@@ -0,0 +1,425 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use crate::date;
+use crate::error::{Error, Result};
+use crate::settings::{Settings, SortPattern, Source, IGNORE_FILE};
+use crate::tags::{self, AudioTags};
+use crate::template;
+use crate::transfer::WindowsNames;
+
+use chrono::prelude::*;
+use chrono::DateTime;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use regex::Captures;
+use std::path::{Component, Path, PathBuf};
+
+pub fn create_dir(path: &Path) -> Result<()> {
+    if !path.exists() {
+        std::fs::create_dir_all(path).map_err(|e| Error::io(path, e))?;
+        info!("Created dir {}", path.display());
+    } else if !path.is_dir() {
+        return Err(Error::io(
+            path,
+            std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                "already exists but is not a directory",
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Files in `path`, also from subdirectories down to `depth` levels below it. The dirs in `skip` (like a
+/// destination inside the source) aren't descended into, and files and dirs `excluded` says so
+/// of, given whether it's a dir, are left out.
+pub fn get_files(
+    path: &Path,
+    depth: usize,
+    skip: &[&Path],
+    excluded: &dyn Fn(&Path, bool) -> bool,
+) -> Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = Vec::new();
+
+    for entry in std::fs::read_dir(path).map_err(|e| Error::io(path, e))? {
+        let entry = entry.map_err(|e| Error::io(path, e))?;
+        let entry_path = entry.path();
+        let is_dir = entry_path.is_dir();
+        if excluded(&entry_path, is_dir) {
+            continue;
+        } else if entry_path.is_file() {
+            files.push(entry_path)
+        } else if depth > 0 && is_dir && !skip.contains(&entry_path.as_path()) {
+            files.extend(get_files(&entry_path, depth - 1, skip, excluded)?);
+        }
+    }
+
+    Ok(files)
+}
+
+/// Whether the file or dir is hidden: its name starts with a dot, or on Windows it has the hidden
+/// attribute.
+pub fn is_hidden(path: &Path) -> bool {
+    let dotted = path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+
+    dotted || has_hidden_attribute(path)
+}
+
+#[cfg(windows)]
+fn has_hidden_attribute(path: &Path) -> bool {
+    use std::os::windows::fs::MetadataExt;
+
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    std::fs::symlink_metadata(path)
+        .is_ok_and(|metadata| metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0)
+}
+
+#[cfg(not(windows))]
+fn has_hidden_attribute(_path: &Path) -> bool {
+    false
+}
+
+/// The `ignore` patterns of the settings along with those of the [`IGNORE_FILE`] of the source,
+/// matched relative to the source like a `.gitignore`.
+pub fn ignore_matcher(settings: &Settings, source: &Path) -> Result<Gitignore> {
+    let invalid = |e: ignore::Error| Error::Settings(format!("Invalid ignore pattern {}", e));
+
+    let mut builder = GitignoreBuilder::new(source);
+    for pattern in &settings.ignore {
+        builder.add_line(None, pattern).map_err(invalid)?;
+    }
+    let ignore_file = source.join(IGNORE_FILE);
+    if ignore_file.is_file() {
+        if let Some(e) = builder.add(&ignore_file) {
+            return Err(invalid(e));
+        }
+    }
+
+    builder.build().map_err(invalid)
+}
+
+/// How to get at a dir the OS keeps private, when that's why it can't be read.
+#[cfg(target_os = "macos")]
+pub fn privacy_hint(error: &Error) -> Option<&'static str> {
+    match error {
+        Error::Io { source, .. } if source.kind() == std::io::ErrorKind::PermissionDenied => Some(
+            "macOS keeps Desktop, Documents, Downloads and removable volumes private, allow \
+             filesorter under Full Disk Access in System Settings > Privacy & Security",
+        ),
+        _ => None,
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+pub fn privacy_hint(_error: &Error) -> Option<&'static str> {
+    None
+}
+
+pub fn get_destination_dir(
+    settings: &Settings,
+    source: &Source,
+    pattern: &SortPattern,
+    file: &Path,
+    destination: &str,
+    captures: Option<&Captures>,
+) -> Result<PathBuf> {
+    let destination_root = settings.destination_for(source);
+    let mut destination = PathBuf::from(expand_destination(
+        settings,
+        source,
+        file,
+        destination,
+        captures,
+    )?);
+
+    if settings.preserve_structure {
+        let relative_dir = file
+            .parent()
+            .and_then(|parent| parent.strip_prefix(&source.path).ok())
+            .filter(|relative_dir| !relative_dir.as_os_str().is_empty());
+        if let Some(relative_dir) = relative_dir {
+            destination.push(relative_dir);
+        }
+    }
+
+    let use_date_pattern = pattern
+        .use_date_pattern
+        .unwrap_or(settings.use_date_pattern);
+    if use_date_pattern {
+        let date_pattern = pattern
+            .date_pattern
+            .as_ref()
+            .unwrap_or(&settings.date_pattern);
+        let date_pattern = date::resolve_date_pattern(date_pattern);
+        let date_folder = settings
+            .date_timezone
+            .format(&date::get_file_date(settings, file)?, date_pattern);
+
+        Ok(destination_root.join(&date_folder).join(destination))
+    } else {
+        Ok(destination_root.join(destination))
+    }
+}
+
+pub const DESTINATION_PLACEHOLDERS: &[&str] = &[
+    "ext",
+    "stem",
+    "year",
+    "month",
+    "day",
+    "date",
+    "mime_major",
+    "mime_minor",
+    "source_name",
+    "artist",
+    "album",
+];
+
+/// Expands `{ext}`, `{stem}`, `{year}`, `{month}`, `{day}`, `{date}`, `{mime_major}`, `{mime_minor}`,
+/// `{source_name}`, `{artist}` and `{album}` placeholders in a pattern destination or rename, along
+/// with the `name_regex` `captures`.
+pub fn expand_destination(
+    settings: &Settings,
+    source: &Source,
+    file: &Path,
+    destination: &str,
+    captures: Option<&Captures>,
+) -> Result<String> {
+    let mut error: Option<Error> = None;
+    let mut date: Option<DateTime<Utc>> = None;
+    let mut mime: Option<String> = None;
+    let mut audio_tags: Option<AudioTags> = None;
+
+    let destination = match captures {
+        Some(_) => capture_placeholders(destination),
+        None => destination.to_string(),
+    };
+    let expanded = template::expand(&destination, |name| match name {
+        "ext" => Some(
+            file.extension()
+                .map(|extension| extension.to_string_lossy().to_lowercase())
+                .unwrap_or_default(),
+        ),
+        "stem" => Some(
+            file.file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        ),
+        "year" | "month" | "day" | "date" => {
+            if date.is_none() {
+                match date::get_file_date(settings, file) {
+                    Ok(file_date) => date = Some(file_date),
+                    Err(e) => {
+                        error = Some(e);
+                        return None;
+                    }
+                }
+            }
+            let date = date.as_ref()?;
+            let format = match name {
+                "year" => "%Y",
+                "month" => "%m",
+                "day" => "%d",
+                _ => "%Y-%m-%d",
+            };
+            Some(settings.date_timezone.format(date, format))
+        }
+        "mime_major" | "mime_minor" => {
+            let mime = mime.get_or_insert_with(|| tree_magic::from_filepath(file));
+            let mut parts = mime.splitn(2, '/');
+            let major = parts.next().unwrap_or_default();
+            let minor = parts.next().unwrap_or_default();
+            Some(String::from(if name == "mime_major" {
+                major
+            } else {
+                minor
+            }))
+        }
+        "source_name" => Some(
+            source
+                .path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        ),
+        "artist" | "album" => {
+            let audio_tags = audio_tags.get_or_insert_with(|| tags::read_audio_tags(file));
+            let (value, fallback) = match name {
+                "artist" => (&audio_tags.artist, "Unknown Artist"),
+                _ => (&audio_tags.album, "Unknown Album"),
+            };
+            let value = value
+                .as_deref()
+                .map(path_part)
+                .filter(|value| !value.is_empty());
+            Some(value.unwrap_or_else(|| String::from(fallback)))
+        }
+        name => Some(capture_value(captures?, name.strip_prefix('$')?)),
+    });
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok(expanded),
+    }
+}
+
+/// `template` with only the `name_regex` captures filled in.
+pub fn expand_captures(template: &str, captures: &Captures) -> String {
+    template::expand(&capture_placeholders(template), |name| {
+        Some(capture_value(captures, name.strip_prefix('$')?))
+    })
+}
+
+/// Turns the `$name`, `${name}` and `$1` references to `name_regex` captures into `{$name}`
+/// placeholders, so they're filled in by the same pass as the others and what a capture took
+/// from the file name isn't expanded again. `$$` is a literal `$`.
+pub fn capture_placeholders(template: &str) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        if let Some(after) = rest.strip_prefix('$') {
+            result.push('$');
+            rest = after;
+            continue;
+        }
+        let (name, after) = match rest.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], &braced[end + 1..]),
+                None => ("", rest),
+            },
+            None => {
+                let end = rest
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .unwrap_or(rest.len());
+                (&rest[..end], &rest[end..])
+            }
+        };
+        if name.is_empty() {
+            result.push('$');
+        } else {
+            result.push_str(&format!("{{${}}}", name));
+            rest = after;
+        }
+    }
+    result.push_str(rest);
+
+    result
+}
+
+/// A capture by name or number, empty when its group didn't take part in the match.
+fn capture_value(captures: &Captures, name: &str) -> String {
+    let value = match name.parse::<usize>() {
+        Ok(index) => captures.get(index),
+        Err(_) => captures.name(name),
+    };
+    value.map_or_else(String::new, |value| path_part(value.as_str()))
+}
+
+/// Free text taken from a file, like a tag or a capture, kept from adding directory levels.
+fn path_part(value: &str) -> String {
+    value.trim().replace(['/', '\\'], "_")
+}
+
+/// Fails unless `destination_file` is below the destination `root`, which a `..` or an absolute
+/// path in a rule, a capture or a plugin's destination could get out of.
+pub fn check_inside(root: &Path, file: &Path, destination_file: &Path) -> Result<()> {
+    let inside = destination_file.strip_prefix(root).is_ok_and(|relative| {
+        relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    });
+
+    if inside {
+        Ok(())
+    } else {
+        Err(Error::OutsideDestination {
+            path: file.to_path_buf(),
+            destination: destination_file.to_path_buf(),
+        })
+    }
+}
+
+/// Names Windows reserves for devices, with any extension.
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// The name Windows would accept instead, if it doesn't accept this one: characters it doesn't
+/// allow become `_`, trailing dots and spaces are dropped and reserved names like `aux.log` get
+/// a `_`, like `aux_.log`.
+pub fn windows_name(name: &str) -> Option<String> {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    sanitized.truncate(sanitized.trim_end_matches(['.', ' ']).len());
+    if sanitized.is_empty() {
+        sanitized.push('_');
+    }
+
+    // Windows takes everything before the first dot, and trailing spaces, as the device
+    let stem_len = sanitized.find('.').unwrap_or(sanitized.len());
+    let stem = sanitized[..stem_len].trim_end_matches(' ');
+    if WINDOWS_RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        sanitized.insert(stem.len(), '_');
+    }
+
+    if sanitized == name {
+        None
+    } else {
+        Some(sanitized)
+    }
+}
+
+/// Applies `windows_names` to the part of the `destination` of `file` below the destination root,
+/// the root itself is left as configured.
+pub fn apply_windows_names(
+    settings: &Settings,
+    file: &Path,
+    root: &Path,
+    destination: PathBuf,
+) -> Result<PathBuf> {
+    let relative = match destination.strip_prefix(root) {
+        Ok(relative) if settings.windows_names != WindowsNames::Keep => relative,
+        _ => return Ok(destination),
+    };
+
+    let mut sanitized = root.to_path_buf();
+    for component in relative.components() {
+        let name = match component {
+            Component::Normal(name) => name.to_string_lossy(),
+            component => {
+                sanitized.push(component);
+                continue;
+            }
+        };
+        match windows_name(&name) {
+            Some(_) if settings.windows_names == WindowsNames::Fail => {
+                return Err(Error::WindowsName {
+                    path: file.to_path_buf(),
+                    name: name.into_owned(),
+                })
+            }
+            Some(name) => sanitized.push(name),
+            None => sanitized.push(component),
+        }
+    }
+
+    Ok(sanitized)
+}
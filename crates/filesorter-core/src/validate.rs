@@ -0,0 +1,397 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use crate::date;
+use crate::hooks;
+use crate::migrate;
+use crate::settings::{self, Settings};
+use crate::template;
+use crate::utils;
+
+use chrono::format::{Item, StrftimeItems};
+use ignore::gitignore::GitignoreBuilder;
+use serde_yaml::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+/// Something wrong with the settings file, optionally pointing at a line.
+#[derive(Clone, Debug)]
+pub struct Problem {
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl Problem {
+    fn new(line: Option<usize>, message: String) -> Self {
+        Problem { line, message }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+pub fn validate_file(path: &Path) -> Vec<Problem> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => {
+            return vec![Problem::new(
+                None,
+                format!("Failed to read {}: {}", path.display(), e),
+            )]
+        }
+    };
+
+    let mut value: Value = match serde_yaml::from_str(&text) {
+        Ok(value) => value,
+        Err(e) => return vec![Problem::new(e.location().map(|l| l.line()), e.to_string())],
+    };
+
+    let version = match migrate::migrate(&mut value) {
+        Ok(version) => version,
+        Err(e) => return vec![Problem::new(find_key_line(&text, "version", true), e)],
+    };
+
+    let mut problems: Vec<Problem> = Vec::new();
+
+    if version < migrate::CURRENT_VERSION {
+        problems.push(Problem::new(
+            None,
+            format!(
+                "Settings file is at version {}, run `filesorter config migrate` to upgrade it to version {}",
+                version,
+                migrate::CURRENT_VERSION
+            ),
+        ));
+    }
+
+    let mut unknown_keys: Vec<String> = Vec::new();
+    let settings: Result<Settings, _> = serde_ignored::deserialize(value, |path| {
+        unknown_keys.push(path.to_string())
+    });
+
+    for key in unknown_keys {
+        let name = key.rsplit('.').next().unwrap_or(&key);
+        problems.push(Problem::new(
+            find_key_line(&text, name, !key.contains('.')),
+            format!("Unknown key '{}'", key),
+        ));
+    }
+
+    match settings {
+        Ok(mut settings) => {
+            problems.extend(check_includes(path, &text, &mut settings));
+            problems.extend(check_settings(&text, &settings));
+        }
+        Err(e) => {
+            // Errors from the parsed value carry no position, reparse the text to get one
+            let line = serde_yaml::from_str::<Settings>(&text)
+                .err()
+                .and_then(|e| e.location())
+                .map(|l| l.line());
+            problems.push(Problem::new(line, e.to_string()));
+        }
+    }
+
+    problems
+}
+
+fn check_includes(path: &Path, text: &str, settings: &mut Settings) -> Vec<Problem> {
+    let mut problems: Vec<Problem> = Vec::new();
+
+    for include in &settings.include {
+        let include_path = Settings::resolve_include(path, include);
+        match Settings::read_include(&include_path) {
+            Ok(patterns) => settings.included_patterns.extend(patterns),
+            Err(e) => problems.push(Problem::new(
+                find_value_line(text, &include.display().to_string()),
+                format!("Failed to load included file {}: {}", include_path.display(), e),
+            )),
+        }
+    }
+
+    problems
+}
+
+fn check_settings(text: &str, settings: &Settings) -> Vec<Problem> {
+    let mut problems: Vec<Problem> = Vec::new();
+
+    if settings.destination.as_os_str().is_empty() {
+        problems.push(Problem::new(
+            find_key_line(text, "destination", true),
+            String::from("Destination is empty"),
+        ));
+    }
+
+    if settings.sources.is_empty() {
+        problems.push(Problem::new(
+            find_key_line(text, "sources", true),
+            String::from("No sources configured"),
+        ));
+    }
+
+    for source in &settings.sources {
+        if !source.path.is_dir() {
+            problems.push(Problem::new(
+                find_value_line(text, &source.path.display().to_string()),
+                format!("Source dir '{}' doesn't exist", source.path.display()),
+            ));
+        }
+        if let Some(destination) = &source.destination {
+            if destination.as_os_str().is_empty() {
+                problems.push(Problem::new(
+                    find_value_line(text, &source.path.display().to_string()),
+                    format!("Source '{}' has an empty destination", source.path.display()),
+                ));
+            }
+        }
+    }
+
+    if settings.use_date_pattern && settings.date_pattern.is_empty() {
+        problems.push(Problem::new(
+            find_key_line(text, "date_pattern", true),
+            String::from("Date pattern is enabled but empty"),
+        ));
+    }
+
+    if !is_valid_date_pattern(&settings.date_pattern) {
+        problems.push(Problem::new(
+            find_key_line(text, "date_pattern", true),
+            format!("Invalid date pattern '{}'", settings.date_pattern),
+        ));
+    }
+
+    if settings.preserve_structure && !settings.recursive {
+        problems.push(Problem::new(
+            find_key_line(text, "preserve_structure", true),
+            String::from("preserve_structure has no effect without recursive"),
+        ));
+    }
+
+    let has_max_depth = settings.max_depth.is_some()
+        || settings.sources.iter().any(|source| source.max_depth.is_some());
+    if has_max_depth && !settings.recursive {
+        problems.push(Problem::new(
+            find_key_line(text, "max_depth", false),
+            String::from("max_depth has no effect without recursive"),
+        ));
+    }
+
+    if settings.watch_quiet_period.is_zero() {
+        problems.push(Problem::new(
+            find_key_line(text, "watch_quiet_period", true),
+            String::from("watch_quiet_period can't be 0"),
+        ));
+    }
+
+    if let Some(similar_images) = &settings.similar_images {
+        if similar_images.threshold >= 64 {
+            problems.push(Problem::new(
+                find_key_line(text, "threshold", false),
+                format!(
+                    "similar_images threshold {} counts every image as alike, keep it under 64",
+                    similar_images.threshold
+                ),
+            ));
+        }
+        if similar_images.folder.is_empty() {
+            problems.push(Problem::new(
+                find_key_line(text, "similar_images", true),
+                String::from("similar_images has an empty folder"),
+            ));
+        }
+    }
+
+    if let (Some(min), Some(max)) = (settings.min_file_size, settings.max_file_size) {
+        if min > max {
+            problems.push(Problem::new(
+                find_key_line(text, "min_file_size", true),
+                format!(
+                    "min_file_size {} is over max_file_size {}, no file would be sorted",
+                    min, max
+                ),
+            ));
+        }
+    }
+
+    for pattern in &settings.ignore {
+        if let Err(e) = GitignoreBuilder::new("/").add_line(None, pattern) {
+            problems.push(Problem::new(
+                find_value_line(text, pattern),
+                format!("Invalid ignore pattern '{}': {}", pattern, e),
+            ));
+        }
+    }
+
+    for regex in &settings.filename_date_regexes {
+        if !regex.0.capture_names().any(|name| name == Some("year")) {
+            problems.push(Problem::new(
+                find_value_line(text, regex.0.as_str()),
+                format!("Filename date regex '{}' has no 'year' group", regex.0.as_str()),
+            ));
+        }
+    }
+
+    let mut hooks = vec![
+        ("pre_hook", &settings.pre_hook),
+        ("post_hook", &settings.post_hook),
+    ];
+    for pattern in &settings.sort_patterns {
+        hooks.push(("pre_hook", &pattern.pre_hook));
+        hooks.push(("post_hook", &pattern.post_hook));
+    }
+    for (key, command) in hooks {
+        if let Some(command) = command {
+            template::expand(command, |name| {
+                if !hooks::HOOK_PLACEHOLDERS.contains(&name) {
+                    problems.push(Problem::new(
+                        find_value_line(text, &format!("{{{}}}", name)),
+                        format!(
+                            "Unknown placeholder '{{{}}}' in {}, write literal braces as '{{{{' and '}}}}'",
+                            name, key
+                        ),
+                    ));
+                }
+                None
+            });
+        }
+    }
+
+    if let Some(body) = &settings.webhook_body {
+        template::expand(body, |name| {
+            if !settings::WEBHOOK_PLACEHOLDERS.contains(&name) {
+                problems.push(Problem::new(
+                    find_key_line(text, "webhook_body", true),
+                    format!(
+                        "Unknown placeholder '{{{}}}' in webhook_body, write literal braces as '{{{{' and '}}}}'",
+                        name
+                    ),
+                ));
+            }
+            None
+        });
+    }
+
+    let last_pattern = settings.sort_patterns.len().saturating_sub(1);
+    for (index, pattern) in settings.sort_patterns.iter().enumerate() {
+        if pattern.is_catch_all() && index != last_pattern {
+            problems.push(Problem::new(
+                None,
+                format!(
+                    "Catch-all ('*') sort pattern '{}' should be the last one",
+                    pattern.destination
+                ),
+            ));
+        }
+    }
+
+    let mut extension_owners: HashMap<String, &str> = HashMap::new();
+    for pattern in settings.all_patterns() {
+        if pattern.destination.is_empty() && pattern.action.is_none() {
+            problems.push(Problem::new(
+                None,
+                format!(
+                    "Sort pattern for extensions [{}] has an empty destination",
+                    pattern.extensions.join(", ")
+                ),
+            ));
+        }
+
+        if let Some(date_pattern) = &pattern.date_pattern {
+            if !is_valid_date_pattern(date_pattern) {
+                problems.push(Problem::new(
+                    None,
+                    format!(
+                        "Invalid date pattern '{}' in sort pattern '{}'",
+                        date_pattern, pattern.destination
+                    ),
+                ));
+            }
+        }
+
+        for template in std::iter::once(&pattern.destination).chain(&pattern.rename) {
+            // `name_regex` captures are filled in before the placeholders
+            let placeholders = match pattern.name_regex {
+                Some(_) => utils::capture_placeholders(template),
+                None => template.clone(),
+            };
+            template::expand(&placeholders, |name| {
+                if !utils::DESTINATION_PLACEHOLDERS.contains(&name) && !name.starts_with('$') {
+                    problems.push(Problem::new(
+                        None,
+                        format!("Unknown placeholder '{{{}}}' in '{}'", name, template),
+                    ));
+                }
+                None
+            });
+        }
+
+        if let Some(rename) = &pattern.rename {
+            if rename.contains(['/', '\\']) {
+                problems.push(Problem::new(
+                    find_value_line(text, rename),
+                    format!("Rename '{}' shouldn't contain path separators", rename),
+                ));
+            }
+        }
+
+        if !pattern.finder_tags.is_empty() && !cfg!(target_os = "macos") {
+            problems.push(Problem::new(
+                find_key_line(text, "finder_tags", false),
+                format!(
+                    "Finder tags of sort pattern '{}' are only set on macOS",
+                    pattern.destination
+                ),
+            ));
+        }
+
+        for extension in &pattern.extensions {
+            let extension = extension.to_lowercase();
+            match extension_owners.get(&extension) {
+                Some(owner) => problems.push(Problem::new(
+                    None,
+                    format!(
+                        "Extension '{}' is used by both '{}' and '{}' sort patterns",
+                        extension, owner, pattern.destination
+                    ),
+                )),
+                None => {
+                    extension_owners.insert(extension, &pattern.destination);
+                }
+            }
+        }
+    }
+
+    problems
+}
+
+pub fn is_valid_date_pattern(date_pattern: &str) -> bool {
+    let date_pattern = date::resolve_date_pattern(date_pattern);
+    !StrftimeItems::new(date_pattern).any(|item| item == Item::Error)
+}
+
+/// Finds the (1-based) line where `key` is defined, optionally only at the top level.
+fn find_key_line(text: &str, key: &str, top_level: bool) -> Option<usize> {
+    text.lines()
+        .position(|line| {
+            if top_level && line.starts_with(char::is_whitespace) {
+                return false;
+            }
+            let line = line.trim_start().trim_start_matches("- ");
+            line.starts_with(key) && line[key.len()..].trim_start().starts_with(':')
+        })
+        .map(|index| index + 1)
+}
+
+fn find_value_line(text: &str, value: &str) -> Option<usize> {
+    text.lines()
+        .position(|line| line.contains(value))
+        .map(|index| index + 1)
+}
@@ -0,0 +1,190 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Checks sorted files against their manifests, and the moves of an interrupted run.
+
+use crate::error::Result;
+use crate::journal::{self, Journal};
+use crate::manifest::{self, ManifestFormat};
+use crate::transfer;
+use crate::utils;
+
+use rayon::prelude::*;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What's wrong with a file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// Listed in a manifest or the journal, but gone
+    Missing,
+    /// Changed since it was sorted, and its modification time says so
+    Modified,
+    /// Changed without its modification time changing, like bit rot does
+    Corrupted,
+    /// A copy of the interrupted run was left half-written
+    Incomplete,
+    /// Couldn't be read to check it
+    Unreadable,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Problem {
+    pub path: PathBuf,
+    pub status: Status,
+    /// The manifest listing the file, if it's listed in one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manifest: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct VerifyReport {
+    /// Files listed in manifests and moves of the journal
+    pub checked: usize,
+    pub problems: Vec<Problem>,
+}
+
+impl VerifyReport {
+    /// Like "Checked 120 file(s), 2 problem(s)".
+    pub fn summary(&self) -> String {
+        format!(
+            "Checked {} file(s), {} problem(s)",
+            self.checked,
+            self.problems.len()
+        )
+    }
+}
+
+/// Rehashes the files listed in the manifests under `dirs`, on `jobs` threads (0 for the number
+/// of CPUs), and checks the moves of the journal of an interrupted run, if there is one.
+pub fn verify(dirs: &[PathBuf], jobs: usize) -> Result<VerifyReport> {
+    let mut report = VerifyReport::default();
+
+    let mut entries: Vec<(PathBuf, ManifestFormat, manifest::Entry)> = Vec::new();
+    for dir in dirs {
+        for (manifest, format) in find_manifests(dir)? {
+            match manifest::read(&manifest) {
+                Ok(manifest_entries) => entries.extend(
+                    manifest_entries
+                        .into_iter()
+                        .map(|entry| (manifest.clone(), format, entry)),
+                ),
+                Err(e) => report.problems.push(Problem {
+                    path: manifest,
+                    status: Status::Unreadable,
+                    manifest: None,
+                    reason: Some(e.reason()),
+                }),
+            }
+        }
+    }
+    report.checked += entries.len();
+
+    // 0 lets rayon pick the number of logical CPUs
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(jobs).build()?;
+    let problems: Vec<Option<Problem>> = pool.install(|| {
+        entries
+            .par_iter()
+            .map(|(manifest, format, entry)| check_entry(manifest, *format, entry))
+            .collect()
+    });
+    report.problems.extend(problems.into_iter().flatten());
+
+    if let Some(journal) = Journal::load()? {
+        report.checked += journal.moves.len();
+        report.problems.extend(check_journal(&journal));
+    }
+
+    Ok(report)
+}
+
+/// The manifests in `dir` and its subdirectories, hidden ones aside.
+fn find_manifests(dir: &Path) -> Result<Vec<(PathBuf, ManifestFormat)>> {
+    let mut files = utils::get_files(dir, usize::MAX, &[], &|path, is_dir| {
+        is_dir && utils::is_hidden(path)
+    })?;
+    files.sort();
+
+    Ok(files
+        .into_iter()
+        .filter_map(|path| {
+            let format = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(ManifestFormat::from_file_name)?;
+            Some((path, format))
+        })
+        .collect())
+}
+
+fn check_entry(
+    manifest: &Path,
+    format: ManifestFormat,
+    entry: &manifest::Entry,
+) -> Option<Problem> {
+    let path = manifest.with_file_name(&entry.name);
+    let problem = |status: Status, reason: Option<String>| {
+        Some(Problem {
+            path: path.clone(),
+            status,
+            manifest: Some(manifest.to_path_buf()),
+            reason,
+        })
+    };
+
+    if !path.exists() {
+        return problem(Status::Missing, None);
+    }
+    let hash = match format.hash_file(&path) {
+        Ok(hash) => hash,
+        Err(e) => return problem(Status::Unreadable, Some(e.reason())),
+    };
+    if hash == entry.hash {
+        return None;
+    }
+
+    // The manifest is written after each file it lists, a file newer than it was changed on purpose
+    let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified());
+    let status = match (modified(&path), modified(manifest)) {
+        (Ok(file), Ok(manifest)) if file <= manifest => Status::Corrupted,
+        _ => Status::Modified,
+    };
+    problem(
+        status,
+        Some(format!("expected {}, found {}", entry.hash, hash)),
+    )
+}
+
+/// Moves that can't be resumed anymore, and copies left half-written.
+fn check_journal(journal: &Journal) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    for planned in &journal.moves {
+        let target = journal.target(planned);
+        if journal::partial_files(target)
+            .iter()
+            .chain([transfer::staged_path(target)].iter())
+            .any(|file| file.exists())
+        {
+            problems.push(Problem {
+                path: target.to_path_buf(),
+                status: Status::Incomplete,
+                manifest: None,
+                reason: Some(String::from("run `filesorter sort --resume` to finish it")),
+            });
+        } else if !planned.file.exists() && !target.exists() {
+            problems.push(Problem {
+                path: planned.file.clone(),
+                status: Status::Missing,
+                manifest: None,
+                reason: Some(format!("neither it nor {} exist anymore", target.display())),
+            });
+        }
+    }
+
+    problems
+}
@@ -0,0 +1,164 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Carrying out planned moves in parallel.
+
+use filesorter_core::executor::{self, Report};
+use filesorter_core::planner;
+use filesorter_core::plugins::Plugins;
+use filesorter_core::settings::{Settings, SortPattern};
+use filesorter_core::transfer::{self, ConflictPolicy, Outcome};
+
+use std::fs;
+use std::path::Path;
+use std::sync::Barrier;
+use std::thread;
+use tempfile::TempDir;
+
+const DIRS: usize = 16;
+const NAMES: usize = 50;
+
+/// `DIRS` dirs in `src` holding the same `NAMES` file names, each with its own contents.
+fn colliding_files(root: &Path) {
+    for dir in 0..DIRS {
+        let dir_path = root.join("src").join(format!("dir{}", dir));
+        fs::create_dir_all(&dir_path).unwrap();
+        for name in 0..NAMES {
+            fs::write(
+                dir_path.join(format!("file{}.txt", name)),
+                format!("{} {}", dir, name),
+            )
+            .unwrap();
+        }
+    }
+    fs::create_dir(root.join("dst")).unwrap();
+}
+
+/// Every file of the sources sorted into `docs`, whatever dir it's in.
+fn settings(root: &Path, conflict_policy: ConflictPolicy) -> Settings {
+    Settings::builder()
+        .source(root.join("src"))
+        .destination(root.join("dst"))
+        .recursive(true)
+        .conflict_policy(conflict_policy)
+        .trash(false)
+        .history(false)
+        .pattern(
+            SortPattern::builder()
+                .extension("txt")
+                .dest("docs")
+                .build()
+                .unwrap(),
+        )
+        .build()
+}
+
+fn sort(settings: &Settings, jobs: usize) -> Report {
+    let plan = planner::plan(settings, &settings.sources[0], &Plugins::default(), &());
+    assert!(plan.failures.is_empty());
+    executor::execute(settings, &plan.moves, jobs, false, false, &()).unwrap()
+}
+
+fn count_files(dir: &Path) -> usize {
+    fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .map(|path| if path.is_dir() { count_files(&path) } else { 1 })
+        .sum()
+}
+
+#[test]
+fn parallel_moves_to_one_destination_dont_replace_each_other() {
+    // Lost files showed up in about one run out of five
+    for _ in 0..20 {
+        let root = TempDir::new().unwrap();
+        colliding_files(root.path());
+
+        let report = sort(&settings(root.path(), ConflictPolicy::Skip), 16);
+
+        assert!(report.failures.is_empty());
+        assert_eq!(report.sorted, NAMES);
+        assert_eq!(count_files(&root.path().join("dst")), NAMES);
+        assert_eq!(count_files(&root.path().join("src")), (DIRS - 1) * NAMES);
+    }
+}
+
+#[test]
+fn transfers_racing_for_a_name_dont_replace_each_other() {
+    for _ in 0..20 {
+        let root = TempDir::new().unwrap();
+        colliding_files(root.path());
+        let settings = settings(root.path(), ConflictPolicy::Skip);
+        let destination_file = root.path().join("dst").join("file0.txt");
+
+        // Past the planner, which runs moves to the same file one after another
+        let barrier = Barrier::new(DIRS);
+        let outcomes: Vec<Outcome> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..DIRS)
+                .map(|dir| {
+                    let file = root
+                        .path()
+                        .join("src")
+                        .join(format!("dir{}", dir))
+                        .join("file0.txt");
+                    let (settings, barrier, destination_file) =
+                        (&settings, &barrier, &destination_file);
+                    scope.spawn(move || {
+                        barrier.wait();
+                        transfer::transfer(settings, &file, destination_file).unwrap()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        let transferred = outcomes
+            .iter()
+            .filter(|outcome| matches!(outcome, Outcome::Transferred(_)))
+            .count();
+        assert_eq!(transferred, 1);
+        assert_eq!(count_files(&root.path().join("dst")), 1);
+        assert_eq!(count_files(&root.path().join("src")), DIRS * NAMES - 1);
+    }
+}
+
+#[test]
+fn parallel_moves_to_one_destination_are_renamed() {
+    let root = TempDir::new().unwrap();
+    colliding_files(root.path());
+
+    let report = sort(&settings(root.path(), ConflictPolicy::Rename), 16);
+
+    assert!(report.failures.is_empty());
+    assert_eq!(report.sorted, DIRS * NAMES);
+    assert_eq!(count_files(&root.path().join("src")), 0);
+    // Every file kept its own contents under a name of its own
+    let docs = root.path().join("dst").join("docs");
+    let mut contents: Vec<String> = fs::read_dir(&docs)
+        .unwrap()
+        .map(|entry| fs::read_to_string(entry.unwrap().path()).unwrap())
+        .collect();
+    contents.sort();
+    contents.dedup();
+    assert_eq!(contents.len(), DIRS * NAMES);
+}
+
+#[test]
+fn parallel_moves_to_one_destination_overwrite_it() {
+    let root = TempDir::new().unwrap();
+    colliding_files(root.path());
+
+    let report = sort(&settings(root.path(), ConflictPolicy::Overwrite), 16);
+
+    assert!(report.failures.is_empty());
+    assert_eq!(report.sorted, DIRS * NAMES);
+    assert_eq!(count_files(&root.path().join("src")), 0);
+    // One of the files is left, whole, and nothing half-written next to it
+    let docs = root.path().join("dst").join("docs");
+    assert_eq!(count_files(&docs), NAMES);
+    for name in 0..NAMES {
+        let contents = fs::read_to_string(docs.join(format!("file{}.txt", name))).unwrap();
+        assert!(contents.ends_with(&format!(" {}", name)));
+    }
+}
@@ -0,0 +1,237 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Resuming interrupted runs.
+
+use filesorter_core::journal::{self, Journal};
+use filesorter_core::planner::{self, PlannedMove};
+use filesorter_core::plugins::Plugins;
+use filesorter_core::settings::{Settings, SortPattern};
+use filesorter_core::transfer::{self, ConflictPolicy, TransferMode};
+
+use std::fs;
+use std::path::Path;
+use std::slice;
+use tempfile::TempDir;
+
+/// `report.txt` in `src`, planned to go to `dst/docs`.
+fn planned(root: &Path) -> (Settings, PlannedMove) {
+    let src = root.join("src");
+    fs::create_dir_all(&src).unwrap();
+    fs::write(src.join("report.txt"), "new").unwrap();
+    fs::create_dir_all(root.join("dst").join("docs")).unwrap();
+
+    let settings = Settings::builder()
+        .source(src)
+        .destination(root.join("dst"))
+        .trash(false)
+        .history(false)
+        .pattern(
+            SortPattern::builder()
+                .extension("txt")
+                .dest("docs")
+                .build()
+                .unwrap(),
+        )
+        .build();
+    let mut plan = planner::plan(&settings, &settings.sources[0], &Plugins::default(), &());
+    assert!(plan.failures.is_empty());
+    let planned = plan.moves.pop().unwrap();
+
+    (settings, planned)
+}
+
+fn resume(settings: &Settings, journal: &Journal) {
+    let failures = journal::resume(settings, journal);
+    assert!(failures.is_empty(), "{:?}", failures);
+}
+
+#[test]
+fn interrupted_cross_device_move_is_finished() {
+    let root = TempDir::new().unwrap();
+    let (settings, planned) = planned(root.path());
+    // Copied, but the original wasn't removed yet, and a copy to the same name half-written
+    fs::copy(&planned.file, &planned.destination_file).unwrap();
+    let partial_file = transfer::temp_path(&planned.destination_file);
+    fs::write(&partial_file, "ne").unwrap();
+
+    let journal = Journal::new(
+        TransferMode::Move,
+        ConflictPolicy::Skip,
+        slice::from_ref(&planned),
+    );
+    resume(&settings, &journal);
+
+    assert!(!planned.file.exists());
+    assert!(!partial_file.exists());
+    assert_eq!(
+        fs::read_to_string(&planned.destination_file).unwrap(),
+        "new"
+    );
+}
+
+#[test]
+fn interrupted_copy_leaves_the_original() {
+    let root = TempDir::new().unwrap();
+    let (settings, planned) = planned(root.path());
+    fs::copy(&planned.file, &planned.destination_file).unwrap();
+
+    let journal = Journal::new(
+        TransferMode::Copy,
+        ConflictPolicy::Skip,
+        slice::from_ref(&planned),
+    );
+    resume(&settings, &journal);
+
+    assert_eq!(fs::read_to_string(&planned.file).unwrap(), "new");
+    assert_eq!(
+        fs::read_to_string(&planned.destination_file).unwrap(),
+        "new"
+    );
+}
+
+#[test]
+fn taken_destination_is_overwritten() {
+    let root = TempDir::new().unwrap();
+    let (settings, planned) = planned(root.path());
+    fs::write(&planned.destination_file, "old").unwrap();
+
+    let journal = Journal::new(
+        TransferMode::Move,
+        ConflictPolicy::Overwrite,
+        slice::from_ref(&planned),
+    );
+    resume(&settings, &journal);
+
+    assert!(!planned.file.exists());
+    assert_eq!(
+        fs::read_to_string(&planned.destination_file).unwrap(),
+        "new"
+    );
+}
+
+#[test]
+fn taken_destination_is_skipped() {
+    let root = TempDir::new().unwrap();
+    let (settings, planned) = planned(root.path());
+    fs::write(&planned.destination_file, "old").unwrap();
+
+    let journal = Journal::new(
+        TransferMode::Move,
+        ConflictPolicy::Skip,
+        slice::from_ref(&planned),
+    );
+    resume(&settings, &journal);
+
+    assert_eq!(fs::read_to_string(&planned.file).unwrap(), "new");
+    assert_eq!(
+        fs::read_to_string(&planned.destination_file).unwrap(),
+        "old"
+    );
+}
+
+#[test]
+fn staged_replacement_is_put_in_place() {
+    let root = TempDir::new().unwrap();
+    let (settings, planned) = planned(root.path());
+    fs::write(&planned.destination_file, "old").unwrap();
+    // Copied next to the destination, the original still there
+    let staged_file = transfer::staged_path(&planned.destination_file);
+    fs::copy(&planned.file, &staged_file).unwrap();
+
+    let journal = Journal::new(
+        TransferMode::Move,
+        ConflictPolicy::Overwrite,
+        slice::from_ref(&planned),
+    );
+    resume(&settings, &journal);
+
+    assert!(!planned.file.exists());
+    assert!(!staged_file.exists());
+    assert_eq!(
+        fs::read_to_string(&planned.destination_file).unwrap(),
+        "new"
+    );
+}
+
+#[test]
+fn staged_file_takes_the_recorded_name() {
+    let root = TempDir::new().unwrap();
+    let (settings, planned) = planned(root.path());
+    fs::write(&planned.destination_file, "old").unwrap();
+    let target = planned.destination_file.with_file_name("report (1).txt");
+    fs::write(&target, "").unwrap();
+    // Renamed next to the reserved name, on the same filesystem
+    let staged_file = transfer::staged_path(&target);
+    fs::rename(&planned.file, &staged_file).unwrap();
+
+    let mut journal = Journal::new(
+        TransferMode::Move,
+        ConflictPolicy::Rename,
+        slice::from_ref(&planned),
+    );
+    journal.targets.insert(planned.file.clone(), target.clone());
+    assert_eq!(journal.target(&planned), target);
+    resume(&settings, &journal);
+
+    assert!(!staged_file.exists());
+    assert_eq!(
+        fs::read_to_string(&planned.destination_file).unwrap(),
+        "old"
+    );
+    assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+}
+
+#[test]
+fn reserved_name_without_the_file_is_reused() {
+    let root = TempDir::new().unwrap();
+    let (settings, planned) = planned(root.path());
+    fs::write(&planned.destination_file, "old").unwrap();
+    let target = planned.destination_file.with_file_name("report (1).txt");
+    fs::write(&target, "").unwrap();
+    let partial_file = transfer::temp_path(&transfer::staged_path(&target));
+    fs::write(&partial_file, "ne").unwrap();
+
+    let mut journal = Journal::new(
+        TransferMode::Move,
+        ConflictPolicy::Rename,
+        slice::from_ref(&planned),
+    );
+    journal.targets.insert(planned.file.clone(), target.clone());
+    resume(&settings, &journal);
+
+    assert!(!planned.file.exists());
+    assert!(!partial_file.exists());
+    assert_eq!(
+        fs::read_to_string(&planned.destination_file).unwrap(),
+        "old"
+    );
+    assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+    let docs = planned.destination_dir;
+    assert_eq!(fs::read_dir(docs).unwrap().count(), 2);
+}
+
+#[test]
+fn journal_without_a_policy_uses_the_settings() {
+    let root = TempDir::new().unwrap();
+    let (mut settings, planned) = planned(root.path());
+    fs::write(&planned.destination_file, "old").unwrap();
+    settings.conflict_policy = ConflictPolicy::Skip;
+
+    let journal: Journal = serde_yaml::from_str(&format!(
+        "mode: move\nmoves:\n  - file: {}\n    destination_dir: {}\n    destination_file: {}\n",
+        planned.file.display(),
+        planned.destination_dir.display(),
+        planned.destination_file.display()
+    ))
+    .unwrap();
+    assert_eq!(journal.conflict_policy, None);
+    resume(&settings, &journal);
+
+    assert_eq!(fs::read_to_string(&planned.file).unwrap(), "new");
+    assert_eq!(
+        fs::read_to_string(&planned.destination_file).unwrap(),
+        "old"
+    );
+}
@@ -0,0 +1,73 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Planning moves.
+
+use filesorter_core::planner::{self, PlannedMove};
+use filesorter_core::plugins::Plugins;
+use filesorter_core::settings::{Settings, SortPattern};
+
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+fn settings(root: &Path) -> Settings {
+    Settings::builder()
+        .source(root.join("src"))
+        .destination(root.join("dst"))
+        .recursive(true)
+        .pattern(
+            SortPattern::builder()
+                .extension("txt")
+                .dest("docs")
+                .build()
+                .unwrap(),
+        )
+        .build()
+}
+
+fn plan(settings: &Settings) -> Vec<PlannedMove> {
+    let plan = planner::plan(settings, &settings.sources[0], &Plugins::default(), &());
+    assert!(plan.failures.is_empty());
+    plan.moves
+}
+
+#[test]
+fn moves_to_one_destination_are_grouped() {
+    let root = TempDir::new().unwrap();
+    for dir in ["a", "b", "c"].iter() {
+        let dir = root.path().join("src").join(dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("report.txt"), dir.to_string_lossy().as_bytes()).unwrap();
+    }
+    fs::write(root.path().join("src").join("notes.txt"), "notes").unwrap();
+    // Only differs in case, which is the same file on case-insensitive filesystems
+    fs::write(root.path().join("src").join("a").join("NOTES.txt"), "NOTES").unwrap();
+
+    let moves = plan(&settings(root.path()));
+    assert_eq!(moves.len(), 5);
+    let moves: Vec<&PlannedMove> = moves.iter().collect();
+
+    let mut groups = planner::destination_groups(&moves);
+    for group in &mut groups {
+        group.sort_unstable();
+    }
+    groups.sort_by_key(|group| {
+        moves[group[0]]
+            .destination_file
+            .to_string_lossy()
+            .to_lowercase()
+    });
+
+    assert_eq!(groups.len(), 2);
+    assert_eq!(groups[0].len(), 2);
+    assert_eq!(groups[1].len(), 3);
+    for index in &groups[1] {
+        assert!(moves[*index].destination_file.ends_with("docs/report.txt"));
+    }
+    // Every move is in exactly one group
+    let mut indices: Vec<usize> = groups.concat();
+    indices.sort_unstable();
+    assert_eq!(indices, (0..moves.len()).collect::<Vec<_>>());
+}
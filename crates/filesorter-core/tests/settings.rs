@@ -0,0 +1,47 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Reading settings files from older versions.
+
+use filesorter_core::error::Error;
+use filesorter_core::migrate::{self, CURRENT_VERSION};
+use filesorter_core::settings::Settings;
+
+use serde_yaml::Value;
+use std::time::Duration;
+
+#[test]
+fn file_without_a_version_is_version_one() {
+    let mut value: Value = serde_yaml::from_str("destination: /tmp/sorted\n").unwrap();
+
+    assert_eq!(migrate::migrate(&mut value), Ok(1));
+    assert_eq!(value["version"], Value::from(CURRENT_VERSION));
+    assert_eq!(value["destination"], Value::from("/tmp/sorted"));
+}
+
+#[test]
+fn idle_seconds_is_read_as_min_age() {
+    let settings = Settings::from_reader("idle_seconds: 600\n".as_bytes()).unwrap();
+    assert_eq!(settings.min_age, Duration::from_secs(600));
+
+    // Zero meant no minimum age
+    let settings = Settings::from_reader("idle_seconds: 0\n".as_bytes()).unwrap();
+    assert_eq!(settings.min_age, Duration::ZERO);
+
+    // `min_age` wins when both are there
+    let settings = Settings::from_reader("idle_seconds: 600\nmin_age: 5m\n".as_bytes()).unwrap();
+    assert_eq!(settings.min_age, Duration::from_secs(300));
+}
+
+#[test]
+fn newer_version_is_refused() {
+    let text = format!("version: {}\n", CURRENT_VERSION + 1);
+    let mut value: Value = serde_yaml::from_str(&text).unwrap();
+    assert!(migrate::migrate(&mut value).is_err());
+
+    match Settings::from_reader(text.as_bytes()) {
+        Err(Error::Settings(message)) => assert!(message.contains("newer")),
+        other => panic!("expected a settings error, got {:?}", other.map(|_| ())),
+    }
+}
@@ -0,0 +1,240 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Python bindings of filesorter-core, so scripts can use the same rules as the `filesorter` command.
+//!
+//! ```python
+//! import filesorter
+//!
+//! settings = filesorter.Settings.load("filesorter.yaml")
+//! print(settings.evaluate("/home/user/Downloads/paper.pdf"))
+//! for planned in settings.plan().moves:
+//!     print(planned.file, "->", planned.destination)
+//! ```
+//!
+//! Nothing is moved, sorting is left to the command.
+
+use filesorter_core::planner;
+use filesorter_core::plugins::Plugins;
+use filesorter_core::rules::FileInfo;
+use filesorter_core::settings::{SortPattern, Source};
+
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use std::path::{Path, PathBuf};
+
+create_exception!(
+    filesorter,
+    FilesorterError,
+    PyException,
+    "A config that can't be loaded or a file that can't be planned."
+);
+
+fn to_py_err(e: filesorter_core::Error) -> PyErr {
+    FilesorterError::new_err(e.to_string())
+}
+
+/// A config along with the plugins of its plugins dir.
+#[pyclass(module = "filesorter", frozen)]
+struct Settings {
+    settings: filesorter_core::Settings,
+    plugins: Plugins,
+}
+
+impl Settings {
+    fn new(settings: filesorter_core::Settings) -> Self {
+        // Like the command, plugins that fail to load are left out
+        let plugins = Plugins::load(&settings.get_plugins_dir()).unwrap_or_default();
+
+        Settings { settings, plugins }
+    }
+
+    /// The configured source holding the file, or its parent dir.
+    fn source_of(&self, file: &Path) -> Source {
+        self.settings
+            .sources
+            .iter()
+            .find(|source| file.starts_with(&source.path))
+            .cloned()
+            .unwrap_or_else(|| Source::from(file.parent().unwrap_or(file).to_path_buf()))
+    }
+}
+
+#[pymethods]
+impl Settings {
+    /// Loads a config file with its includes and environment overrides.
+    #[staticmethod]
+    fn load(path: PathBuf) -> PyResult<Self> {
+        let mut settings = filesorter_core::Settings::load(&path).map_err(to_py_err)?;
+        settings.apply_env_overrides().map_err(to_py_err)?;
+
+        Ok(Settings::new(settings))
+    }
+
+    #[staticmethod]
+    fn from_yaml(yaml: &str) -> PyResult<Self> {
+        let settings =
+            filesorter_core::Settings::from_reader(yaml.as_bytes()).map_err(to_py_err)?;
+
+        Ok(Settings::new(settings))
+    }
+
+    #[getter]
+    fn sources(&self) -> Vec<PathBuf> {
+        self.settings
+            .sources
+            .iter()
+            .map(|source| source.path.clone())
+            .collect()
+    }
+
+    #[getter]
+    fn destination(&self) -> PathBuf {
+        self.settings.destination.clone()
+    }
+
+    /// The global rules and those of every source.
+    #[getter]
+    fn rules(&self) -> Vec<Rule> {
+        self.settings
+            .all_patterns()
+            .map(|pattern| Rule {
+                pattern: pattern.clone(),
+            })
+            .collect()
+    }
+
+    /// Where the rules would sort a file, `None` if no rule matches it.
+    fn evaluate(&self, py: Python<'_>, path: PathBuf) -> PyResult<Option<PlannedMove>> {
+        py.detach(|| {
+            let source = self.source_of(&path);
+            planner::plan_one(&self.settings, &source, &self.plugins, &path)
+        })
+        .map(|planned| planned.map(PlannedMove::from))
+        .map_err(to_py_err)
+    }
+
+    /// Plans sorting a dir, or every source without one.
+    #[pyo3(signature = (source=None))]
+    fn plan(&self, py: Python<'_>, source: Option<PathBuf>) -> Plan {
+        let sources = match source {
+            Some(path) => vec![self
+                .settings
+                .sources
+                .iter()
+                .find(|source| source.path == path)
+                .cloned()
+                .unwrap_or_else(|| Source::from(path))],
+            None => self.settings.sources.clone(),
+        };
+
+        py.detach(|| {
+            let mut plan = Plan::default();
+            for source in &sources {
+                let source_plan = planner::plan(&self.settings, source, &self.plugins, &());
+                plan.moves
+                    .extend(source_plan.moves.into_iter().map(PlannedMove::from));
+                plan.failures
+                    .extend(source_plan.failures.iter().map(|e| e.to_string()));
+            }
+            plan
+        })
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Settings(sources={:?}, destination={:?})",
+            self.sources(),
+            self.destination()
+        )
+    }
+}
+
+/// A sort rule of the config.
+#[pyclass(module = "filesorter", frozen)]
+struct Rule {
+    pattern: SortPattern,
+}
+
+#[pymethods]
+impl Rule {
+    #[getter]
+    fn destination(&self) -> &str {
+        &self.pattern.destination
+    }
+
+    #[getter]
+    fn extensions(&self) -> Vec<String> {
+        self.pattern.extensions.clone()
+    }
+
+    #[getter]
+    fn mime_types(&self) -> Vec<String> {
+        self.pattern.mime_types.clone()
+    }
+
+    #[getter]
+    fn priority(&self) -> i32 {
+        self.pattern.priority
+    }
+
+    /// The destination the rule picks for a file, with its `name_regex` captures filled in,
+    /// `None` if it doesn't match.
+    fn matches(&self, path: PathBuf) -> Option<String> {
+        self.pattern.matched_destination(&FileInfo::new(&path))
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Rule(destination={:?})", self.pattern.destination)
+    }
+}
+
+/// Where a file would be sorted and by which rule.
+#[pyclass(module = "filesorter", frozen, get_all, skip_from_py_object)]
+#[derive(Clone)]
+struct PlannedMove {
+    file: PathBuf,
+    destination: PathBuf,
+    rule: String,
+}
+
+impl From<planner::PlannedMove> for PlannedMove {
+    fn from(planned: planner::PlannedMove) -> Self {
+        PlannedMove {
+            file: planned.file,
+            destination: planned.destination_file,
+            rule: planned.rule,
+        }
+    }
+}
+
+#[pymethods]
+impl PlannedMove {
+    fn __repr__(&self) -> String {
+        format!(
+            "PlannedMove(file={:?}, destination={:?}, rule={:?})",
+            self.file, self.destination, self.rule
+        )
+    }
+}
+
+/// The moves planned for the sources, and why the files that couldn't be planned failed.
+#[pyclass(module = "filesorter", frozen, get_all)]
+#[derive(Default)]
+struct Plan {
+    moves: Vec<PlannedMove>,
+    failures: Vec<String>,
+}
+
+#[pymodule]
+fn filesorter(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Settings>()?;
+    m.add_class::<Rule>()?;
+    m.add_class::<PlannedMove>()?;
+    m.add_class::<Plan>()?;
+    m.add("FilesorterError", m.py().get_type::<FilesorterError>())?;
+
+    Ok(())
+}
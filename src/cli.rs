@@ -0,0 +1,535 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use crate::logger;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use filesorter_core::history;
+use filesorter_core::settings::Settings;
+use std::path::PathBuf;
+
+pub fn get_arg_matches() -> ArgMatches<'static> {
+    let app = App::new(crate_name!())
+        .version(crate_version!())
+        .author(crate_authors!())
+        .about(crate_description!())
+        .setting(AppSettings::ArgRequiredElseHelp)
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .help("Use an alternate settings file")
+                .takes_value(true)
+                .value_name("PATH")
+                .env("FILESORTER_CONFIG")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .help("Log more details, use twice for even more")
+                .multiple(true)
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
+                .help("Only log errors")
+                .conflicts_with("verbose")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("color")
+                .long("color")
+                .help("When to color the output, `auto` leaves it plain when piped or NO_COLOR is set")
+                .takes_value(true)
+                .value_name("WHEN")
+                .possible_values(logger::ColorChoice::VARIANTS)
+                .default_value("auto")
+                .global(true),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .short("P")
+                .long("profile")
+                .help("Use a named profile stored in the config dir")
+                .takes_value(true)
+                .value_name("NAME")
+                .validator(validate_profile_name)
+                .conflicts_with("config")
+                .global(true),
+        )
+        .subcommand(
+            SubCommand::with_name("init")
+                .about("(Re)Initialize configuration file")
+                .arg(
+                    Arg::with_name("source_dir")
+                        .help("Source directory")
+                        .index(1)
+                        .required(true)
+                        .value_name("SOURCE"),
+                )
+                .arg(
+                    Arg::with_name("destination_dir")
+                        .help("Destination directory")
+                        .index(2)
+                        .required(true)
+                        .value_name("DESTINATION"),
+                )
+                .arg(
+                    Arg::with_name("use_date_pattern")
+                        .short("d")
+                        .long("use-date-pattern")
+                        .help("Use date pattern")
+                        .takes_value(false),
+                )
+                .arg(
+                    Arg::with_name("date_pattern")
+                        .short("p")
+                        .long("date-pattern")
+                        .help("Date subdir pattern, or a layout: yearly, monthly, daily, iso-week")
+                        .takes_value(true)
+                        .default_value("%Y-%m-%d"), // 2020-01-01
+                )
+                .arg(
+                    Arg::with_name("template")
+                        .short("t")
+                        .long("template")
+                        .help("Start with the rules of a template instead of the general ones")
+                        .takes_value(true)
+                        .possible_values(&[
+                            "general",
+                            "photographer",
+                            "developer",
+                            "minimal",
+                            "media-server",
+                        ]),
+                )
+                .arg(
+                    Arg::with_name("empty")
+                        .long("empty")
+                        .help("Start without any rules")
+                        .takes_value(false)
+                        .conflicts_with("template"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("sort")
+                .about(
+                    "Sorting source directory to destination (config file should be initialized first!)",
+                )
+                .args(&run_args())
+                .arg(
+                    Arg::with_name("every")
+                        .long("every")
+                        .help("Keep sorting every DURATION (like 10m) in the foreground until stopped")
+                        .takes_value(true)
+                        .value_name("DURATION")
+                        .validator(validate_interval),
+                )
+                .arg(
+                    Arg::with_name("paths")
+                        .help("Sort just these files and dirs instead of the sources")
+                        .value_name("PATH")
+                        .multiple(true)
+                        .conflicts_with("every"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("watch")
+                .about("Sort the sources, then keep sorting new files as they appear")
+                .args(&run_args())
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .help("`ndjson` streams a JSON line per event to stdout, logging to stderr")
+                        .takes_value(true)
+                        .value_name("FORMAT")
+                        .possible_values(&["text", "ndjson"])
+                        .default_value("text"),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("Take over the pid file even if the pid in it is running"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("dedup")
+                .about("Replace identical files in the destination with hardlinks to a single copy")
+                .arg(
+                    Arg::with_name("dir")
+                        .help("Dir to deduplicate instead of the destinations of the settings")
+                        .value_name("DIR"),
+                )
+                .arg(
+                    Arg::with_name("dry_run")
+                        .long("dry-run")
+                        .help("Only report what would be linked"),
+                )
+                .arg(jobs_arg())
+                .subcommand(
+                    SubCommand::with_name("report")
+                        .about("List the groups of identical files and the space linking them would free")
+                        .arg(
+                            Arg::with_name("dir")
+                                .help("Dir to scan instead of the destinations of the settings")
+                                .value_name("DIR"),
+                        )
+                        .arg(
+                            Arg::with_name("sources")
+                                .long("sources")
+                                .help("Scan the sources of the settings instead of the destinations")
+                                .conflicts_with("dir"),
+                        )
+                        .arg(
+                            Arg::with_name("output")
+                                .long("output")
+                                .help("`json` prints the report as JSON, for scripts")
+                                .takes_value(true)
+                                .value_name("FORMAT")
+                                .possible_values(&["table", "json"])
+                                .default_value("table"),
+                        )
+                        .arg(jobs_arg()),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Rehash sorted files against their manifests and check an interrupted run")
+                .arg(
+                    Arg::with_name("dir")
+                        .help("Dir to check instead of the destinations of the settings")
+                        .value_name("DIR"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .help("`json` prints the report as JSON, for scripts")
+                        .takes_value(true)
+                        .value_name("FORMAT")
+                        .possible_values(&["text", "json"])
+                        .default_value("text"),
+                )
+                .arg(jobs_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("history")
+                .about("List the files sorted so far, latest last")
+                .arg(
+                    Arg::with_name("since")
+                        .long("since")
+                        .help("Only what happened since a date (2024-05-14), a time (2024-05-14 18:30) or a duration ago (3d)")
+                        .takes_value(true)
+                        .value_name("TIME")
+                        .validator(validate_time),
+                )
+                .arg(
+                    Arg::with_name("until")
+                        .long("until")
+                        .help("Only what happened before TIME, like --since")
+                        .takes_value(true)
+                        .value_name("TIME")
+                        .validator(validate_time),
+                )
+                .arg(
+                    Arg::with_name("rule")
+                        .long("rule")
+                        .help("Only files of the rule with this destination")
+                        .takes_value(true)
+                        .value_name("DESTINATION"),
+                )
+                .arg(
+                    Arg::with_name("source")
+                        .long("source")
+                        .help("Only files from this source or a dir in it")
+                        .takes_value(true)
+                        .value_name("DIR"),
+                )
+                .arg(
+                    Arg::with_name("limit")
+                        .long("limit")
+                        .help("Show at most the latest N files")
+                        .takes_value(true)
+                        .value_name("N"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .help("`json` prints the files as JSON, for scripts")
+                        .takes_value(true)
+                        .value_name("FORMAT")
+                        .possible_values(&["table", "json"])
+                        .default_value("table"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("where")
+                .about("Find where files of this name were sorted to, from the history")
+                .arg(
+                    Arg::with_name("name")
+                        .help("Name the file had before it was sorted, `*` and `?` match any characters")
+                        .value_name("NAME")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("doctor")
+                .about("Check the settings, sources, destinations and other runs, for bug reports too"),
+        )
+        .subcommand(
+            SubCommand::with_name("clean")
+                .about("Remove the empty dirs of the sources, the sources themselves are kept")
+                .arg(
+                    Arg::with_name("dry_run")
+                        .long("dry-run")
+                        .help("Only list the dirs that would be removed"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("stats")
+                .about("Show how many files each category of the destinations holds and their size")
+                .arg(
+                    Arg::with_name("dir")
+                        .help("Dir to look at instead of the destinations of the settings")
+                        .value_name("DIR"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .help("`json` prints the stats as JSON, for scripts")
+                        .takes_value(true)
+                        .value_name("FORMAT")
+                        .possible_values(&["table", "json"])
+                        .default_value("table"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("explain")
+                .about("Show which rule a file matches, where it would go and why")
+                .arg(
+                    Arg::with_name("file")
+                        .help("File to run through the rules")
+                        .value_name("FILE")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("status")
+                .about("Show how the running `watch` is doing")
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .help("`json` prints the status as JSON, for scripts")
+                        .takes_value(true)
+                        .value_name("FORMAT")
+                        .possible_values(&["text", "json"])
+                        .default_value("text"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("pause")
+                .about("Hold off the running `watch` until `resume`, changes pile up meanwhile"),
+        )
+        .subcommand(
+            SubCommand::with_name("resume").about("Sort again, starting with what changed while paused"),
+        )
+        .subcommand(
+            SubCommand::with_name("config")
+                .about("Configuration file management")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("validate")
+                        .about("Check the settings file and report any problems"),
+                )
+                .subcommand(
+                    SubCommand::with_name("migrate")
+                        .about("Upgrade the settings file to the current format version"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("rules")
+                .about("Manage sort rules")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(SubCommand::with_name("list").about("List sort rules"))
+                .subcommand(
+                    SubCommand::with_name("add")
+                        .about("Add extension(s) to the rule with the given destination")
+                        .arg(
+                            Arg::with_name("extension")
+                                .short("e")
+                                .long("ext")
+                                .help("File extension, e.g. webp")
+                                .takes_value(true)
+                                .value_name("EXT")
+                                .multiple(true)
+                                .number_of_values(1)
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::with_name("destination")
+                                .short("d")
+                                .long("dest")
+                                .help("Destination subdir, e.g. images")
+                                .takes_value(true)
+                                .value_name("DEST")
+                                .required(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("remove")
+                        .about("Remove extension(s) from all rules")
+                        .arg(
+                            Arg::with_name("extension")
+                                .short("e")
+                                .long("ext")
+                                .help("File extension, e.g. webp")
+                                .takes_value(true)
+                                .value_name("EXT")
+                                .multiple(true)
+                                .number_of_values(1)
+                                .required(true),
+                        ),
+                ),
+        );
+    #[cfg(windows)]
+    let app = app.subcommand(
+        SubCommand::with_name("service")
+            .about("Run `watch` as a Windows service, started at boot")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                SubCommand::with_name("install")
+                    .about("Register the service, sorting by the current settings file"),
+            )
+            .subcommand(SubCommand::with_name("uninstall").about("Stop and remove the service"))
+            .subcommand(
+                SubCommand::with_name("run")
+                    .about("Run as the service, for the service control manager to start"),
+            ),
+    );
+
+    #[cfg(target_os = "macos")]
+    let app = app.subcommand(
+        SubCommand::with_name("daemon")
+            .about("Run `watch` in the background, started at login")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(
+                SubCommand::with_name("install")
+                    .about("Install and load an agent sorting by the current settings file")
+                    .arg(launchd_arg()),
+            )
+            .subcommand(
+                SubCommand::with_name("uninstall")
+                    .about("Unload and remove the agent")
+                    .arg(launchd_arg()),
+            ),
+    );
+
+    app.get_matches()
+}
+
+#[cfg(target_os = "macos")]
+fn launchd_arg() -> Arg<'static, 'static> {
+    Arg::with_name("launchd")
+        .long("launchd")
+        .help("As a LaunchAgent of the user")
+        .required(true)
+}
+
+fn jobs_arg() -> Arg<'static, 'static> {
+    Arg::with_name("jobs")
+        .short("j")
+        .long("jobs")
+        .help("Number of parallel jobs (0 = number of CPUs)")
+        .takes_value(true)
+        .value_name("N")
+        .default_value("0")
+}
+
+/// Options shared by the commands that sort files.
+fn run_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        jobs_arg(),
+        Arg::with_name("resume")
+            .long("resume")
+            .help("Finish the moves of an interrupted run, `sort` stops there"),
+        Arg::with_name("wait")
+            .long("wait")
+            .help("Wait for another running filesorter to finish instead of exiting"),
+        Arg::with_name("no_trash")
+            .long("no-trash")
+            .help("Delete replaced and duplicate files instead of sending them to the trash"),
+        Arg::with_name("hidden")
+            .long("hidden")
+            .help("Also sort hidden files, like `include_hidden` in the settings"),
+        Arg::with_name("source")
+            .long("source")
+            .help("Sort DIR instead of the sources of the settings, can be given more than once")
+            .takes_value(true)
+            .value_name("DIR")
+            .multiple(true)
+            .number_of_values(1),
+        Arg::with_name("destination")
+            .long("dest")
+            .help("Sort into DIR instead of the destination of the settings")
+            .takes_value(true)
+            .value_name("DIR"),
+        Arg::with_name("no_config")
+            .long("no-config")
+            .help("Ignore the settings file and use the built-in rules, needs --dest")
+            .requires("destination"),
+        Arg::with_name("prune_empty")
+            .long("prune-empty")
+            .help("Remove the source dirs the run leaves empty"),
+        Arg::with_name("keep_going")
+            .long("keep-going")
+            .help("Keep sorting other files when one fails (default)")
+            .overrides_with("fail_fast"),
+        Arg::with_name("fail_fast")
+            .long("fail-fast")
+            .help("Stop at the first file that fails")
+            .overrides_with("keep_going"),
+    ]
+}
+
+fn validate_interval(interval: String) -> Result<(), String> {
+    match humantime::parse_duration(&interval) {
+        Ok(interval) if interval.is_zero() => Err(String::from("interval must be longer than 0")),
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn validate_time(time: String) -> Result<(), String> {
+    history::parse_time(&time).map(|_| ())
+}
+
+fn validate_profile_name(name: String) -> Result<(), String> {
+    let is_valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+
+    if is_valid {
+        Ok(())
+    } else {
+        Err(String::from(
+            "profile name may only contain letters, digits, '-' and '_'",
+        ))
+    }
+}
+
+/// The settings file to use. `--profile` is checked first: `config` may come from
+/// `FILESORTER_CONFIG`, which clap doesn't count as a conflict.
+pub fn get_settings_path(matches: &ArgMatches) -> PathBuf {
+    if let Some(profile) = matches.value_of("profile") {
+        Settings::get_profile_path(profile)
+    } else if let Some(path) = matches.value_of("config") {
+        PathBuf::from(path)
+    } else {
+        Settings::get_settings_path()
+    }
+}
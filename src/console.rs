@@ -0,0 +1,131 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use filesorter_core::events::{EventSink, SkipReason};
+use filesorter_core::executor::{self, Report};
+use filesorter_core::planner::PlannedMove;
+use filesorter_core::settings::Settings;
+use filesorter_core::size::ByteSize;
+use filesorter_core::targets;
+use filesorter_core::transfer::{CleanupAction, DedupPolicy, TransferMode};
+
+use log::Level;
+use std::path::Path;
+
+/// Logs what happened to each file.
+pub struct ConsoleSink {
+    verb: &'static str,
+    duplicate_action: &'static str,
+    /// Whether files cleaned up with `trash` go to the trash
+    trash: bool,
+    /// The progress bar already showed progress, so single files are only logged with `-v`
+    progress: bool,
+    /// Too many files to list, so they're only logged with `-v` and summed up if any were sorted
+    summarize: bool,
+}
+
+impl ConsoleSink {
+    pub fn new(settings: &Settings, progress: bool) -> Self {
+        let verb = match settings.mode {
+            TransferMode::Move => "Moved",
+            TransferMode::Copy => "Copied",
+        };
+        let duplicate_action = if !settings.dedup.removes(settings.mode) {
+            "Skipped"
+        } else if settings.dedup == DedupPolicy::Trash && settings.trash {
+            "Trashed"
+        } else {
+            "Removed"
+        };
+
+        ConsoleSink {
+            verb,
+            duplicate_action,
+            trash: settings.trash,
+            progress: executor::shows_progress(progress),
+            summarize: false,
+        }
+    }
+
+    /// Sums the run up instead of logging every file, like with the progress bar.
+    pub fn summarized(mut self) -> Self {
+        self.summarize = true;
+        self
+    }
+}
+
+impl EventSink for ConsoleSink {
+    fn on_move(&self, planned: &PlannedMove, destination_file: &Path) {
+        let level = if self.progress || self.summarize {
+            Level::Debug
+        } else {
+            Level::Info
+        };
+        log!(
+            target: targets::SORTED,
+            level,
+            "{} {} to {}",
+            self.verb,
+            planned.file.display(),
+            destination_file.display()
+        );
+    }
+
+    fn on_skip(&self, planned: &PlannedMove, reason: SkipReason) {
+        match reason {
+            SkipReason::Exists => info!(
+                target: targets::SKIPPED,
+                "Skipped {}, {} already exists",
+                planned.file.display(),
+                planned.destination_file.display()
+            ),
+            SkipReason::Duplicate => info!(
+                target: targets::SKIPPED,
+                "{} {}, identical to {}",
+                self.duplicate_action,
+                planned.file.display(),
+                planned.destination_file.display()
+            ),
+        }
+    }
+
+    fn on_clean_up(&self, planned: &PlannedMove) {
+        let level = if self.progress || self.summarize {
+            Level::Debug
+        } else {
+            Level::Info
+        };
+        let action = match planned.action {
+            Some(CleanupAction::Trash) if self.trash => "Trashed",
+            _ => "Deleted",
+        };
+        log!(
+            target: targets::SORTED,
+            level,
+            "{} {}",
+            action,
+            planned.file.display()
+        );
+    }
+
+    fn on_finish(&self, report: &Report) {
+        if self.progress || (self.summarize && report.sorted > 0) {
+            info!("{} {} file(s)", self.verb, report.sorted);
+        }
+        if (self.progress || self.summarize) && report.cleaned_up > 0 {
+            info!("Cleaned up {} file(s)", report.cleaned_up);
+        }
+        if report.duplicates > 0 {
+            info!(
+                "{} {} duplicate(s){}",
+                self.duplicate_action,
+                report.duplicates,
+                match report.duplicate_bytes {
+                    0 => String::new(),
+                    bytes => format!(", freeing {}", ByteSize(bytes)),
+                }
+            );
+        }
+    }
+}
@@ -0,0 +1,222 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! The control socket `filesorter status`, `pause` and `resume` talk to a running `watch` over,
+//! a unix socket in the runtime dir, or a named pipe on Windows.
+
+use filesorter_core::metrics::{self, Snapshot};
+use filesorter_core::size::ByteSize;
+use filesorter_core::{Error, Result};
+
+use chrono::{Local, TimeZone, Utc};
+use interprocess::local_socket::prelude::*;
+use interprocess::local_socket::{ListenerOptions, Name, Stream};
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// Set while sorting is paused, changes pile up meanwhile and are sorted on resume.
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// What a client asks for, one per connection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    Status,
+    Pause,
+    Resume,
+}
+
+impl Command {
+    fn name(self) -> &'static str {
+        match self {
+            Command::Status => "status",
+            Command::Pause => "pause",
+            Command::Resume => "resume",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        [Command::Status, Command::Pause, Command::Resume]
+            .iter()
+            .copied()
+            .find(|command| command.name() == name)
+    }
+}
+
+/// What `filesorter status` shows, also the answer to every command.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Status {
+    pub pid: u32,
+    /// When the daemon started, as a Unix timestamp
+    pub started: i64,
+    pub sources: usize,
+    pub paused: bool,
+    #[serde(flatten)]
+    pub metrics: Snapshot,
+}
+
+/// Whether sorting is paused.
+pub fn paused() -> bool {
+    PAUSED.load(Ordering::SeqCst)
+}
+
+fn set_paused(paused: bool) {
+    if PAUSED.swap(paused, Ordering::SeqCst) != paused {
+        if paused {
+            info!("Paused, changes are sorted once resumed");
+        } else {
+            info!("Resumed");
+        }
+    }
+}
+
+/// Answers commands in the background, and pauses on SIGUSR1 and resumes on SIGUSR2 on Unix. A
+/// socket left behind by a daemon that died is replaced, the run lock already made sure it isn't
+/// running.
+pub fn serve(sources: usize) -> Result<()> {
+    let started = Utc::now().timestamp();
+    let listener = ListenerOptions::new()
+        .name(name()?)
+        .try_overwrite(true)
+        .create_sync()
+        .map_err(|e| Error::Settings(format!("Failed to listen for commands: {}", e)))?;
+    #[cfg(unix)]
+    handle_signals()?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = respond(stream, started, sources) {
+                debug!("Failed to answer a command: {}", e);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn handle_signals() -> Result<()> {
+    use signal_hook::consts::{SIGUSR1, SIGUSR2};
+
+    // Only stores to an atomic, which is all a signal handler may do
+    unsafe {
+        signal_hook::low_level::register(SIGUSR1, || PAUSED.store(true, Ordering::SeqCst))
+            .and_then(|_| {
+                signal_hook::low_level::register(SIGUSR2, || PAUSED.store(false, Ordering::SeqCst))
+            })
+            .map_err(|e| Error::Settings(format!("Failed to handle signals: {}", e)))?;
+    }
+
+    Ok(())
+}
+
+fn respond(stream: Stream, started: i64, sources: usize) -> io::Result<()> {
+    let mut stream = BufReader::new(stream);
+    let mut request = String::new();
+    stream.read_line(&mut request)?;
+
+    match Command::parse(request.trim()) {
+        Some(Command::Status) => {}
+        Some(Command::Pause) => set_paused(true),
+        Some(Command::Resume) => set_paused(false),
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown command '{}'", request.trim()),
+            ))
+        }
+    }
+
+    let status = Status {
+        pid: std::process::id(),
+        started,
+        sources,
+        paused: paused(),
+        metrics: metrics::snapshot(),
+    };
+    let stream = stream.get_mut();
+    serde_json::to_writer(&mut *stream, &status)?;
+    stream.flush()
+}
+
+/// Sends the command to the running daemon, returning its status once it's done.
+pub fn send(command: Command) -> Result<Status> {
+    let mut stream = Stream::connect(name()?).map_err(|e| {
+        Error::Settings(format!(
+            "No filesorter watch seems to be running ({}), start one with `filesorter watch`",
+            e
+        ))
+    })?;
+
+    let mut response = String::new();
+    writeln!(stream, "{}", command.name())
+        .and_then(|()| stream.flush())
+        .and_then(|()| stream.read_to_string(&mut response))
+        .map_err(|e| Error::Settings(format!("Failed to {}: {}", command.name(), e)))?;
+
+    serde_json::from_str(&response)
+        .map_err(|e| Error::Settings(format!("Failed to read the status: {}", e)))
+}
+
+pub fn print(status: &Status) {
+    let now = Utc::now().timestamp();
+    let uptime = Duration::from_secs((now - status.started).max(0) as u64);
+    let metrics = &status.metrics;
+
+    println!(
+        "filesorter watch is {} (pid {}), up {}",
+        if status.paused { "paused" } else { "running" },
+        status.pid,
+        humantime::format_duration(uptime)
+    );
+    println!("Sources: {}", status.sources);
+    println!(
+        "Sorted: {} file(s), {} in {} run(s)",
+        metrics.sorted,
+        ByteSize(metrics.bytes),
+        metrics.runs
+    );
+    if metrics.runs > 0 {
+        println!("Last run: {}", format_time(metrics.last_run));
+    }
+    println!("Queued: {} file(s)", metrics.queue_depth);
+    println!("Failed: {} file(s)", metrics.errors);
+    if !metrics.last_errors.is_empty() {
+        println!("Last errors:");
+        for error in &metrics.last_errors {
+            println!("  {} {}", format_time(error.time), error.message);
+        }
+    }
+}
+
+fn format_time(timestamp: i64) -> String {
+    Local
+        .timestamp_opt(timestamp, 0)
+        .single()
+        .map_or_else(String::new, |time| {
+            time.format("%Y-%m-%d %H:%M:%S").to_string()
+        })
+}
+
+#[cfg(windows)]
+fn name() -> Result<Name<'static>> {
+    use interprocess::local_socket::GenericNamespaced;
+
+    "filesorter"
+        .to_ns_name::<GenericNamespaced>()
+        .map_err(|e| Error::Settings(format!("Invalid control pipe name: {}", e)))
+}
+
+#[cfg(not(windows))]
+fn name() -> Result<Name<'static>> {
+    use filesorter_core::settings::Settings;
+    use interprocess::local_socket::GenericFilePath;
+
+    let path = Settings::get_runtime_dir().join("filesorter.sock");
+    path.clone()
+        .to_fs_name::<GenericFilePath>()
+        .map_err(|e| Error::io(&path, e))
+}
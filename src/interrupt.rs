@@ -0,0 +1,25 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use filesorter_core::error::{self, Error, Result};
+use filesorter_core::executor;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// On SIGINT or SIGTERM, lets the files being transferred finish and starts no more, so none is
+/// left half-moved and the run still reports what it did, then calls `on_interrupt`. A second
+/// signal stops right away.
+pub fn finish_transfers(on_interrupt: impl Fn() + Send + 'static) -> Result<()> {
+    let interrupted = AtomicBool::new(false);
+    ctrlc::set_handler(move || {
+        if interrupted.swap(true, Ordering::SeqCst) {
+            warn!("Stopping right away");
+            std::process::exit(error::EXIT_FATAL);
+        }
+        info!("Finishing the files in progress, interrupt again to stop right away");
+        executor::interrupt();
+        on_interrupt();
+    })
+    .map_err(|e| Error::Settings(format!("Failed to handle signals: {}", e)))
+}
@@ -0,0 +1,100 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use crate::console::ConsoleSink;
+use crate::control;
+use crate::logger;
+use crate::metrics_server;
+use crate::webhook;
+
+use filesorter_core::error::{self, Error, Result};
+use filesorter_core::lock;
+use filesorter_core::metrics;
+use filesorter_core::sorter::{self, SortEngine, SortOptions};
+
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+
+/// Share of the interval added at random to each wait, so machines started together don't sort together.
+const JITTER: f64 = 0.1;
+
+/// Sorts the sources every `interval`, until interrupted. A run in progress when SIGTERM or Ctrl-C
+/// arrives is finished first, a second signal stops right away.
+pub fn sort_every(settings_path: &Path, options: &SortOptions, interval: Duration) -> Result<()> {
+    let _lock = lock::RunLock::acquire(options.wait)?;
+
+    let settings = sorter::load_settings(settings_path, options)?;
+    logger::configure(&settings)?;
+    if let Some(failures) = sorter::check_journal(&settings, options.resume)? {
+        if !failures.is_empty() {
+            warn!("{} file(s) of the interrupted run failed", failures.len());
+        }
+    }
+
+    if let Some(address) = settings.metrics_address {
+        metrics_server::serve(address)?;
+    }
+    control::serve(settings.sources.len())?;
+
+    let (sender, receiver) = mpsc::channel();
+    let stopping = AtomicBool::new(false);
+    ctrlc::set_handler(move || {
+        if stopping.swap(true, Ordering::SeqCst) {
+            warn!("Stopping right away");
+            std::process::exit(error::EXIT_FATAL);
+        }
+        info!("Stopping after the current run, interrupt again to stop right away");
+        let _ = sender.send(());
+    })
+    .map_err(|e| Error::Settings(format!("Failed to handle signals: {}", e)))?;
+
+    let engine = SortEngine::new(settings).options(options.clone());
+    let settings = engine.settings();
+
+    info!(
+        "Sorting {} source(s) every {}",
+        settings.sources.len(),
+        humantime::format_duration(interval)
+    );
+
+    loop {
+        if control::paused() {
+            info!("Paused, skipping this run");
+        } else {
+            run(&engine, options);
+        }
+
+        let wait = interval.mul_f64(1.0 + fastrand::f64() * JITTER);
+        debug!(
+            "Next run in {}",
+            humantime::format_duration(Duration::from_secs(wait.as_secs()))
+        );
+        match receiver.recv_timeout(wait) {
+            Err(RecvTimeoutError::Timeout) => {}
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    info!("Stopped");
+
+    Ok(())
+}
+
+fn run(engine: &SortEngine, options: &SortOptions) {
+    let settings = engine.settings();
+    match engine.run(ConsoleSink::new(settings, options.progress)) {
+        Ok(report) => {
+            // Runs with nothing to sort have nothing to report
+            if report.sorted > 0 || !report.failures.is_empty() {
+                webhook::send(settings, &report);
+            }
+        }
+        Err(e) => {
+            error!("{}", e);
+            metrics::record_error(&e);
+        }
+    }
+}
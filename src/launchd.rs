@@ -0,0 +1,188 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Runs `watch` as a launchd agent on macOS, started at login.
+
+use filesorter_core::error::{Error, Result};
+use filesorter_core::settings::Settings;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Also the name of the plist, like Apple asks.
+const LABEL: &str = "com.elxreno.filesorter";
+
+/// Folders macOS keeps private, apps need to be allowed in System Settings to read them.
+const PROTECTED_FOLDERS: &[&str] = &["Desktop", "Documents", "Downloads"];
+
+/// Writes a LaunchAgent running `watch` with these settings and loads it. A previous one is replaced.
+pub fn install(settings_path: &Path) -> Result<()> {
+    let settings_path = settings_path
+        .canonicalize()
+        .map_err(|e| Error::io(settings_path, e))?;
+    let settings = Settings::load(&settings_path)?;
+    let executable_path = std::env::current_exe()
+        .map_err(|e| Error::Settings(format!("Failed to find the filesorter executable: {}", e)))?;
+    let log_path = Settings::get_data_dir().join("launchd.log");
+
+    if let Some(dir) = log_path.parent() {
+        fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+    }
+
+    let plist_path = plist_path()?;
+    if plist_path.exists() {
+        // launchd keeps the old one until it's booted out
+        bootout(&plist_path);
+    }
+    if let Some(dir) = plist_path.parent() {
+        fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+    }
+    fs::write(
+        &plist_path,
+        plist(&executable_path, &settings_path, &log_path),
+    )
+    .map_err(|e| Error::io(&plist_path, e))?;
+    launchctl(&["bootstrap", &domain(), &plist_path.to_string_lossy()])?;
+
+    info!(
+        "Installed {}, watch runs at login with {} and logs to {}",
+        plist_path.display(),
+        settings_path.display(),
+        log_path.display()
+    );
+    warn_protected(&settings, &executable_path);
+
+    Ok(())
+}
+
+/// Unloads the LaunchAgent and removes it.
+pub fn uninstall() -> Result<()> {
+    let plist_path = plist_path()?;
+    if !plist_path.exists() {
+        return Err(Error::Settings(format!(
+            "No launchd agent installed at {}",
+            plist_path.display()
+        )));
+    }
+
+    bootout(&plist_path);
+    fs::remove_file(&plist_path).map_err(|e| Error::io(&plist_path, e))?;
+    info!("Uninstalled {}", plist_path.display());
+
+    Ok(())
+}
+
+fn plist_path() -> Result<PathBuf> {
+    let home =
+        std::env::var_os("HOME").ok_or_else(|| Error::Settings(String::from("HOME isn't set")))?;
+
+    Ok(PathBuf::from(home)
+        .join("Library/LaunchAgents")
+        .join(format!("{}.plist", LABEL)))
+}
+
+/// The GUI session of the user, where agents that read their files belong.
+fn domain() -> String {
+    format!("gui/{}", unsafe { libc::getuid() })
+}
+
+/// Kept alive unless it exits cleanly, at background priority.
+fn plist(executable_path: &Path, settings_path: &Path, log_path: &Path) -> String {
+    let string = |path: &Path| format!("<string>{}</string>", escape(&path.to_string_lossy()));
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{label}</string>
+    <key>ProgramArguments</key>
+    <array>
+        {executable}
+        <string>--config</string>
+        {settings}
+        <string>watch</string>
+    </array>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <dict>
+        <key>SuccessfulExit</key>
+        <false/>
+    </dict>
+    <key>ProcessType</key>
+    <string>Background</string>
+    <key>StandardOutPath</key>
+    {log}
+    <key>StandardErrorPath</key>
+    {log}
+</dict>
+</plist>
+"#,
+        label = LABEL,
+        executable = string(executable_path),
+        settings = string(settings_path),
+        log = string(log_path),
+    )
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn launchctl(args: &[&str]) -> Result<()> {
+    let output = Command::new("launchctl")
+        .args(args)
+        .output()
+        .map_err(|e| Error::Settings(format!("Failed to run launchctl: {}", e)))?;
+    if output.status.success() {
+        return Ok(());
+    }
+
+    Err(Error::Settings(format!(
+        "launchctl {} failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr).trim()
+    )))
+}
+
+/// Unloads the agent, it may not be loaded.
+fn bootout(plist_path: &Path) {
+    if let Err(e) = launchctl(&["bootout", &domain(), &plist_path.to_string_lossy()]) {
+        debug!("{}", e);
+    }
+}
+
+/// Agents can't answer the prompt macOS shows before an app reads a protected folder, they're
+/// denied instead, so tell how to allow it up front.
+fn warn_protected(settings: &Settings, executable_path: &Path) {
+    let home = match std::env::var_os("HOME") {
+        Some(home) => PathBuf::from(home),
+        None => return,
+    };
+    let mut paths: Vec<PathBuf> = settings
+        .sources
+        .iter()
+        .map(|source| source.path.clone())
+        .collect();
+    paths.extend(settings.destination_roots());
+
+    let protected = paths.iter().filter(|path| {
+        PROTECTED_FOLDERS
+            .iter()
+            .any(|folder| path.starts_with(home.join(folder)))
+    });
+    for path in protected {
+        warn!(
+            "{} is in a folder macOS keeps private, allow {} under Full Disk Access in \
+             System Settings > Privacy & Security or the agent can't read it",
+            path.display(),
+            executable_path.display()
+        );
+    }
+}
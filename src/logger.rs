@@ -0,0 +1,288 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use chrono::Local;
+use filesorter_core::settings::{LogFile, Settings};
+use filesorter_core::{targets, Error, Result};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// When the terminal output is colored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// When writing to a terminal and `NO_COLOR` isn't set
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub const VARIANTS: &'static [&'static str] = &["auto", "always", "never"];
+
+    fn enabled(self, terminal: bool) -> bool {
+        match self {
+            ColorChoice::Auto => {
+                terminal && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+            }
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+impl std::str::FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("Unknown color choice '{}'", s)),
+        }
+    }
+}
+
+/// The ANSI color of a record on the terminal, if any.
+fn color(record: &Record) -> Option<&'static str> {
+    match record.level() {
+        Level::Error => Some("31"),
+        Level::Warn => Some("33"),
+        _ if record.target() == targets::SORTED => Some("32"),
+        _ if record.target() == targets::SKIPPED => Some("33"),
+        _ => None,
+    }
+}
+
+/// The open log file and how much has been written to it.
+struct FileSink {
+    config: LogFile,
+    file: File,
+    size: u64,
+}
+
+impl FileSink {
+    fn open(config: &LogFile) -> io::Result<Self> {
+        if let Some(parent) = config.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.path)?;
+        let size = file.metadata()?.len();
+
+        Ok(FileSink {
+            config: config.clone(),
+            file,
+            size,
+        })
+    }
+
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.config.max_size.0 {
+            self.rotate()?;
+        }
+
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+
+        Ok(())
+    }
+
+    /// Shifts `<path>.N` to `<path>.N+1`, dropping the oldest, and starts a new file.
+    fn rotate(&mut self) -> io::Result<()> {
+        let path = &self.config.path;
+        if self.config.max_files == 0 {
+            fs::remove_file(path)?;
+        } else {
+            for n in (1..self.config.max_files).rev() {
+                let from = rotated_path(path, n);
+                if from.exists() {
+                    fs::rename(&from, rotated_path(path, n + 1))?;
+                }
+            }
+            fs::rename(path, rotated_path(path, 1))?;
+        }
+
+        *self = FileSink::open(&self.config)?;
+
+        Ok(())
+    }
+}
+
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    PathBuf::from(format!("{}.{}", path.display(), n))
+}
+
+/// Prints timestamped records of filesorter itself, warnings and errors to stderr and the rest to stdout,
+/// or sends them to the journal, and copies them to the log file if there's one.
+struct Logger {
+    file: Mutex<Option<FileSink>>,
+    journald: AtomicBool,
+    color_stdout: AtomicBool,
+    color_stderr: AtomicBool,
+    /// Leaves stdout to the event stream
+    stderr_only: AtomicBool,
+}
+
+static LOGGER: Logger = Logger {
+    file: Mutex::new(None),
+    journald: AtomicBool::new(false),
+    color_stdout: AtomicBool::new(false),
+    color_stderr: AtomicBool::new(false),
+    stderr_only: AtomicBool::new(false),
+};
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level() && metadata.target().starts_with(crate_name!())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let timestamp = Local::now().format("%Y-%m-%d %H:%M:%S");
+        let message = format!("{:<5} {}", record.level(), record.args());
+        let line = format!("{} {}\n", timestamp, message);
+
+        // Losing a log line mustn't abort a run halfway, so write errors are ignored
+        if let Some(file) = self.file.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            let _ = file.write(&line);
+        }
+
+        if self.journald.load(Ordering::Relaxed) {
+            let _ = journald::send(record);
+        } else {
+            let to_stderr =
+                record.level() <= Level::Warn || self.stderr_only.load(Ordering::Relaxed);
+            let colored = if to_stderr {
+                &self.color_stderr
+            } else {
+                &self.color_stdout
+            };
+            let line = match color(record) {
+                Some(code) if colored.load(Ordering::Relaxed) => {
+                    format!("{} \x1b[{}m{}\x1b[0m\n", timestamp, code, message)
+                }
+                _ => line,
+            };
+
+            let _ = if to_stderr {
+                io::stderr().write_all(line.as_bytes())
+            } else {
+                io::stdout().write_all(line.as_bytes())
+            };
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = self.file.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            let _ = file.file.flush();
+        }
+    }
+}
+
+/// Sets up logging: info by default, debug and trace with one or more `-v`, only errors with `-q`.
+pub fn init(verbosity: u64, quiet: bool, color: ColorChoice) {
+    let level = if quiet {
+        LevelFilter::Error
+    } else {
+        match verbosity {
+            0 => LevelFilter::Info,
+            1 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    };
+
+    LOGGER
+        .color_stdout
+        .store(color.enabled(io::stdout().is_terminal()), Ordering::Relaxed);
+    LOGGER
+        .color_stderr
+        .store(color.enabled(io::stderr().is_terminal()), Ordering::Relaxed);
+
+    log::set_logger(&LOGGER).expect("Expected logger to be set once");
+    log::set_max_level(level);
+}
+
+/// Logs everything to stderr, for when stdout carries something else.
+pub fn log_to_stderr() {
+    LOGGER.stderr_only.store(true, Ordering::Relaxed);
+}
+
+/// Starts logging to the `log_file` and the journal, as the settings ask.
+pub fn configure(settings: &Settings) -> Result<()> {
+    if let Some(log_file) = &settings.log_file {
+        let sink = FileSink::open(log_file).map_err(|e| Error::io(&log_file.path, e))?;
+        *LOGGER.file.lock().unwrap_or_else(|e| e.into_inner()) = Some(sink);
+    }
+
+    if settings.journald {
+        if !journald::AVAILABLE {
+            return Err(Error::Settings(String::from(
+                "journald logging needs filesorter built with the `journald` feature on Linux",
+            )));
+        }
+        LOGGER.journald.store(true, Ordering::Relaxed);
+    }
+
+    Ok(())
+}
+
+/// Writes records with the journal's native protocol, keeping their priority.
+#[cfg(all(feature = "journald", target_os = "linux"))]
+mod journald {
+    use log::{Level, Record};
+    use std::io;
+    use std::os::unix::net::UnixDatagram;
+
+    pub const AVAILABLE: bool = true;
+
+    const SOCKET: &str = "/run/systemd/journal/socket";
+
+    pub fn send(record: &Record) -> io::Result<()> {
+        let priority = match record.level() {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug | Level::Trace => 7,
+        };
+        let message = record.args().to_string();
+
+        let mut datagram = format!(
+            "PRIORITY={}\nSYSLOG_IDENTIFIER={}\n",
+            priority,
+            crate_name!()
+        )
+        .into_bytes();
+        // The length-prefixed form, messages may contain newlines
+        datagram.extend_from_slice(b"MESSAGE\n");
+        datagram.extend_from_slice(&(message.len() as u64).to_le_bytes());
+        datagram.extend_from_slice(message.as_bytes());
+        datagram.push(b'\n');
+
+        UnixDatagram::unbound()?.send_to(&datagram, SOCKET)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(not(all(feature = "journald", target_os = "linux")))]
+mod journald {
+    use log::Record;
+    use std::io;
+
+    pub const AVAILABLE: bool = false;
+
+    pub fn send(_record: &Record) -> io::Result<()> {
+        Ok(())
+    }
+}
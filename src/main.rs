@@ -4,46 +4,314 @@
 
 #[macro_use]
 extern crate clap;
+#[macro_use]
+extern crate log;
 extern crate chrono;
 
-use std::path::PathBuf;
+use chrono::{Local, TimeZone};
+use std::path::{Path, PathBuf};
+
+mod cli;
+mod console;
+mod control;
+mod interrupt;
+mod interval;
+#[cfg(target_os = "macos")]
+mod launchd;
+mod logger;
+mod metrics_server;
+mod ndjson;
+#[cfg(windows)]
+mod service;
+mod systemd;
+mod watch;
+mod webhook;
 
-mod settings;
-mod utils;
+use console::ConsoleSink;
+use filesorter_core::{
+    doctor, error, executor, hardlink, history, lock, migrate, planner, plugins, presets, prune,
+    settings, size, sorter, stats, transfer, validate, verify,
+};
 
 fn main() {
-    let matches = utils::get_arg_matches();
-
-    match matches.subcommand_name() {
-        Some("init") => {
-            if let Some(ref matches) = matches.subcommand_matches("init") {
-                init(
-                    PathBuf::from(matches.value_of("source_dir").expect("Expected source dir")),
-                    PathBuf::from(
-                        matches
-                            .value_of("destination_dir")
-                            .expect("Expected destination dir"),
-                    ),
-                    matches.is_present("use_date_pattern"),
+    let matches = cli::get_arg_matches();
+    logger::init(
+        matches.occurrences_of("verbose"),
+        matches.is_present("quiet"),
+        value_t!(matches, "color", logger::ColorChoice).unwrap_or_else(|e| e.exit()),
+    );
+
+    match matches.subcommand() {
+        ("init", Some(matches)) => init(
+            &cli::get_settings_path(matches),
+            PathBuf::from(matches.value_of("source_dir").expect("Expected source dir")),
+            PathBuf::from(
+                matches
+                    .value_of("destination_dir")
+                    .expect("Expected destination dir"),
+            ),
+            matches.is_present("use_date_pattern"),
+            matches
+                .value_of("date_pattern")
+                .expect("Expected output pattern"),
+            if matches.is_present("empty") {
+                None
+            } else {
+                Some(
                     matches
-                        .value_of("date_pattern")
-                        .expect("Expected output pattern"),
+                        .value_of("template")
+                        .and_then(presets::Preset::from_name)
+                        .unwrap_or(presets::Preset::General),
                 )
+            },
+        ),
+        ("sort", Some(matches)) if matches.is_present("every") => {
+            let interval = humantime::parse_duration(matches.value_of("every").unwrap())
+                .expect("Expected the interval to be validated");
+            if let Err(e) = interval::sort_every(
+                &cli::get_settings_path(matches),
+                &sort_options(matches),
+                interval,
+            ) {
+                exit_fatal(e);
+            }
+        }
+        ("sort", Some(matches)) => {
+            let paths: Vec<PathBuf> = matches
+                .values_of("paths")
+                .map(|paths| paths.map(PathBuf::from).collect())
+                .unwrap_or_default();
+            let result = sort(
+                &cli::get_settings_path(matches),
+                &sort_options(matches),
+                &paths,
+            );
+            std::process::exit(match result {
+                Ok(failures) if failures.is_empty() && !executor::interrupted() => {
+                    error::EXIT_SUCCESS
+                }
+                Ok(failures) => {
+                    if !failures.is_empty() {
+                        print_failure_summary(&failures);
+                    }
+                    error::EXIT_PARTIAL
+                }
+                Err(e) => exit_fatal(e),
+            });
+        }
+        ("watch", Some(matches)) => {
+            let options = sort_options(matches);
+            if matches.value_of("output") == Some("ndjson") {
+                ndjson::enable();
+                logger::log_to_stderr();
+            }
+            if let Err(e) = watch::watch(
+                &cli::get_settings_path(matches),
+                &options,
+                matches.is_present("force"),
+            ) {
+                exit_fatal(e);
+            }
+        }
+        ("dedup", Some(matches)) if matches.subcommand_name() == Some("report") => {
+            let matches = matches.subcommand_matches("report").unwrap();
+            let json = matches.value_of("output") == Some("json");
+            if json {
+                logger::log_to_stderr();
+            }
+            let result = dedup_report(
+                &cli::get_settings_path(matches),
+                matches.value_of("dir").map(PathBuf::from),
+                matches.is_present("sources"),
+                value_t!(matches, "jobs", usize).unwrap_or_else(|e| e.exit()),
+                json,
+            );
+            std::process::exit(match result {
+                Ok(failures) if failures.is_empty() => error::EXIT_SUCCESS,
+                Ok(failures) => {
+                    print_failure_summary(&failures);
+                    error::EXIT_PARTIAL
+                }
+                Err(e) => exit_fatal(e),
+            });
+        }
+        ("dedup", Some(matches)) => {
+            let result = dedup(
+                &cli::get_settings_path(matches),
+                matches.value_of("dir").map(PathBuf::from),
+                value_t!(matches, "jobs", usize).unwrap_or_else(|e| e.exit()),
+                matches.is_present("dry_run"),
+            );
+            std::process::exit(match result {
+                Ok(failures) if failures.is_empty() => error::EXIT_SUCCESS,
+                Ok(failures) => {
+                    print_failure_summary(&failures);
+                    error::EXIT_PARTIAL
+                }
+                Err(e) => exit_fatal(e),
+            });
+        }
+        ("verify", Some(matches)) => {
+            let json = matches.value_of("output") == Some("json");
+            if json {
+                logger::log_to_stderr();
+            }
+            let result = verify(
+                &cli::get_settings_path(matches),
+                matches.value_of("dir").map(PathBuf::from),
+                value_t!(matches, "jobs", usize).unwrap_or_else(|e| e.exit()),
+                json,
+            );
+            std::process::exit(match result {
+                Ok(true) => error::EXIT_SUCCESS,
+                Ok(false) => error::EXIT_PARTIAL,
+                Err(e) => exit_fatal(e),
+            });
+        }
+        ("history", Some(matches)) => {
+            let json = matches.value_of("output") == Some("json");
+            let parse_time = |name| {
+                matches
+                    .value_of(name)
+                    .map(|time| history::parse_time(time).unwrap_or_else(|e| exit_fatal(e)))
+            };
+            let filter = history::Filter {
+                since: parse_time("since"),
+                until: parse_time("until"),
+                rule: matches.value_of("rule").map(String::from),
+                source: matches.value_of("source").map(|dir| {
+                    let dir = PathBuf::from(dir);
+                    dir.canonicalize().unwrap_or(dir)
+                }),
+                limit: matches
+                    .is_present("limit")
+                    .then(|| value_t!(matches, "limit", usize).unwrap_or_else(|e| e.exit())),
+                name: None,
+            };
+            print_history(&filter, json).unwrap_or_else(|e| exit_fatal(e));
+        }
+        ("where", Some(matches)) => {
+            let found =
+                find_sorted(matches.value_of("name").unwrap()).unwrap_or_else(|e| exit_fatal(e));
+            if !found {
+                std::process::exit(1);
+            }
+        }
+        ("doctor", Some(matches)) => doctor(&cli::get_settings_path(matches)),
+        ("clean", Some(matches)) => {
+            let result = clean(
+                &cli::get_settings_path(matches),
+                matches.is_present("dry_run"),
+            );
+            std::process::exit(match result {
+                Ok(failures) if failures.is_empty() => error::EXIT_SUCCESS,
+                Ok(failures) => {
+                    print_failure_summary(&failures);
+                    error::EXIT_PARTIAL
+                }
+                Err(e) => exit_fatal(e),
+            });
+        }
+        ("stats", Some(matches)) => {
+            let json = matches.value_of("output") == Some("json");
+            if json {
+                logger::log_to_stderr();
+            }
+            let result = stats(
+                &cli::get_settings_path(matches),
+                matches.value_of("dir").map(PathBuf::from),
+                json,
+            );
+            std::process::exit(match result {
+                Ok(failures) if failures.is_empty() => error::EXIT_SUCCESS,
+                Ok(failures) => {
+                    print_failure_summary(&failures);
+                    error::EXIT_PARTIAL
+                }
+                Err(e) => exit_fatal(e),
+            });
+        }
+        ("explain", Some(matches)) => explain(
+            &cli::get_settings_path(matches),
+            Path::new(matches.value_of("file").unwrap()),
+        )
+        .unwrap_or_else(|e| exit_fatal(e)),
+        ("status", Some(matches)) => {
+            let status = control::send(control::Command::Status).unwrap_or_else(|e| exit_fatal(e));
+            if matches.value_of("output") == Some("json") {
+                let json = serde_json::to_string_pretty(&status)
+                    .unwrap_or_else(|e| exit_fatal(format!("Failed to write the status: {}", e)));
+                println!("{}", json);
             } else {
-                panic!("No provided arguments!");
+                control::print(&status);
             }
         }
-        Some("sort") => sort(),
-        None => {}
+        ("pause", Some(_)) => {
+            let status = control::send(control::Command::Pause).unwrap_or_else(|e| exit_fatal(e));
+            info!("Paused the watch (pid {})", status.pid);
+        }
+        ("resume", Some(_)) => {
+            let status = control::send(control::Command::Resume).unwrap_or_else(|e| exit_fatal(e));
+            info!("Resumed the watch (pid {})", status.pid);
+        }
+        #[cfg(windows)]
+        ("service", Some(matches)) => {
+            let result = match matches.subcommand() {
+                ("install", Some(matches)) => service::install(&cli::get_settings_path(matches)),
+                ("uninstall", Some(_)) => service::uninstall(),
+                ("run", Some(matches)) => service::run(&cli::get_settings_path(matches)),
+                _ => Ok(()),
+            };
+            if let Err(e) = result {
+                exit_fatal(e);
+            }
+        }
+        #[cfg(target_os = "macos")]
+        ("daemon", Some(matches)) => {
+            let result = match matches.subcommand() {
+                ("install", Some(matches)) => launchd::install(&cli::get_settings_path(matches)),
+                ("uninstall", Some(_)) => launchd::uninstall(),
+                _ => Ok(()),
+            };
+            if let Err(e) = result {
+                exit_fatal(e);
+            }
+        }
+        ("config", Some(matches)) => match matches.subcommand() {
+            ("validate", Some(matches)) => config_validate(&cli::get_settings_path(matches)),
+            ("migrate", Some(matches)) => config_migrate(&cli::get_settings_path(matches)),
+            _ => {}
+        },
+        ("rules", Some(matches)) => match matches.subcommand() {
+            ("list", Some(matches)) => rules_list(&cli::get_settings_path(matches)),
+            ("add", Some(matches)) => rules_add(
+                &cli::get_settings_path(matches),
+                matches.values_of("extension").unwrap().collect(),
+                matches.value_of("destination").unwrap(),
+            ),
+            ("remove", Some(matches)) => rules_remove(
+                &cli::get_settings_path(matches),
+                matches.values_of("extension").unwrap().collect(),
+            ),
+            _ => {}
+        },
         _ => {}
     }
 }
 
-fn init(source_dir: PathBuf, destination_dir: PathBuf, use_date_pattern: bool, date_pattern: &str) {
+fn init(
+    settings_path: &Path,
+    source_dir: PathBuf,
+    destination_dir: PathBuf,
+    use_date_pattern: bool,
+    date_pattern: &str,
+    preset: Option<presets::Preset>,
+) {
     println!("Source dir: {}", source_dir.display());
     println!("Destination dir: {}", destination_dir.display());
     println!("Use date pattern: {}", use_date_pattern);
     println!("Date pattern: {}", date_pattern);
+    println!("Rules: {}", preset.map_or("none", |preset| preset.name()));
 
     let mut settings = settings::Settings::default();
 
@@ -51,104 +319,663 @@ fn init(source_dir: PathBuf, destination_dir: PathBuf, use_date_pattern: bool, d
         .add_source(source_dir)
         .destination(destination_dir)
         .use_date_pattern(use_date_pattern)
-        .date_pattern(date_pattern.to_string());
+        .date_pattern(date_pattern.to_string())
+        .sort_patterns(preset.map_or_else(Vec::new, |preset| preset.sort_patterns()));
 
-    settings.backup_old_config().save_to_file_warn();
+    settings
+        .backup_old_config(settings_path)
+        .save_to_file_warn(settings_path);
 
     println!("Initialized successfully!");
 }
 
-fn sort() {
-    println!("Starting...");
+fn sort_options(matches: &clap::ArgMatches) -> sorter::SortOptions {
+    sorter::SortOptions {
+        jobs: value_t!(matches, "jobs", usize).unwrap_or_else(|e| e.exit()),
+        fail_fast: matches.is_present("fail_fast"),
+        wait: matches.is_present("wait"),
+        resume: matches.is_present("resume"),
+        no_trash: matches.is_present("no_trash"),
+        hidden: matches.is_present("hidden"),
+        prune_empty: matches.is_present("prune_empty"),
+        no_config: matches.is_present("no_config"),
+        sources: matches
+            .values_of("source")
+            .map(|sources| sources.map(PathBuf::from).collect())
+            .unwrap_or_default(),
+        destination: matches.value_of("destination").map(PathBuf::from),
+        // The bar would garble the event stream
+        progress: matches.value_of("output") != Some("ndjson"),
+    }
+}
+
+/// Sorts the sources, or only `paths` if there are any. Returns the failures of files that couldn't
+/// be sorted, errors are the ones that stopped the run.
+fn sort(
+    settings_path: &Path,
+    options: &sorter::SortOptions,
+    paths: &[PathBuf],
+) -> error::Result<Vec<error::Error>> {
+    let _lock = lock::RunLock::acquire(options.wait)?;
 
-    let settings = settings::Settings::load();
+    info!("Starting...");
 
-    if settings.sources.len() == 0 {
-        panic!("Config file not initialized, you should initialize them! Run `filesorter help init` for help.")
+    let settings = sorter::load_settings(settings_path, options)?;
+    logger::configure(&settings)?;
+    if let Some(failures) = sorter::check_journal(&settings, options.resume)? {
+        return Ok(failures);
     }
 
-    for source in &settings.sources {
-        if !source.exists() {
-            panic!("Source dir '{}' doesn't exists!", source.display());
-        }
-        if !source.is_dir() {
-            panic!(
-                "Source dir '{}' exists but is not a directory, exiting...",
-                source.display()
+    interrupt::finish_transfers(|| {})?;
+    let engine = sorter::SortEngine::new(settings).options(options.clone());
+    let sink = ConsoleSink::new(engine.settings(), options.progress);
+    let report = if paths.is_empty() {
+        engine.run(sink)?
+    } else {
+        engine.run_paths(paths, sink)?
+    };
+    webhook::send(engine.settings(), &report);
+
+    if executor::interrupted() {
+        warn!("{}", report.summary());
+    } else {
+        info!("Done!");
+    }
+
+    Ok(report.failures)
+}
+
+/// Links duplicates in `dir`, or in every destination, returning the files that failed.
+fn dedup(
+    settings_path: &Path,
+    dir: Option<PathBuf>,
+    jobs: usize,
+    dry_run: bool,
+) -> error::Result<Vec<error::Error>> {
+    // A sort running meanwhile could move files that are being linked
+    let _lock = lock::RunLock::acquire(false)?;
+
+    let settings = load_settings(settings_path);
+    let dirs = match dir {
+        Some(dir) => vec![dir],
+        None => settings.destination_roots(),
+    };
+
+    let mut failures = Vec::new();
+    for dir in dirs {
+        let mut report =
+            hardlink::link_duplicates(&dir, settings.partial_hash.0, jobs, dry_run)?;
+        info!("{}: {}", dir.display(), report.summary());
+        failures.append(&mut report.failures);
+    }
+
+    Ok(failures)
+}
+
+/// Prints the identical files of `dir`, the sources or every destination, returning the files
+/// that failed.
+fn dedup_report(
+    settings_path: &Path,
+    dir: Option<PathBuf>,
+    sources: bool,
+    jobs: usize,
+    json: bool,
+) -> error::Result<Vec<error::Error>> {
+    let settings = load_settings(settings_path);
+    let dirs = match dir {
+        Some(dir) => vec![dir],
+        None if sources => settings
+            .sources
+            .iter()
+            .map(|source| source.path.clone())
+            .collect(),
+        None => settings.destination_roots(),
+    };
+
+    let mut report = hardlink::DuplicateReport::default();
+    for dir in dirs {
+        let mut dir_report = hardlink::find_duplicates(&dir, settings.partial_hash.0, jobs)?;
+        report.groups.append(&mut dir_report.groups);
+        report.failures.append(&mut dir_report.failures);
+    }
+    report
+        .groups
+        .sort_by_key(|group| std::cmp::Reverse(group.savings));
+
+    if json {
+        let json = serde_json::to_string_pretty(&report)
+            .unwrap_or_else(|e| exit_fatal(format!("Failed to write the report: {}", e)));
+        println!("{}", json);
+    } else {
+        if !report.groups.is_empty() {
+            println!(
+                "{:<16} {:>10} {:>6} {:>10}",
+                "HASH", "SIZE", "FILES", "SAVINGS"
             );
         }
-        if !settings.destination.is_dir() {
-            panic!(
-                "Destination dir '{}' doesn't exists!",
-                settings.destination.display()
+        for group in &report.groups {
+            println!(
+                "{:<16} {:>10} {:>6} {:>10}",
+                &group.hash[..16],
+                size::ByteSize(group.size).to_string(),
+                group.paths.len(),
+                size::ByteSize(group.savings).to_string()
             );
+            for path in &group.paths {
+                println!("  {}", path.display());
+            }
         }
-        if !settings.destination.is_dir() {
-            panic!(
-                "Destination dir '{}' exists but is not a directory, exiting...",
-                settings.destination.display()
-            );
+        println!("{}", report.summary());
+    }
+
+    Ok(report.failures)
+}
+
+/// Checks the files in `dir`, or in every destination, returning whether they're all fine.
+fn verify(
+    settings_path: &Path,
+    dir: Option<PathBuf>,
+    jobs: usize,
+    json: bool,
+) -> error::Result<bool> {
+    let dirs = match dir {
+        Some(dir) => vec![dir],
+        None => load_settings(settings_path).destination_roots(),
+    };
+
+    let report = verify::verify(&dirs, jobs)?;
+    if json {
+        let json = serde_json::to_string_pretty(&report)
+            .unwrap_or_else(|e| exit_fatal(format!("Failed to write the report: {}", e)));
+        println!("{}", json);
+    } else {
+        for problem in &report.problems {
+            let status = format!("{:?}", problem.status).to_lowercase();
+            match &problem.reason {
+                Some(reason) => println!("{:<10} {} ({})", status, problem.path.display(), reason),
+                None => println!("{:<10} {}", status, problem.path.display()),
+            }
         }
+        println!("{}", report.summary());
+    }
 
-        utils::create_dirs(vec![&source, &settings.destination]);
+    Ok(report.problems.is_empty())
+}
 
-        let files = utils::get_files(&source);
-        for file in &files {
-            // Ignore files which starts from dot
-            if let Some(filename) = &file.file_name() {
-                if &filename.to_str().unwrap_or(".")[..1] == "." {
-                    println!("Ignoring file {}", &file.display());
-                    continue;
-                }
+fn print_history(filter: &history::Filter, json: bool) -> error::Result<()> {
+    let entries = history::History::open()?.query(filter)?;
+    if json {
+        let json = serde_json::to_string_pretty(&entries)
+            .unwrap_or_else(|e| exit_fatal(format!("Failed to write the history: {}", e)));
+        println!("{}", json);
+        return Ok(());
+    }
 
-                let file_extension = &file.extension();
-
-                if let None = file_extension {
-                    'outer: for pattern in &settings.sort_patterns {
-                        for mime_type in &pattern.mime_types {
-                            if tree_magic::match_filepath(mime_type, &file) {
-                                let destination_dir = utils::get_destination_dir(
-                                    &settings,
-                                    &file,
-                                    &pattern.destination,
-                                );
-                                let destination_file =
-                                    &destination_dir.join(&file.file_name().unwrap());
-
-                                utils::move_file(&file, &destination_dir, &destination_file);
-
-                                break 'outer;
-                            }
-                        }
-                    }
+    for entry in &entries {
+        let time = Local
+            .timestamp_opt(entry.time, 0)
+            .single()
+            .map_or_else(String::new, |time| {
+                time.format("%Y-%m-%d %H:%M:%S").to_string()
+            });
+        let action = format!("{:?}", entry.action).to_lowercase();
+        match &entry.destination {
+            Some(destination) => println!(
+                "{} {:<7} {} -> {}",
+                time,
+                action,
+                entry.file.display(),
+                destination.display()
+            ),
+            None => println!("{} {:<7} {}", time, action, entry.file.display()),
+        }
+    }
+    let bytes = entries.iter().map(|entry| entry.size).sum();
+    println!("{} file(s), {}", entries.len(), size::ByteSize(bytes));
 
-                    continue;
-                }
+    Ok(())
+}
 
-                let file_extension_str = &file_extension
-                    .unwrap()
-                    .to_os_string()
-                    .into_string()
-                    .unwrap()
-                    .to_lowercase();
+/// Prints where files that had this name went, latest last, returning whether there were any.
+fn find_sorted(name: &str) -> error::Result<bool> {
+    let filter = history::Filter {
+        name: Some(name.to_string()),
+        ..Default::default()
+    };
+    let entries = history::History::open()?.query(&filter)?;
+    if entries.is_empty() {
+        println!("No file named {} was sorted", name);
+        return Ok(false);
+    }
 
-                for pattern in &settings.sort_patterns {
-                    if pattern.extensions.contains(&file_extension_str) {
-                        let destination_dir =
-                            utils::get_destination_dir(&settings, &file, &pattern.destination);
-                        let destination_file = &destination_dir.join(&file.file_name().unwrap());
+    for entry in &entries {
+        let time = Local
+            .timestamp_opt(entry.time, 0)
+            .single()
+            .map_or_else(String::new, |time| {
+                time.format("%Y-%m-%d %H:%M:%S").to_string()
+            });
+        let destination = match &entry.destination {
+            Some(destination) => destination,
+            None => {
+                let action = format!("{:?}", entry.action).to_lowercase();
+                println!("{} {} was {}", time, entry.file.display(), action);
+                continue;
+            }
+        };
 
-                        utils::move_file(&file, &destination_dir, &destination_file);
+        let mut notes = Vec::new();
+        if destination.file_name() != entry.file.file_name() {
+            notes.push("renamed, a file of that name was there");
+        }
+        if !destination.exists() {
+            notes.push("not there anymore");
+        }
+        let notes = if notes.is_empty() {
+            String::new()
+        } else {
+            format!(" ({})", notes.join(", "))
+        };
+        println!(
+            "{} {} -> {}{}",
+            time,
+            entry.file.display(),
+            destination.display(),
+            notes
+        );
+    }
 
-                        break;
-                    }
-                }
+    Ok(true)
+}
+
+/// Prints the environment and the checks of `doctor::check`, exiting with 1 if any failed.
+fn doctor(settings_path: &Path) {
+    for (name, value) in doctor::environment(settings_path) {
+        println!("{:<12} {}", format!("{}:", name), value);
+    }
+    println!();
+
+    let checks = doctor::check(settings_path);
+    for check in &checks {
+        let severity = format!("{:?}", check.severity).to_lowercase();
+        println!("{:<8} {}", severity, check.message);
+    }
+
+    let count = |severity| {
+        checks
+            .iter()
+            .filter(|check| check.severity == severity)
+            .count()
+    };
+    let (errors, warnings) = (
+        count(doctor::Severity::Error),
+        count(doctor::Severity::Warning),
+    );
+    println!("{} error(s), {} warning(s)", errors, warnings);
+    if errors > 0 {
+        std::process::exit(1);
+    }
+}
+
+/// Removes the empty dirs of every source, returning the dirs that couldn't be removed.
+fn clean(settings_path: &Path, dry_run: bool) -> error::Result<Vec<error::Error>> {
+    let _lock = lock::RunLock::acquire(false)?;
+    let settings = load_settings(settings_path);
+
+    let mut removed = 0;
+    let mut failures = Vec::new();
+    for source in &settings.sources {
+        let mut pruned = prune::prune_source(&settings, source, dry_run)?;
+        for dir in &pruned.removed {
+            if dry_run {
+                info!("Would remove {}", dir.display());
             } else {
-                println!("Failed to proceed file {}", file.display());
-                continue;
+                info!("Removed {}", dir.display());
             }
         }
+        removed += pruned.removed.len();
+        failures.append(&mut pruned.failures);
+    }
+
+    if dry_run {
+        info!("Would remove {} empty dir(s)", removed);
+    } else {
+        info!(
+            "Removed {} empty dir(s), {} failed",
+            removed,
+            failures.len()
+        );
+    }
+
+    Ok(failures)
+}
+
+/// Prints the files and sizes by category of `dir` or every destination, returning the files that
+/// couldn't be looked at. Only the destinations are compared with and saved for the next time.
+fn stats(
+    settings_path: &Path,
+    dir: Option<PathBuf>,
+    json: bool,
+) -> error::Result<Vec<error::Error>> {
+    let settings = load_settings(settings_path);
+    let (dirs, whole) = match dir {
+        Some(dir) => (vec![dir], false),
+        None => (settings.destination_roots(), true),
+    };
+
+    let mut report = stats::stats(&settings, &dirs)?;
+    if whole {
+        report.compare_with_saved()?;
+        if let Err(e) = report.save() {
+            warn!("Failed to save the stats for next time: {}", e);
+        }
+    }
+
+    if json {
+        let json = serde_json::to_string_pretty(&report)
+            .unwrap_or_else(|e| exit_fatal(format!("Failed to write the stats: {}", e)));
+        println!("{}", json);
+        return Ok(report.failures);
+    }
+
+    if !report.categories.is_empty() {
+        println!(
+            "{:<24} {:>7} {:>10} {:>10} {:>10} {:>10}",
+            "CATEGORY", "FILES", "SIZE", "GROWTH", "OLDEST", "NEWEST"
+        );
+    }
+    for category in &report.categories {
+        let growth = category.growth.map_or_else(
+            || String::from("-"),
+            |growth| {
+                let sign = if growth.bytes < 0 { "-" } else { "+" };
+                format!("{}{}", sign, size::ByteSize(growth.bytes.unsigned_abs()))
+            },
+        );
+        let date = |file: &Option<stats::DatedFile>| {
+            file.as_ref()
+                .and_then(|file| Local.timestamp_opt(file.modified, 0).single())
+                .map_or_else(String::new, |time| time.format("%Y-%m-%d").to_string())
+        };
+        println!(
+            "{:<24} {:>7} {:>10} {:>10} {:>10} {:>10}",
+            category.name,
+            category.files,
+            size::ByteSize(category.bytes).to_string(),
+            growth,
+            date(&category.oldest),
+            date(&category.newest)
+        );
+    }
+
+    println!(
+        "{} file(s), {} in {} categories",
+        report.files(),
+        size::ByteSize(report.bytes()),
+        report.categories.len()
+    );
+    if let Some(previous) = report
+        .previous
+        .and_then(|time| Local.timestamp_opt(time, 0).single())
+    {
+        let files: i64 = report
+            .categories
+            .iter()
+            .filter_map(|category| category.growth)
+            .map(|growth| growth.files)
+            .sum();
+        println!(
+            "{:+} file(s) since {}",
+            files,
+            previous.format("%Y-%m-%d %H:%M:%S")
+        );
+    }
+
+    Ok(report.failures)
+}
+
+/// Prints how the rules see `file`: the checks of each rule and where the file would go.
+fn explain(settings_path: &Path, file: &Path) -> error::Result<()> {
+    let settings = load_settings(settings_path);
+    let file = file.canonicalize().map_err(|e| error::Error::io(file, e))?;
+    if !file.is_file() {
+        return Err(error::Error::Settings(format!(
+            "{} is not a file",
+            file.display()
+        )));
+    }
+
+    let source = settings.source_of(&file).unwrap_or_else(|| {
+        let dir = file.parent().unwrap_or(&file).to_path_buf();
+        warn!(
+            "{} isn't in any source, explaining it as if {} were one",
+            file.display(),
+            dir.display()
+        );
+        settings::Source::from(dir)
+    });
+    let plugins = plugins::Plugins::load(&settings.get_plugins_dir()).unwrap_or_default();
+    let explanation = planner::explain(&settings, &source, &plugins, &file)?;
+
+    println!("File:   {}", file.display());
+    println!("Source: {}", source.path.display());
+    if let Some(reason) = &explanation.skipped {
+        println!("Skipped: {}", reason);
+    }
+    if let Some(plugin) = &explanation.plugin {
+        println!("Picked by plugin {}, the rules aren't checked", plugin);
+    }
+    for rule in &explanation.rules {
+        let verdict = if rule.matched { "matches" } else { "no match" };
+        println!("\nRule {}: {}", rule.rule, verdict);
+        for (check, passed) in &rule.checks {
+            let status = if *passed { "passed" } else { "failed" };
+            println!("  {:<7} {}", status, check);
+        }
+    }
+
+    println!();
+    match &explanation.planned {
+        Some(planned) => match planned.action {
+            Some(action) => {
+                let verb = match action {
+                    transfer::CleanupAction::Delete => "delete",
+                    transfer::CleanupAction::Trash => "trash",
+                };
+                println!("Rule {} would {} the file", planned.rule, verb);
+            }
+            None => println!(
+                "Rule {} would move it to {}",
+                planned.rule,
+                planned.destination_file.display()
+            ),
+        },
+        None => println!("No rule matches, the file stays where it is"),
+    }
+    if explanation.skipped.is_some() {
+        println!("A run doesn't get that far though, the file is skipped");
+    }
+
+    Ok(())
+}
+
+/// Lists failed files grouped by what went wrong.
+fn print_failure_summary(failures: &[error::Error]) {
+    let mut groups: Vec<(String, Vec<&error::Error>)> = Vec::new();
+    for failure in failures {
+        let reason = failure.reason();
+        match groups.iter_mut().find(|(group, _)| *group == reason) {
+            Some((_, group_failures)) => group_failures.push(failure),
+            None => groups.push((reason, vec![failure])),
+        }
+    }
+
+    println!("Finished with {} error(s):", failures.len());
+    for (reason, group_failures) in groups {
+        println!("  {} ({}):", reason, group_failures.len());
+        for failure in group_failures {
+            if let Some(path) = failure.path() {
+                println!("    {}", path.display());
+            }
+        }
+    }
+}
+
+/// Reports an error that prevents the command from running and exits with `EXIT_FATAL`.
+fn exit_fatal(error: impl std::fmt::Display) -> ! {
+    error!("{}", error);
+    std::process::exit(error::EXIT_FATAL);
+}
+
+fn load_settings(settings_path: &Path) -> settings::Settings {
+    settings::Settings::load(settings_path).unwrap_or_else(|e| exit_fatal(e))
+}
+
+fn config_validate(settings_path: &Path) {
+    println!("Validating {}...", settings_path.display());
+
+    let problems = validate::validate_file(settings_path);
+    if problems.is_empty() {
+        println!("No problems found!");
+        return;
+    }
+
+    for problem in &problems {
+        println!("{}: {}", settings_path.display(), problem);
+    }
+    println!("Found {} problem(s)", problems.len());
+
+    std::process::exit(1);
+}
+
+fn rules_list(settings_path: &Path) {
+    let settings = load_settings(settings_path);
+
+    for pattern in &settings.sort_patterns {
+        print_rule(pattern);
+        println!();
+    }
+    for pattern in &settings.included_patterns {
+        print_rule(pattern);
+        println!(" [included]");
+    }
+}
+
+fn print_rule(pattern: &settings::SortPattern) {
+    print!("{}: {}", pattern.destination, pattern.extensions.join(", "));
+    if !pattern.mime_types.is_empty() {
+        print!(" (mime: {})", pattern.mime_types.join(", "));
+    }
+    if let Some(name_regex) = &pattern.name_regex {
+        print!(" (name: /{}/)", name_regex.0);
+    }
+    if !pattern.globs.is_empty() {
+        print!(" (globs: {})", pattern.globs.patterns.join(", "));
+    }
+    if let Some(min_size) = pattern.min_size {
+        print!(" (>= {})", min_size);
+    }
+    if let Some(max_size) = pattern.max_size {
+        print!(" (<= {})", max_size);
+    }
+    if let Some(older_than) = pattern.older_than {
+        print!(" (older than {})", humantime::format_duration(older_than));
+    }
+    if let Some(newer_than) = pattern.newer_than {
+        print!(" (newer than {})", humantime::format_duration(newer_than));
+    }
+    if let Some(owner) = &pattern.owner {
+        print!(" (owner {})", owner);
+    }
+    if let Some(group) = &pattern.group {
+        print!(" (group {})", group);
+    }
+    if !pattern.exclude.is_empty() {
+        print!(" (except: {})", pattern.exclude.patterns.join(", "));
+    }
+    if pattern.script.is_some() {
+        print!(" (script)");
+    }
+    if let Some(rename) = &pattern.rename {
+        print!(" (rename to {})", rename);
+    }
+    if pattern.priority != 0 {
+        print!(" (priority {})", pattern.priority);
+    }
+    if let Some(match_mode) = pattern.match_mode {
+        print!(" [match {}]", format!("{:?}", match_mode).to_lowercase());
+    }
+}
+
+fn rules_add(settings_path: &Path, extensions: Vec<&str>, destination: &str) {
+    let mut settings = load_settings(settings_path);
+
+    for extension in extensions {
+        if let Some(pattern) = settings.find_extension(extension) {
+            eprintln!(
+                "Extension '{}' is already sorted to '{}', remove it first",
+                extension, pattern.destination
+            );
+            std::process::exit(1);
+        }
+
+        settings.add_extension(extension, destination);
+        println!("Added '{}' -> '{}'", extension, destination);
     }
 
-    println!("Done!")
+    settings.save_to_file_warn(settings_path);
+}
+
+fn rules_remove(settings_path: &Path, extensions: Vec<&str>) {
+    let mut settings = load_settings(settings_path);
+
+    for extension in extensions {
+        if settings.remove_extension(extension) {
+            println!("Removed '{}'", extension);
+        } else {
+            eprintln!("Extension '{}' not found in any rule", extension);
+            std::process::exit(1);
+        }
+    }
+
+    settings.save_to_file_warn(settings_path);
+}
+
+fn config_migrate(settings_path: &Path) {
+    let file = std::fs::File::open(settings_path)
+        .unwrap_or_else(|e| exit_fatal(error::Error::io(settings_path, e)));
+    let mut value: serde_yaml::Value = serde_yaml::from_reader(file).unwrap_or_else(|e| {
+        exit_fatal(format!(
+            "Failed to parse {}: {}",
+            settings_path.display(),
+            e
+        ))
+    });
+
+    let version = migrate::migrate(&mut value).unwrap_or_else(|e| exit_fatal(e));
+    if version == migrate::CURRENT_VERSION {
+        println!(
+            "Settings file is already at version {}",
+            migrate::CURRENT_VERSION
+        );
+        return;
+    }
+
+    let settings: settings::Settings = serde_yaml::from_value(value).unwrap_or_else(|e| {
+        exit_fatal(format!(
+            "Failed to upgrade {}: {}",
+            settings_path.display(),
+            e
+        ))
+    });
+
+    settings
+        .backup_old_config(settings_path)
+        .save_to_file_warn(settings_path);
+
+    println!(
+        "Migrated settings from version {} to {}",
+        version,
+        migrate::CURRENT_VERSION
+    );
 }
@@ -0,0 +1,47 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use filesorter_core::metrics;
+use filesorter_core::{Error, Result};
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+
+/// Serves the metrics in the Prometheus text format at `http://<address>/metrics`, in the background.
+pub fn serve(address: SocketAddr) -> Result<()> {
+    let listener = TcpListener::bind(address)
+        .map_err(|e| Error::Settings(format!("Failed to serve metrics on {}: {}", address, e)))?;
+    info!("Serving metrics on http://{}/metrics", address);
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = respond(stream) {
+                debug!("Failed to answer a metrics request: {}", e);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream) -> std::io::Result<()> {
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+    let (status, body) = if request_line.starts_with("GET ") && path == "/metrics" {
+        ("200 OK", metrics::render())
+    } else {
+        ("404 Not Found", String::from("Not found, try /metrics\n"))
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
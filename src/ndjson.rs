@@ -0,0 +1,40 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use chrono::{SecondsFormat, Utc};
+use filesorter_core::events::Event;
+use serde::Serialize;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[derive(Serialize)]
+struct Line<'a> {
+    time: String,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Starts streaming events to stdout, for `--output ndjson`.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Writes the event as a line of JSON, if streaming is enabled.
+pub fn emit(event: &Event) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let line = Line {
+        time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        event,
+    };
+    if let Ok(mut json) = serde_json::to_string(&line) {
+        json.push('\n');
+        // One write per line, so lines from worker threads don't interleave
+        let _ = io::stdout().lock().write_all(json.as_bytes());
+    }
+}
@@ -0,0 +1,189 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Runs `watch` as a native Windows service, started at boot.
+
+use crate::watch;
+
+use filesorter_core::error::{self, Error, Result};
+use filesorter_core::sorter::SortOptions;
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+    ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_service::{define_windows_service, service_dispatcher};
+
+const SERVICE_NAME: &str = "filesorter";
+const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
+
+/// The settings the service sorts by, the dispatcher has no way to hand them to [`service_main`].
+static SETTINGS_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+fn service_error(action: &str, e: windows_service::Error) -> Error {
+    Error::Settings(format!(
+        "Failed to {} the {} service: {}",
+        action, SERVICE_NAME, e
+    ))
+}
+
+/// Registers the service to start at boot, running `service run` with these settings. It runs as
+/// LocalSystem, whose profile holds no settings of its own, so their path is kept absolute.
+pub fn install(settings_path: &Path) -> Result<()> {
+    let settings_path = settings_path
+        .canonicalize()
+        .map_err(|e| Error::io(settings_path, e))?;
+    let executable_path = std::env::current_exe()
+        .map_err(|e| Error::Settings(format!("Failed to find the filesorter executable: {}", e)))?;
+
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )
+    .map_err(|e| service_error("install", e))?;
+    let service_info = ServiceInfo {
+        name: OsString::from(SERVICE_NAME),
+        display_name: OsString::from("filesorter"),
+        service_type: SERVICE_TYPE,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path,
+        launch_arguments: vec![
+            OsString::from("--config"),
+            settings_path.clone().into_os_string(),
+            OsString::from("service"),
+            OsString::from("run"),
+        ],
+        dependencies: vec![],
+        // LocalSystem
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager
+        .create_service(&service_info, ServiceAccess::CHANGE_CONFIG)
+        .map_err(|e| service_error("install", e))?;
+    service
+        .set_description("Sorts new files of the sources as they appear")
+        .map_err(|e| service_error("install", e))?;
+
+    info!(
+        "Installed the {} service with {}, it starts at boot or with `sc start {}`",
+        SERVICE_NAME,
+        settings_path.display(),
+        SERVICE_NAME
+    );
+
+    Ok(())
+}
+
+/// Stops the service if it's running and removes it.
+pub fn uninstall() -> Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+        .map_err(|e| service_error("uninstall", e))?;
+    let service = manager
+        .open_service(
+            SERVICE_NAME,
+            ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+        )
+        .map_err(|e| service_error("uninstall", e))?;
+
+    // Deleted services linger until they've stopped
+    service
+        .delete()
+        .map_err(|e| service_error("uninstall", e))?;
+    let status = service
+        .query_status()
+        .map_err(|e| service_error("uninstall", e))?;
+    if status.current_state != ServiceState::Stopped {
+        service.stop().map_err(|e| service_error("stop", e))?;
+    }
+
+    // A watch finishes its run in progress before stopping
+    let started = Instant::now();
+    while started.elapsed() < Duration::from_secs(30) {
+        match service.query_status() {
+            Ok(status) if status.current_state != ServiceState::Stopped => {
+                thread::sleep(Duration::from_secs(1))
+            }
+            _ => break,
+        }
+    }
+
+    info!("Uninstalled the {} service", SERVICE_NAME);
+
+    Ok(())
+}
+
+/// Hands the process over to the service control manager, which starts [`service_main`]. Only
+/// works when started by it.
+pub fn run(settings_path: &Path) -> Result<()> {
+    SETTINGS_PATH.get_or_init(|| settings_path.to_path_buf());
+
+    service_dispatcher::start(SERVICE_NAME, ffi_service_main).map_err(|e| service_error("run", e))
+}
+
+define_windows_service!(ffi_service_main, service_main);
+
+fn service_main(_arguments: Vec<OsString>) {
+    if let Err(e) = run_service() {
+        error!("{}", e);
+    }
+}
+
+fn run_service() -> Result<()> {
+    let status_handle = service_control_handler::register(SERVICE_NAME, |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            watch::stop();
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    })
+    .map_err(|e| service_error("run", e))?;
+    let set_state = |current_state: ServiceState, exit_code: ServiceExitCode| {
+        let controls_accepted = if current_state == ServiceState::Running {
+            ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN
+        } else {
+            ServiceControlAccept::empty()
+        };
+        status_handle
+            .set_service_status(ServiceStatus {
+                service_type: SERVICE_TYPE,
+                current_state,
+                controls_accepted,
+                exit_code,
+                checkpoint: 0,
+                wait_hint: Duration::default(),
+                process_id: None,
+            })
+            .map_err(|e| service_error("run", e))
+    };
+
+    set_state(ServiceState::Running, ServiceExitCode::NO_ERROR)?;
+    let settings_path = SETTINGS_PATH
+        .get()
+        .expect("Expected the settings path to be set");
+    let options = SortOptions {
+        // Nobody sees the bar of a service
+        progress: false,
+        ..SortOptions::default()
+    };
+    // The service manager already keeps it to one instance, so a pid file left by a crash is
+    // always stale
+    let exit_code = match watch::watch(settings_path, &options, true) {
+        Ok(()) => ServiceExitCode::NO_ERROR,
+        Err(e) => {
+            error!("{}", e);
+            ServiceExitCode::ServiceSpecific(error::EXIT_FATAL as u32)
+        }
+    };
+    set_state(ServiceState::Stopped, exit_code)
+}
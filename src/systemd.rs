@@ -0,0 +1,61 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+//! Tells systemd how `watch` is doing when it runs as a `Type=notify` service, and keeps its
+//! watchdog fed. Outside of systemd, and off Unix, nothing is sent.
+
+use filesorter_core::events::Event;
+
+use std::time::Duration;
+
+/// Startup is done, with what `systemctl status` shows meanwhile.
+pub fn ready(status: &str) {
+    #[cfg(unix)]
+    notify(&[
+        sd_notify::NotifyState::Ready,
+        sd_notify::NotifyState::Status(status),
+    ]);
+    #[cfg(not(unix))]
+    let _ = status;
+}
+
+/// Replaces the line `systemctl status` shows.
+pub fn status(status: &str) {
+    #[cfg(unix)]
+    notify(&[sd_notify::NotifyState::Status(status)]);
+    #[cfg(not(unix))]
+    let _ = status;
+}
+
+/// Tells the watchdog the process isn't hung.
+pub fn ping() {
+    #[cfg(unix)]
+    notify(&[sd_notify::NotifyState::Watchdog]);
+}
+
+/// Pings the watchdog on every event, so long runs aren't taken for hangs.
+pub fn on_event(_event: &Event) {
+    ping();
+}
+
+/// How often the watchdog has to be pinged, half its timeout as systemd advises, `None` if
+/// `WatchdogSec=` isn't set.
+pub fn watchdog_interval() -> Option<Duration> {
+    #[cfg(unix)]
+    {
+        let mut usec = 0;
+        if sd_notify::watchdog_enabled(false, &mut usec) {
+            return Some(Duration::from_micros(usec) / 2);
+        }
+    }
+
+    None
+}
+
+#[cfg(unix)]
+fn notify(states: &[sd_notify::NotifyState]) {
+    if let Err(e) = sd_notify::notify(false, states) {
+        debug!("Failed to notify systemd: {}", e);
+    }
+}
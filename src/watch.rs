@@ -0,0 +1,306 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use crate::console::ConsoleSink;
+use crate::control;
+use crate::interrupt;
+use crate::logger;
+use crate::metrics_server;
+use crate::ndjson;
+use crate::systemd;
+use crate::webhook;
+
+use filesorter_core::events::{Event, EventSink};
+use filesorter_core::executor::Report;
+use filesorter_core::lock;
+use filesorter_core::metrics;
+use filesorter_core::schedule::Schedule;
+use filesorter_core::settings::Settings;
+use filesorter_core::sorter::{self, SortEngine, SortOptions};
+use filesorter_core::{Error, Result};
+
+use chrono::Local;
+use notify::RecursiveMode;
+use notify_debouncer_mini::DebounceEventResult;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A batch is sorted after this many quiet periods even if changes keep coming, so a file
+/// written to all the time can't hold the others back.
+const MAX_BATCH_PERIODS: u32 = 10;
+/// Batches with more changes than this are summed up instead of logging every file.
+const LIST_LIMIT: usize = 20;
+/// How late a scheduled run has to be to count as missed, the machine was suspended most likely.
+const MISSED_AFTER: Duration = Duration::from_secs(60);
+/// How often a watch waiting for changes checks whether it was stopped.
+const STOP_POLL: Duration = Duration::from_secs(1);
+
+/// Set by [`stop`].
+static STOP: AtomicBool = AtomicBool::new(false);
+
+/// Ends [`watch`] once the run in progress, if any, is done.
+pub fn stop() {
+    STOP.store(true, Ordering::SeqCst);
+}
+
+/// Sorts the sources, then again whenever something changes in them or at the times of the
+/// `schedule`, until killed or stopped.
+/// `force` takes over a pid file naming a running process, see [`lock::PidFile::check`].
+pub fn watch(settings_path: &Path, options: &SortOptions, force: bool) -> Result<()> {
+    lock::PidFile::check(force)?;
+    let lock = lock::RunLock::acquire(options.wait)?;
+    let _pid_file = lock::PidFile::create(&lock)?;
+    interrupt::finish_transfers(stop)?;
+
+    let settings = sorter::load_settings(settings_path, options)?;
+    logger::configure(&settings)?;
+    if let Some(failures) = sorter::check_journal(&settings, options.resume)? {
+        if !failures.is_empty() {
+            warn!("{} file(s) of the interrupted run failed", failures.len());
+        }
+    }
+
+    if let Some(address) = settings.metrics_address {
+        metrics_server::serve(address)?;
+    }
+    control::serve(settings.sources.len())?;
+
+    let engine = SortEngine::new(settings).options(options.clone());
+    let settings = engine.settings();
+    let wait = systemd::watchdog_interval().map_or(STOP_POLL, |interval| interval.min(STOP_POLL));
+    if let Some(schedule) = &settings.schedule {
+        return watch_schedule(&engine, options, schedule, wait);
+    }
+
+    systemd::ready("Sorting the sources");
+    // Problems with the sources show up here, before anything is watched
+    let report = engine.run(sink(settings, options, false))?;
+    notify_sorted(settings, &report);
+    webhook::send(settings, &report);
+    let mut sorted = report.sorted;
+    systemd::status(&watch_status(settings, sorted, &report));
+
+    let (sender, receiver) = mpsc::channel();
+    let mut debouncer = notify_debouncer_mini::new_debouncer(settings.watch_quiet_period, sender)?;
+    let mode = if settings.recursive {
+        RecursiveMode::Recursive
+    } else {
+        RecursiveMode::NonRecursive
+    };
+    for source in &settings.sources {
+        debouncer.watcher().watch(&source.path, mode)?;
+    }
+
+    info!(
+        "Watching {} source(s) for new files",
+        settings.sources.len()
+    );
+
+    // Changes not sorted yet, kept while paused
+    let mut changed = BTreeSet::new();
+    // Files left for being modified less than `min_age` ago are sorted once they've settled
+    let mut settled_at = settle_time(settings);
+    while !STOP.load(Ordering::SeqCst) {
+        match receiver.recv_timeout(wait) {
+            Ok(events) => collect_batch(
+                &receiver,
+                events,
+                &mut changed,
+                settings.watch_quiet_period,
+                wait,
+            ),
+            Err(RecvTimeoutError::Timeout) => systemd::ping(),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        let settled = settled_at.is_some_and(|at| Instant::now() >= at);
+        if (changed.is_empty() && !settled) || control::paused() {
+            continue;
+        }
+
+        if changed.is_empty() {
+            debug!("Sorting files that have settled");
+            settled_at = None;
+        } else {
+            debug!("Sorting after {} change(s)", changed.len());
+            settled_at = settle_time(settings);
+        }
+        let summarize = changed.len() > LIST_LIMIT;
+        changed.clear();
+        run(&engine, options, summarize, &mut sorted);
+    }
+
+    Ok(())
+}
+
+/// Sorts at each time of the schedule, whatever changes in between. The wall clock is checked
+/// every `wait`, so a time that passed while the machine was suspended is caught up on, once,
+/// as soon as it wakes.
+fn watch_schedule(
+    engine: &SortEngine,
+    options: &SortOptions,
+    schedule: &Schedule,
+    wait: Duration,
+) -> Result<()> {
+    let next_after = |time| {
+        schedule.next_after(&time).ok_or_else(|| {
+            Error::Settings(format!(
+                "Schedule '{}' has no time left to sort at",
+                schedule
+            ))
+        })
+    };
+
+    let mut next = next_after(Local::now())?;
+    let status = format!(
+        "Sorting on schedule '{}', next at {}",
+        schedule,
+        next.format("%Y-%m-%d %H:%M")
+    );
+    info!("{}", status);
+    systemd::ready(&status);
+
+    let mut sorted = 0;
+    while !STOP.load(Ordering::SeqCst) {
+        thread::sleep(wait);
+        systemd::ping();
+        let now = Local::now();
+        if now < next {
+            continue;
+        }
+
+        if (now - next).to_std().is_ok_and(|late| late > MISSED_AFTER) {
+            info!(
+                "Catching up on the run due at {}",
+                next.format("%Y-%m-%d %H:%M")
+            );
+        }
+        if control::paused() {
+            info!(
+                "Paused, skipping the run due at {}",
+                next.format("%Y-%m-%d %H:%M")
+            );
+        } else {
+            run(engine, options, false, &mut sorted);
+        }
+        // Times missed more than once in a row are caught up on by a single run
+        next = next_after(Local::now())?;
+        debug!("Next run at {}", next.format("%Y-%m-%d %H:%M"));
+    }
+
+    Ok(())
+}
+
+/// When files modified just before a run have been there for `min_age`, if it's set.
+fn settle_time(settings: &Settings) -> Option<Instant> {
+    (!settings.min_age.is_zero()).then(|| Instant::now() + settings.min_age)
+}
+
+/// Sorts the sources and reports how it went, counting the files into `sorted`.
+fn run(engine: &SortEngine, options: &SortOptions, summarize: bool, sorted: &mut usize) {
+    let settings = engine.settings();
+    match engine.run(sink(settings, options, summarize)) {
+        Ok(report) => {
+            *sorted += report.sorted;
+            systemd::status(&watch_status(settings, *sorted, &report));
+            notify_sorted(settings, &report);
+            // Runs set off by our own moves have nothing to report
+            if report.sorted > 0 || !report.failures.is_empty() {
+                webhook::send(settings, &report);
+            }
+        }
+        Err(e) => {
+            error!("{}", e);
+            metrics::record_error(&e);
+            ndjson::emit(&Event::error(&e));
+        }
+    }
+}
+
+/// Logs every file unless `summarize`, streams events with `--output ndjson` and feeds the
+/// systemd watchdog.
+fn sink(settings: &Settings, options: &SortOptions, summarize: bool) -> impl EventSink {
+    let console = ConsoleSink::new(settings, options.progress);
+    let console = if summarize {
+        console.summarized()
+    } else {
+        console
+    };
+    (console, (ndjson::emit, systemd::on_event))
+}
+
+/// Adds the paths changed from the first `events` until the sources have been quiet for
+/// `quiet_period` to `changed`, so a download dumping hundreds of files is sorted in one run.
+fn collect_batch(
+    receiver: &Receiver<DebounceEventResult>,
+    events: DebounceEventResult,
+    changed: &mut BTreeSet<PathBuf>,
+    quiet_period: Duration,
+    wait: Duration,
+) {
+    let mut add = |events: DebounceEventResult| match events {
+        Ok(events) => {
+            for event in events {
+                if changed.insert(event.path.clone()) {
+                    ndjson::emit(&Event::detected(&event.path));
+                }
+            }
+        }
+        Err(e) => {
+            let e = Error::from(e);
+            error!("{}", e);
+            metrics::record_error(&e);
+            ndjson::emit(&Event::error(&e));
+        }
+    };
+    add(events);
+
+    let deadline = Instant::now() + quiet_period * MAX_BATCH_PERIODS;
+    let mut quiet_until = Instant::now() + quiet_period;
+    loop {
+        let now = Instant::now();
+        let until = quiet_until.min(deadline);
+        if now >= until || STOP.load(Ordering::SeqCst) {
+            break;
+        }
+        match receiver.recv_timeout((until - now).min(wait)) {
+            Ok(events) => {
+                add(events);
+                quiet_until = Instant::now() + quiet_period;
+            }
+            Err(RecvTimeoutError::Timeout) => systemd::ping(),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+}
+
+/// Like "Watching 2 source(s), 15 file(s) sorted, last run at 14:03:12: Sorted 3 file(s)".
+fn watch_status(settings: &Settings, sorted: usize, report: &Report) -> String {
+    format!(
+        "Watching {} source(s), {} file(s) sorted, last run at {}: {}",
+        settings.sources.len(),
+        sorted,
+        chrono::Local::now().format("%H:%M:%S"),
+        report.summary()
+    )
+}
+
+/// Shows a desktop notification once a run sorted at least `notify_threshold` files.
+fn notify_sorted(settings: &Settings, report: &Report) {
+    if settings.notify_threshold == 0 || report.sorted < settings.notify_threshold {
+        return;
+    }
+
+    if let Err(e) = notify_rust::Notification::new()
+        .appname(crate_name!())
+        .summary(crate_name!())
+        .body(&report.summary())
+        .show()
+    {
+        warn!("Failed to show notification: {}", e);
+    }
+}
@@ -0,0 +1,75 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/. */
+
+use filesorter_core::executor::Report;
+use filesorter_core::settings::Settings;
+use filesorter_core::template;
+
+use serde_json::{json, Value};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Posts the report to `webhook_url`, if set. A webhook that can't be reached is only logged.
+pub fn send(settings: &Settings, report: &Report) {
+    let url = match &settings.webhook_url {
+        Some(url) => url,
+        None => return,
+    };
+
+    let body = body(settings.webhook_body.as_deref(), report);
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(Some(TIMEOUT))
+        .build()
+        .into();
+
+    match agent
+        .post(url)
+        .header("Content-Type", "application/json")
+        .send(&body)
+    {
+        Ok(_) => debug!("Sent report to {}", url),
+        Err(e) => warn!("Failed to send report to {}: {}", url, e),
+    }
+}
+
+/// The `template` with its placeholders filled in, or a JSON object with everything without one.
+fn body(template: Option<&str>, report: &Report) -> String {
+    let failures: Vec<Value> = report
+        .failures
+        .iter()
+        .map(|failure| {
+            json!({
+                "path": failure.path().map(|path| path.display().to_string()),
+                "reason": failure.reason(),
+            })
+        })
+        .collect();
+
+    let template = match template {
+        Some(template) => template,
+        None => {
+            return json!({
+                "sorted": report.sorted,
+                "duplicates": report.duplicates,
+                "failed": report.failures.len(),
+                "failures": failures,
+            })
+            .to_string()
+        }
+    };
+
+    template::expand(template, |name| match name {
+        "sorted" => Some(report.sorted.to_string()),
+        "duplicates" => Some(report.duplicates.to_string()),
+        "failed" => Some(report.failures.len().to_string()),
+        "failures" => Some(Value::from(failures.clone()).to_string()),
+        // Escaped to go inside a JSON string
+        "summary" => {
+            let quoted = Value::from(report.summary()).to_string();
+            Some(quoted[1..quoted.len() - 1].to_string())
+        }
+        _ => None,
+    })
+}